@@ -1,14 +1,31 @@
 use base64::{engine::general_purpose, Engine as _};
+use notify_debouncer_mini::notify::{self, RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
 use screenshots::Screen;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use tauri::menu::{Menu, MenuBuilder, MenuItem, PredefinedMenuItem, SubmenuBuilder};
-use tauri::tray::TrayIconBuilder;
+use tauri::menu::{
+    CheckMenuItem, Menu, MenuBuilder, MenuItem, PredefinedMenuItem, Submenu, SubmenuBuilder,
+};
+use tauri::tray::{MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent};
 use tauri::{
-    AppHandle, Emitter, Manager, State, WebviewUrl, WebviewWindowBuilder, WindowEvent,
+    AppHandle, DragDropEvent, Emitter, Manager, State, WebviewUrl, WebviewWindowBuilder,
+    WindowEvent,
 };
+use tauri_plugin_autostart::{MacosLauncher, ManagerExt as AutostartExt};
+use tauri_plugin_clipboard_manager::ClipboardExt;
+use tauri_plugin_deep_link::DeepLinkExt;
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, Shortcut, ShortcutState};
+use tauri_plugin_notification::NotificationExt;
+use tauri_plugin_opener::OpenerExt;
 use tauri_plugin_store::StoreBuilder;
+use tauri_plugin_updater::UpdaterExt;
+use tracing::{debug, error, info, warn};
 
 #[cfg(target_os = "macos")]
 use core_foundation::data::CFData;
@@ -22,34 +39,333 @@ use tauri::ActivationPolicy;
 
 // Constants
 const UNLIMITED_THINKING_BUDGET: i32 = -1;
-const GEMINI_API_ENDPOINT: &str =
-    "https://generativelanguage.googleapis.com/v1beta/models/gemini-flash-latest:generateContent";
+/// Slightly above the default of 1.0 so a regenerated answer reads differently.
+const REGENERATE_TEMPERATURE: f32 = 1.3;
+const SUMMARIZE_SYSTEM_PROMPT: &str = "You compress chat transcripts. Summarize the conversation \
+you are given into a concise briefing that preserves the user's goals, key facts, decisions, \
+open questions and any code or names that later turns may refer to. Write it in the third \
+person, use short bullet points, and do not add anything that is not in the transcript.";
+const DEFAULT_IMAGE_ONLY_PROMPT: &str = "Describe this image.";
+const MAX_CONTEXT_URLS: usize = 20;
+/// Most questions `batch_ask` takes at once.
+const MAX_BATCH_PROMPTS: usize = 10;
+/// Batch questions in flight at the same time.
+const BATCH_CONCURRENCY: usize = 3;
+/// Gap between batch request starts so a full batch doesn't trip rate limits.
+const BATCH_REQUEST_INTERVAL: Duration = Duration::from_millis(250);
+/// Gemini rejects requests with more stop sequences than this.
+const MAX_STOP_SEQUENCES: usize = 5;
+/// Most alternatives Gemini will generate for one request.
+const MAX_CANDIDATE_COUNT: i32 = 8;
+/// Presence and frequency penalties must lie in `[-2.0, 2.0)`.
+const PENALTY_RANGE: std::ops::Range<f32> = -2.0..2.0;
+/// Confidence threshold range for dynamic grounding.
+const DYNAMIC_THRESHOLD_RANGE: std::ops::RangeInclusive<f32> = 0.0..=1.0;
+/// Below this the overlay is too faint to read or find again.
+const WINDOW_OPACITY_RANGE: std::ops::RangeInclusive<f64> = 0.2..=1.0;
+const DEFAULT_HISTORY_TOKEN_BUDGET: usize = 200_000;
+const DEFAULT_MAX_REQUEST_BYTES: usize = 20_000_000;
+/// Request bodies are uploaded in chunks of this size so progress can be reported.
+const UPLOAD_CHUNK_BYTES: usize = 256 * 1024;
+/// Input token limit of `GEMINI_MODEL`.
+const GEMINI_CONTEXT_WINDOW_TOKENS: usize = 1_048_576;
+const DEFAULT_CONTEXT_WARNING_THRESHOLD: f64 = 0.75;
+const MIN_RETAINED_HISTORY_MESSAGES: usize = 2;
+const ESTIMATED_CHARS_PER_TOKEN: usize = 4;
+const ESTIMATED_TOKENS_PER_IMAGE: usize = 258;
+const JSON_MIME_TYPE: &str = "application/json";
+/// Elements removed together with their contents when sanitizing output.
+const DANGEROUS_HTML_ELEMENTS: &[&str] = &[
+    "script", "style", "iframe", "object", "embed", "noscript", "template", "svg", "math",
+];
+const DANGEROUS_URL_SCHEMES: &[&str] = &["javascript:", "vbscript:", "data:"];
+const SUPPORTED_AUDIO_MIME_TYPES: &[&str] = &[
+    "audio/wav",
+    "audio/mp3",
+    "audio/aiff",
+    "audio/aac",
+    "audio/ogg",
+    "audio/flac",
+];
+/// Documents Gemini reads directly as inline data.
+const SUPPORTED_DOCUMENT_MIME_TYPES: &[&str] = &[
+    "application/pdf",
+    "text/plain",
+    "text/markdown",
+    "text/csv",
+    "text/html",
+    "application/json",
+    "application/xml",
+];
+const BLOCKED_FINISH_REASONS: &[&str] = &[
+    "SAFETY",
+    "RECITATION",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+];
+const GEMINI_API_KEY_HEADER: &str = "x-goog-api-key";
+/// Headers users may not set: hop-by-hop headers belong to a single connection
+/// and the rest are managed by the client itself.
+const RESTRICTED_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    "content-length",
+    GEMINI_API_KEY_HEADER,
+];
+const GEMINI_API_BASE_URL: &str = "https://generativelanguage.googleapis.com";
+const DEFAULT_GEMINI_API_VERSION: &str = "v1beta";
+const GEMINI_MODELS_PAGE_SIZE: u32 = 1000;
+/// `health_check` gives up after this long and reports the network as down.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Favicons are decoration, so a slow site just goes without one.
+const FAVICON_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_FAVICON_BYTES: usize = 256 * 1024;
+/// Icon links live in `<head>`, so only the start of a page is read.
+const MAX_FAVICON_PAGE_BYTES: usize = 256 * 1024;
+const MAX_FAVICON_REDIRECTS: usize = 10;
+const GENERATE_CONTENT_METHOD: &str = "generateContent";
+/// The Gemini model used when the active profile does not name one.
+const GEMINI_MODEL: &str = "gemini-flash-latest";
+const DEFAULT_OPENAI_MODEL: &str = "gpt-4o-mini";
+const DEFAULT_OLLAMA_BASE_URL: &str = "http://localhost:11434";
+const DEFAULT_OLLAMA_MODEL: &str = "llama3.2";
+const OPENAI_BLOCKED_FINISH_REASON: &str = "content_filter";
 const MAIN_WINDOW_LABEL: &str = "main";
 const TRAY_ICON_ID: &str = "spotlight-tray";
 const MENU_ITEM_SHOW: &str = "tray-show";
 const MENU_ITEM_HIDE: &str = "tray-hide";
 const MENU_ITEM_QUIT: &str = "tray-quit";
+const MENU_ITEM_ALWAYS_ON_TOP: &str = "tray-always-on-top";
+const MENU_ITEM_AUTOSTART: &str = "tray-autostart";
+const MENU_ITEM_RECENT_PREFIX: &str = "tray-recent-";
+const MENU_ITEM_RECENT_EMPTY: &str = "tray-recent-empty";
 const MENU_ITEM_API_SETTINGS: &str = "menu-api-settings";
+const MENU_ITEM_CHECK_UPDATE: &str = "menu-check-update";
 const TRAY_TOOLTIP: &str = "Spotlight";
 const SETTINGS_WINDOW_LABEL: &str = "settings";
+const DEFAULT_SETTINGS_WINDOW_SIZE: WindowSize = WindowSize {
+    width: 520.0,
+    height: 700.0,
+};
+fn init_logging(app: &AppHandle) -> Result<(), SpotlightError> {
+    use tracing_subscriber::fmt::writer::MakeWriterExt;
+
+    let log_dir = app.path().app_log_dir()?;
+    std::fs::create_dir_all(&log_dir).map_err(|e| {
+        SpotlightError::Platform(format!("Failed to create log directory: {}", e))
+    })?;
+
+    let level = settings_store(app)
+        .ok()
+        .and_then(|store| store.get(LOG_LEVEL_KEY))
+        .and_then(|json| json.as_str().and_then(|level| level.parse().ok()))
+        .unwrap_or(DEFAULT_LOG_LEVEL);
+
+    let file_appender = tracing_appender::rolling::daily(&log_dir, LOG_FILE_PREFIX);
+    let (file_writer, guard) = tracing_appender::non_blocking(file_appender);
+    tracing_subscriber::fmt()
+        .with_writer(file_writer.and(std::io::stderr))
+        .with_ansi(false)
+        .with_max_level(level)
+        .try_init()
+        .map_err(|e| SpotlightError::Platform(format!("Failed to initialize logging: {}", e)))?;
+    app.manage(LoggingState { _guard: guard });
+
+    info!("Logging to {} at level {}", log_dir.display(), level);
+    Ok(())
+}
+
 fn get_settings_store_path(app: &AppHandle) -> String {
+    app_data_file_path(app, SETTINGS_FILE_NAME)
+}
+
+fn app_data_file_path(app: &AppHandle, file_name: &str) -> String {
     let app_data_dir = app.path().app_data_dir().unwrap_or_else(|_| {
-        eprintln!("Failed to get app data directory, using fallback");
+        error!("Failed to get app data directory, using fallback");
         std::env::current_dir().unwrap().join("data")
     });
 
     // Ensure the directory exists
     if let Err(err) = std::fs::create_dir_all(&app_data_dir) {
-        eprintln!("Failed to create app data directory: {}", err);
+        error!("Failed to create app data directory: {}", err);
     }
 
-    app_data_dir.join("settings.json").to_string_lossy().to_string()
+    app_data_dir.join(file_name).to_string_lossy().to_string()
 }
+const SETTINGS_FILE_NAME: &str = "settings.json";
+/// Kept apart from settings.json so answers never show up in settings
+/// backups or `settings-changed` events.
+const RESPONSE_CACHE_FILE_NAME: &str = "last-response.json";
+const LAST_RESPONSE_KEY: &str = "LAST_RESPONSE";
+/// Local-only usage counters; nothing in it is ever sent anywhere.
+const USAGE_STATS_FILE_NAME: &str = "stats.json";
+const USAGE_DAYS_KEY: &str = "DAYS";
+/// Chat history lives apart from settings.json so saving a message doesn't
+/// rewrite settings or trip the settings watcher.
+const CONVERSATIONS_FILE_NAME: &str = "conversations.json";
+/// Days of usage kept on disk, and the longest series `get_usage_stats` returns.
+const MAX_USAGE_STATS_DAYS: u32 = 365;
+/// Must match `identifier` in tauri.conf.json; the app data directory is named after it.
+const APP_IDENTIFIER: &str = "com.saif.spotlight";
 const SETTINGS_STORE_KEY: &str = "GEMINI_API_KEY";
+/// Environment variable consulted when no key is stored, e.g. in CI.
+const API_KEY_ENV_VAR: &str = "GEMINI_API_KEY";
+/// When truthy, `API_KEY_ENV_VAR` wins over a stored key instead of deferring to it.
+const API_KEY_OVERRIDE_ENV_VAR: &str = "GEMINI_API_KEY_OVERRIDE";
+const PROFILES_KEY: &str = "PROFILES";
+const SCHEMA_VERSION_KEY: &str = "SCHEMA_VERSION";
+/// Bump alongside a new step in [`migrate_settings`].
+const CURRENT_SCHEMA_VERSION: u64 = 1;
+const ACTIVE_PROFILE_KEY: &str = "ACTIVE_PROFILE";
+const DEFAULT_PROFILE_NAME: &str = "default";
+const LEGACY_PROFILE_NAME: &str = "legacy";
+const KEYCHAIN_SERVICE: &str = "com.saif.spotlight";
+/// Single rect saved before geometry was kept per monitor layout; read once
+/// as a fallback and dropped on the next save.
+const MAIN_WINDOW_GEOMETRY_KEY: &str = "MAIN_WINDOW_GEOMETRY";
+/// Main window rects keyed by [`monitor_signature`].
+const MAIN_WINDOW_GEOMETRIES_KEY: &str = "MAIN_WINDOW_GEOMETRIES";
+const SETTINGS_WINDOW_SIZE_KEY: &str = "SETTINGS_WINDOW_SIZE";
+const SETTINGS_WINDOW_RESIZABLE_KEY: &str = "SETTINGS_WINDOW_RESIZABLE";
+const WINDOW_TINT_KEY: &str = "WINDOW_TINT";
+const CAPTURE_MAX_DIMENSION_KEY: &str = "CAPTURE_MAX_DIMENSION";
+const CAPTURE_DEBOUNCE_MS_KEY: &str = "CAPTURE_DEBOUNCE_MS";
+const EXCLUDED_WINDOWS_KEY: &str = "EXCLUDED_WINDOWS";
+const RATE_LIMIT_KEY: &str = "RATE_LIMIT";
+const MAX_IMAGE_FILE_BYTES_KEY: &str = "MAX_IMAGE_FILE_BYTES";
+#[cfg(target_os = "macos")]
+const VIBRANCY_MATERIAL_KEY: &str = "VIBRANCY_MATERIAL";
+const GLOBAL_SHORTCUT_KEY: &str = "GLOBAL_SHORTCUT";
+const DEFAULT_GLOBAL_SHORTCUT: &str = "CommandOrControl+K";
+const CAPTURE_SHORTCUT_KEY: &str = "CAPTURE_SHORTCUT";
+const DEFAULT_CAPTURE_SHORTCUT: &str = "CommandOrControl+Shift+4";
+const ALWAYS_ON_TOP_KEY: &str = "ALWAYS_ON_TOP";
+const WINDOW_OPACITY_KEY: &str = "WINDOW_OPACITY";
+const AUTOSTART_KEY: &str = "AUTOSTART";
+/// Passed by the login item; the main window starts hidden either way.
+const AUTOSTART_ARG: &str = "--minimized";
+const RECENT_QUERIES_KEY: &str = "RECENT_QUERIES";
+const TRAY_LEFT_CLICK_KEY: &str = "TRAY_LEFT_CLICK";
+const UPDATE_CHANNEL_KEY: &str = "UPDATE_CHANNEL";
+const CACHE_LAST_RESPONSE_KEY: &str = "CACHE_LAST_RESPONSE";
+const HISTORY_TOKEN_BUDGET_KEY: &str = "HISTORY_TOKEN_BUDGET";
+const MAX_REQUEST_BYTES_KEY: &str = "MAX_REQUEST_BYTES";
+const CONTEXT_WARNING_THRESHOLD_KEY: &str = "CONTEXT_WARNING_THRESHOLD";
+const IMAGE_ONLY_PROMPT_KEY: &str = "IMAGE_ONLY_PROMPT";
+const SANITIZE_OUTPUT_KEY: &str = "SANITIZE_OUTPUT";
+const DEFAULT_GROUNDING_KEY: &str = "DEFAULT_GROUNDING";
+const DEFAULT_THINKING_KEY: &str = "DEFAULT_THINKING";
+const CUSTOM_HEADERS_KEY: &str = "CUSTOM_HEADERS";
+const API_VERSION_KEY: &str = "API_VERSION";
+const TTS_ENABLED_KEY: &str = "TTS_ENABLED";
+const TTS_VOICE_KEY: &str = "TTS_VOICE";
+const TTS_RATE_KEY: &str = "TTS_RATE";
+/// Speech rates accepted by `set_tts_rate`, in words per minute.
+const TTS_RATE_RANGE: std::ops::RangeInclusive<u32> = 80..=400;
+const MICROPHONE_ENABLED_KEY: &str = "MICROPHONE_ENABLED";
+/// Recordings stop growing after this long so the clip stays under the request limit.
+#[cfg(feature = "microphone")]
+const MAX_RECORDING_SECS: u32 = 60;
+/// Minimum gap between `recording-level` events.
+#[cfg(feature = "microphone")]
+const RECORDING_LEVEL_INTERVAL: Duration = Duration::from_millis(100);
+const TRANSCRIBE_PROMPT: &str = "Transcribe this audio verbatim. Reply with only the transcript.";
+const BACKEND_KEY: &str = "BACKEND";
+const MAX_RECENT_QUERIES: usize = 5;
+const RECENT_QUERY_LABEL_MAX_CHARS: usize = 40;
+const NOTIFICATION_PREVIEW_MAX_CHARS: usize = 120;
+const NOTIFY_ON_COMPLETE_KEY: &str = "NOTIFY_ON_COMPLETE";
+const LOG_LEVEL_KEY: &str = "LOG_LEVEL";
+const DEFAULT_LOG_LEVEL: tracing_subscriber::filter::LevelFilter =
+    tracing_subscriber::filter::LevelFilter::INFO;
+const LOG_FILE_PREFIX: &str = "spotlight.log";
 const SYSTEM_INSTRUCTIONS_KEY: &str = "SYSTEM_INSTRUCTIONS";
 const SYSTEM_INSTRUCTIONS_PRESETS_KEY: &str = "SYSTEM_INSTRUCTIONS_PRESETS";
+const CONVERSATIONS_KEY: &str = "CONVERSATIONS";
+const ACTIVE_CONVERSATION_KEY: &str = "ACTIVE_CONVERSATION";
+const DEFAULT_CONVERSATION_ID: &str = "default";
+const DEFAULT_CONVERSATION_TITLE: &str = "New chat";
+const MAX_SEARCH_HITS: usize = 50;
+/// Characters of context kept on each side of the first match in a snippet.
+const SEARCH_SNIPPET_CONTEXT_CHARS: usize = 60;
 const API_KEY_UPDATED_EVENT: &str = "api-key-updated";
 const SYSTEM_INSTRUCTIONS_UPDATED_EVENT: &str = "system-instructions-updated";
+const DATA_RESET_EVENT: &str = "data-reset";
+#[cfg(feature = "microphone")]
+const RECORDING_LEVEL_EVENT: &str = "recording-level";
+const CAPTURE_READY_EVENT: &str = "capture-ready";
+const CAPTURE_ATTACHED_EVENT: &str = "capture-attached";
+const SECOND_INSTANCE_EVENT: &str = "second-instance";
+const RERUN_EVENT: &str = "spotlight-rerun";
+const UPDATE_AVAILABLE_EVENT: &str = "update-available";
+const HISTORY_TRIMMED_EVENT: &str = "history-trimmed";
+const CONTEXT_WARNING_EVENT: &str = "context-warning";
+const REQUEST_PREPARING_EVENT: &str = "request-preparing";
+const REQUEST_PROGRESS_EVENT: &str = "request-progress";
+const REQUEST_SENT_EVENT: &str = "request-sent";
+const UPDATE_NOT_AVAILABLE_EVENT: &str = "update-not-available";
+const UPDATE_CHECK_FAILED_EVENT: &str = "update-check-failed";
+const GEMINI_CHUNK_EVENT: &str = "gemini-chunk";
+const THEME_CHANGED_EVENT: &str = "theme-changed";
+const IMAGES_DROPPED_EVENT: &str = "images-dropped";
+const DROP_REJECTED_EVENT: &str = "drop-rejected";
+const QUERY_EVENT: &str = "spotlight-query";
+const DEFAULT_TOGGLES_EVENT: &str = "default-toggles";
+const SETTINGS_CHANGED_EVENT: &str = "settings-changed";
+const SOURCES_READY_EVENT: &str = "sources-ready";
+/// Outside edits to the settings file are picked up once it has stopped
+/// changing for this long.
+const SETTINGS_WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+/// Window moves and resizes arrive as a stream while dragging; geometry is
+/// written once they have stopped for this long.
+const WINDOW_SAVE_DEBOUNCE: Duration = Duration::from_millis(500);
+const MAX_CAPTURE_DELAY_MS: u64 = 10_000;
+/// Strong enough that text under a blurred redaction is unreadable.
+const REDACTION_BLUR_SIGMA: f32 = 12.0;
+/// Time for the compositor to drop the hidden window before the capture shortcut shoots.
+const CAPTURE_HIDE_SETTLE_MS: u64 = 150;
+/// Arrow pointer drawn onto captures that ask for the cursor when the system
+/// cursor can't be read: `B` is the outline, `W` the fill and spaces are left
+/// transparent.
+const CURSOR_SPRITE: [&str; 20] = [
+    "B",
+    "BB",
+    "BWB",
+    "BWWB",
+    "BWWWB",
+    "BWWWWB",
+    "BWWWWWB",
+    "BWWWWWWB",
+    "BWWWWWWWB",
+    "BWWWWWWWWB",
+    "BWWWWWWWWWB",
+    "BWWWWWWBBBBB",
+    "BWWWBWWB",
+    "BWWB BWWB",
+    "BWB  BWWB",
+    "BB    BWWB",
+    "B     BWWB",
+    "       BWWB",
+    "       BWWB",
+    "        BB",
+];
+const DEFAULT_CAPTURE_DEBOUNCE_MS: u64 = 200;
+/// Gemini's free-tier requests-per-minute allowance for `GEMINI_MODEL`.
+const DEFAULT_REQUESTS_PER_MINUTE: u32 = 10;
+const DEFAULT_MAX_IMAGE_FILE_BYTES: u64 = 20 * 1024 * 1024;
+/// Longest side of the preview returned by `capture_with_thumbnail`.
+const THUMBNAIL_MAX_DIMENSION: u32 = 320;
+const DEEP_LINK_SCHEME: &str = "spotlight";
+const DEEP_LINK_ASK_HOST: &str = "ask";
+const MAX_DEEP_LINK_QUERY_CHARS: usize = 2_000;
 
 #[derive(Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -63,726 +379,8481 @@ struct SystemInstructionsPayload {
     system_instructions: Option<String>,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-struct InstructionPreset {
-    id: String,
-    name: String,
-    instructions: String,
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HistoryTrimmedPayload {
+    dropped_messages: usize,
+    estimated_tokens: usize,
+    token_budget: usize,
 }
 
-#[derive(Clone)]
-struct TrayMenuState {
-    show_item: MenuItem<tauri::Wry>,
-    hide_item: MenuItem<tauri::Wry>,
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ContextWarningPayload {
+    used_tokens: usize,
+    limit_tokens: usize,
 }
 
-impl TrayMenuState {
-    fn set_visibility(&self, is_visible: bool) {
-        if let Err(err) = self.show_item.set_enabled(!is_visible) {
-            eprintln!("Failed to update Show menu item: {err}");
-        }
-        if let Err(err) = self.hide_item.set_enabled(is_visible) {
-            eprintln!("Failed to update Hide menu item: {err}");
-        }
-    }
+#[cfg(feature = "microphone")]
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RecordingLevelPayload {
+    /// RMS level of the latest audio, from 0.0 (silence) to 1.0 (full scale).
+    level: f32,
 }
 
-#[tauri::command]
-async fn capture_screen(window: tauri::Window) -> Result<String, String> {
-    capture_screen_inner(&window)
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SettingsChangedPayload {
+    keys: Vec<String>,
 }
 
-#[tauri::command]
-fn sync_tray_visibility(state: State<'_, TrayMenuState>, visible: bool) {
-    state.set_visibility(visible);
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RequestProgressPayload {
+    sent_bytes: usize,
+    total_bytes: usize,
 }
 
-#[tauri::command]
-fn open_api_settings_window(app: AppHandle) -> Result<(), String> {
-    open_settings_window(&app).map_err(|e| e.to_string())
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateAvailablePayload {
+    version: String,
+    body: String,
+    date: String,
+    channel: UpdateChannel,
 }
 
-#[tauri::command]
-fn close_api_settings_window(app: AppHandle) -> Result<(), String> {
-    if let Some(window) = app.get_webview_window(SETTINGS_WINDOW_LABEL) {
-        window.close().map_err(|e| e.to_string())
-    } else {
-        // Window is already closed or doesn't exist
-        Ok(())
-    }
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateCheckResult {
+    available: bool,
+    version: Option<String>,
+    current_version: String,
+    channel: UpdateChannel,
 }
 
-fn capture_screen_inner(_window: &tauri::Window) -> Result<String, String> {
-    #[cfg(target_os = "macos")]
-    {
-        match capture_screen_without_overlay_mac(_window) {
-            Ok(png_bytes) => return Ok(general_purpose::STANDARD.encode(png_bytes)),
-            Err(err) => {
-                eprintln!("Falling back to regular capture: {}", err);
-            }
-        }
-    }
-
-    #[cfg(target_os = "windows")]
-    {
-        match capture_screen_without_overlay_windows(_window) {
-            Ok(png_bytes) => return Ok(general_purpose::STANDARD.encode(png_bytes)),
-            Err(err) => {
-                eprintln!("Falling back to regular capture: {}", err);
-            }
-        }
-    }
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiChunkPayload {
+    text: String,
+    /// `text` is the whole answer so far rather than the next piece, sent when
+    /// sanitizing may rewrite what was already streamed.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    replace: bool,
+}
 
-    capture_full_display_base64()
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QueryPayload {
+    query: String,
 }
 
-fn capture_full_display_png() -> Result<Vec<u8>, String> {
-    let screens = Screen::all().map_err(|e| e.to_string())?;
-    let screen = screens.first().ok_or("No screens found")?;
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RerunPayload {
+    prompt: String,
+}
 
-    let image = screen.capture().map_err(|e| e.to_string())?;
-    image.to_png().map_err(|e| e.to_string())
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SecondInstancePayload {
+    args: Vec<String>,
+    cwd: String,
 }
 
-fn capture_full_display_base64() -> Result<String, String> {
-    capture_full_display_png().map(|png_bytes| general_purpose::STANDARD.encode(png_bytes))
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CaptureReadyPayload {
+    image_data: Option<String>,
+    error: Option<String>,
 }
 
-#[cfg(target_os = "macos")]
-fn capture_screen_without_overlay_mac(window: &tauri::Window) -> Result<Vec<u8>, String> {
-    use core_graphics::window::{
-        create_image, kCGWindowImageDefault, kCGWindowListOptionOnScreenBelowWindow,
-    };
-    use objc::runtime::Object;
-    use png::{BitDepth, ColorType, Encoder};
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CaptureResult {
+    image_data: String,
+    width: u32,
+    height: u32,
+    mime_type: String,
+}
 
-    let ns_window_ptr = window
-        .ns_window()
-        .map_err(|e| format!("Failed to access native window: {}", e))?;
-    let ns_window = ns_window_ptr as *mut Object;
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ImagesDroppedPayload {
+    images: Vec<CaptureResult>,
+}
 
-    #[allow(unexpected_cfgs)]
-    let window_number: u32 = unsafe { msg_send![ns_window, windowNumber] };
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RejectedDrop {
+    path: String,
+    reason: String,
+}
 
-    let bounds = CGDisplay::main().bounds();
-    let cg_image = create_image(
-        bounds,
-        kCGWindowListOptionOnScreenBelowWindow,
-        window_number,
-        kCGWindowImageDefault,
-    )
-    .ok_or_else(|| "CGWindowListCreateImage returned null".to_string())?;
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DropRejectedPayload {
+    rejected: Vec<RejectedDrop>,
+}
 
-    let width = cg_image.width() as usize;
-    let height = cg_image.height() as usize;
-    let bytes_per_row = cg_image.bytes_per_row() as usize;
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiProfile {
+    /// Plaintext key, only populated when the OS keychain is unavailable.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    api_key: String,
+    #[serde(default)]
+    key_in_keychain: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+}
 
-    let cf_data: CFData = cg_image.data();
-    let data: &[u8] = cf_data.as_ref();
+impl ApiProfile {
+    fn new(profile_name: &str, api_key: String, model: Option<String>) -> Self {
+        let mut profile = ApiProfile {
+            api_key: String::new(),
+            key_in_keychain: false,
+            model,
+        };
+        profile.store_api_key(profile_name, api_key);
+        profile
+    }
 
-    if data.len() < bytes_per_row * height {
-        return Err("Unexpected pixel buffer length".to_string());
+    fn store_api_key(&mut self, profile_name: &str, api_key: String) {
+        match keychain_entry(profile_name).and_then(|entry| entry.set_password(&api_key)) {
+            Ok(()) => {
+                self.api_key.clear();
+                self.key_in_keychain = true;
+            }
+            Err(err) => {
+                warn!("Keychain unavailable, storing API key in settings file: {err}");
+                self.api_key = api_key;
+                self.key_in_keychain = false;
+            }
+        }
     }
 
-    let mut rgba = vec![0u8; width * height * 4];
-    for y in 0..height {
-        let src_offset = y * bytes_per_row;
-        let dst_offset = y * width * 4;
-        let src_row = &data[src_offset..src_offset + width * 4];
-        let dst_row = &mut rgba[dst_offset..dst_offset + width * 4];
+    fn resolve_api_key(&self, profile_name: &str) -> Option<String> {
+        if !self.key_in_keychain {
+            return Some(self.api_key.clone()).filter(|key| !key.is_empty());
+        }
+        match keychain_entry(profile_name).and_then(|entry| entry.get_password()) {
+            Ok(api_key) => Some(api_key),
+            Err(err) => {
+                error!("Failed to read API key from keychain: {err}");
+                None
+            }
+        }
+    }
 
-        for (dst_px, src_px) in dst_row.chunks_exact_mut(4).zip(src_row.chunks_exact(4)) {
-            // Convert BGRA -> RGBA
-            dst_px[0] = src_px[2];
-            dst_px[1] = src_px[1];
-            dst_px[2] = src_px[0];
-            dst_px[3] = src_px[3];
+    fn forget_api_key(&self, profile_name: &str) {
+        if !self.key_in_keychain {
+            return;
+        }
+        match keychain_entry(profile_name).and_then(|entry| entry.delete_credential()) {
+            Ok(()) | Err(keyring::Error::NoEntry) => {}
+            Err(err) => error!("Failed to delete API key from keychain: {err}"),
         }
     }
 
-    let mut png_bytes = Vec::new();
-    {
-        let mut encoder = Encoder::new(&mut png_bytes, width as u32, height as u32);
-        encoder.set_color(ColorType::Rgba);
-        encoder.set_depth(BitDepth::Eight);
-        let mut writer = encoder
-            .write_header()
-            .map_err(|e| format!("Failed to write PNG header: {}", e))?;
-        writer
-            .write_image_data(&rgba)
-            .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    /// Drops the key wherever it is kept, leaving the rest of the profile.
+    fn clear_api_key(&mut self, profile_name: &str) {
+        self.forget_api_key(profile_name);
+        self.api_key.clear();
+        self.key_in_keychain = false;
     }
+}
 
-    Ok(png_bytes)
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileSummary {
+    name: String,
+    model: Option<String>,
+    is_active: bool,
 }
 
-#[cfg(target_os = "windows")]
-fn capture_screen_without_overlay_windows(window: &tauri::Window) -> Result<Vec<u8>, String> {
-    use std::{thread, time::Duration};
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct WindowGeometry {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
 
-    let was_visible = window
-        .is_visible()
-        .map_err(|e| format!("Failed to determine window visibility: {}", e))?;
+/// Area of a capture in image pixels, measured from the top-left corner.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
 
-    if was_visible {
-        window
-            .hide()
-            .map_err(|e| format!("Failed to hide window before capture: {}", e))?;
-        // Reduced delay for better UX - modern compositors are fast
-        thread::sleep(Duration::from_millis(80));
-    }
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RedactionStyle {
+    #[default]
+    Fill,
+    Blur,
+}
 
-    let capture_result = capture_full_display_png();
+/// RGBA tint behind the translucent Windows backdrop.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct WindowTint {
+    r: u8,
+    g: u8,
+    b: u8,
+    a: u8,
+}
 
-    if was_visible {
-        if let Err(err) = window.show() {
-            eprintln!("Failed to restore window visibility after capture: {}", err);
-        } else {
-            // Reduced delay - window redraws quickly on modern systems
-            thread::sleep(Duration::from_millis(30));
-        }
+impl WindowTint {
+    const LIGHT: WindowTint = WindowTint {
+        r: 255,
+        g: 255,
+        b: 255,
+        a: 125,
+    };
+    const DARK: WindowTint = WindowTint {
+        r: 24,
+        g: 24,
+        b: 24,
+        a: 125,
+    };
 
-        if let Err(err) = window.set_focus() {
-            eprintln!("Failed to refocus window after capture: {}", err);
+    fn for_theme(theme: tauri::Theme) -> Self {
+        match theme {
+            tauri::Theme::Dark => WindowTint::DARK,
+            _ => WindowTint::LIGHT,
         }
     }
+}
 
-    capture_result
+/// The subset of `NSVisualEffectMaterial` that suits a floating panel.
+#[cfg(target_os = "macos")]
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum VibrancyMaterial {
+    #[default]
+    HudWindow,
+    Sidebar,
+    Popover,
+    Menu,
+    WindowBackground,
+    UnderWindowBackground,
 }
 
-#[derive(Serialize, Deserialize)]
-struct GeminiPart {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    text: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    inline_data: Option<InlineData>,
+#[cfg(target_os = "macos")]
+impl VibrancyMaterial {
+    fn ns_material(self) -> window_vibrancy::NSVisualEffectMaterial {
+        use window_vibrancy::NSVisualEffectMaterial;
+
+        match self {
+            VibrancyMaterial::HudWindow => NSVisualEffectMaterial::HudWindow,
+            VibrancyMaterial::Sidebar => NSVisualEffectMaterial::Sidebar,
+            VibrancyMaterial::Popover => NSVisualEffectMaterial::Popover,
+            VibrancyMaterial::Menu => NSVisualEffectMaterial::Menu,
+            VibrancyMaterial::WindowBackground => NSVisualEffectMaterial::WindowBackground,
+            VibrancyMaterial::UnderWindowBackground => {
+                NSVisualEffectMaterial::UnderWindowBackground
+            }
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-struct InlineData {
-    mime_type: String,
-    data: String,
+/// Logical size, so a saved size looks the same across display scale factors.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct WindowSize {
+    width: f64,
+    height: f64,
 }
 
-#[derive(Serialize, Deserialize)]
-struct GeminiContent {
-    role: String,
-    parts: Vec<GeminiPart>,
+impl WindowGeometry {
+    fn fits_within(&self, monitor: &tauri::Monitor) -> bool {
+        let origin = monitor.position();
+        let size = monitor.size();
+        let right = i64::from(origin.x) + i64::from(size.width);
+        let bottom = i64::from(origin.y) + i64::from(size.height);
+        self.x >= origin.x
+            && self.y >= origin.y
+            && i64::from(self.x) + i64::from(self.width) <= right
+            && i64::from(self.y) + i64::from(self.height) <= bottom
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-struct ChatMessage {
-    role: String,
-    content: String,
+#[derive(Clone, Serialize, Deserialize)]
+struct InstructionPreset {
+    id: String,
+    name: String,
+    instructions: String,
 }
 
-#[derive(Serialize, Deserialize)]
-struct GoogleSearch {}
+/// A saved chat with its own system prompt, overriding the global
+/// instructions while it is active.
+#[derive(Clone, Serialize, Deserialize)]
+struct Conversation {
+    id: String,
+    title: String,
+    #[serde(default)]
+    system_prompt: Option<String>,
+    #[serde(default)]
+    messages: Vec<ChatMessage>,
+    /// Normalized with [`normalize_tags`].
+    #[serde(default)]
+    tags: Vec<String>,
+    /// RFC 3339 time the conversation was created or its messages last changed.
+    #[serde(rename = "updatedAt", default, skip_serializing_if = "Option::is_none")]
+    updated_at: Option<String>,
+}
 
-#[derive(Serialize, Deserialize)]
-struct Tool {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    google_search: Option<GoogleSearch>,
+/// A saved message matching `search_history`.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchHit {
+    conversation_id: String,
+    conversation_title: String,
+    message_index: usize,
+    role: String,
+    /// Text around the first match, with every match wrapped in `**`.
+    snippet: String,
+    /// Total occurrences of all search terms in the message.
+    score: usize,
 }
 
-#[derive(Serialize, Deserialize)]
-struct ThinkingConfig {
-    #[serde(rename = "thinkingBudget")]
-    thinking_budget: i32,
-    #[serde(rename = "includeThoughts")]
-    include_thoughts: bool,
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConversationSummary {
+    id: String,
+    title: String,
+    tags: Vec<String>,
+    updated_at: Option<String>,
+    message_count: usize,
+    is_active: bool,
 }
 
-#[derive(Serialize, Deserialize)]
-struct GenerationConfig {
-    #[serde(rename = "thinkingConfig")]
-    thinking_config: ThinkingConfig,
+/// Error returned by every command. Serialized as `{ kind, message }` so the
+/// frontend can react to the kind of failure rather than parsing strings.
+#[derive(Debug, Clone, thiserror::Error)]
+enum SpotlightError {
+    #[error("No API key is configured")]
+    MissingApiKey,
+    #[error("Authentication failed: {0}")]
+    AuthFailed(String),
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+    #[error("Network error: {0}")]
+    Network(String),
+    #[error("You appear to be offline; check your internet connection and try again")]
+    Offline,
+    #[error("Request timed out: {0}")]
+    Timeout(String),
+    #[error("Failed to parse response: {0}")]
+    Parse(String),
+    #[error("Response blocked: {0}")]
+    Blocked(String),
+    #[error("API error: {0}")]
+    Api(String),
+    #[error("Capture failed: {0}")]
+    Capture(String),
+    #[error("Settings store error: {0}")]
+    Store(String),
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+    #[error("Type a question or attach an image first")]
+    EmptyPrompt,
+    #[error("{0}")]
+    Platform(String),
+    #[error("Update check failed: {0}")]
+    Update(String),
+    #[error("Context cache {0} has expired; create it again")]
+    CacheExpired(String),
+    #[error("Shortcut '{0}' is already in use by another application")]
+    ShortcutTaken(String),
+    #[error("Microphone is unavailable: {0}")]
+    MicrophoneUnavailable(String),
+    #[error("Text-to-speech is unavailable: {0}")]
+    SpeechUnavailable(String),
+    #[error("{0} is not supported on this platform")]
+    Unsupported(String),
+    #[error("Too many requests; try again in {:.1}s", *retry_after_ms as f64 / 1000.0)]
+    RateLimitedLocally { retry_after_ms: u64 },
+    #[error("Request is {size} bytes, over the {limit} byte limit; try a smaller image or clear the conversation")]
+    PayloadTooLarge { size: usize, limit: usize },
 }
 
-#[derive(Serialize, Deserialize)]
-struct SystemInstruction {
-    parts: Vec<GeminiPart>,
+impl SpotlightError {
+    fn kind(&self) -> &'static str {
+        match self {
+            SpotlightError::MissingApiKey => "missingApiKey",
+            SpotlightError::AuthFailed(_) => "authFailed",
+            SpotlightError::RateLimited(_) => "rateLimited",
+            SpotlightError::Network(_) => "network",
+            SpotlightError::Offline => "offline",
+            SpotlightError::Timeout(_) => "timeout",
+            SpotlightError::Parse(_) => "parse",
+            SpotlightError::Blocked(_) => "blocked",
+            SpotlightError::Api(_) => "api",
+            SpotlightError::Capture(_) => "capture",
+            SpotlightError::Store(_) => "store",
+            SpotlightError::InvalidInput(_) => "invalidInput",
+            SpotlightError::EmptyPrompt => "emptyPrompt",
+            SpotlightError::Platform(_) => "platform",
+            SpotlightError::Update(_) => "update",
+            SpotlightError::CacheExpired(_) => "cacheExpired",
+            SpotlightError::ShortcutTaken(_) => "shortcutTaken",
+            SpotlightError::MicrophoneUnavailable(_) => "microphoneUnavailable",
+            SpotlightError::SpeechUnavailable(_) => "speechUnavailable",
+            SpotlightError::Unsupported(_) => "unsupported",
+            SpotlightError::RateLimitedLocally { .. } => "rateLimitedLocally",
+            SpotlightError::PayloadTooLarge { .. } => "payloadTooLarge",
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-struct GeminiRequest {
+impl SpotlightError {
+    /// Replaces every occurrence of `secret` in the message so API keys can
+    /// never leak through an error returned to the frontend or printed.
+    fn redacted(self, secret: &str) -> Self {
+        let secret = secret.trim();
+        if secret.is_empty() {
+            return self;
+        }
+        let redact = |message: String| message.replace(secret, "[REDACTED]");
+        match self {
+            SpotlightError::MissingApiKey => SpotlightError::MissingApiKey,
+            SpotlightError::AuthFailed(m) => SpotlightError::AuthFailed(redact(m)),
+            SpotlightError::RateLimited(m) => SpotlightError::RateLimited(redact(m)),
+            SpotlightError::Network(m) => SpotlightError::Network(redact(m)),
+            SpotlightError::Offline => SpotlightError::Offline,
+            SpotlightError::Timeout(m) => SpotlightError::Timeout(redact(m)),
+            SpotlightError::Parse(m) => SpotlightError::Parse(redact(m)),
+            SpotlightError::Blocked(m) => SpotlightError::Blocked(redact(m)),
+            SpotlightError::Api(m) => SpotlightError::Api(redact(m)),
+            SpotlightError::Capture(m) => SpotlightError::Capture(redact(m)),
+            SpotlightError::Store(m) => SpotlightError::Store(redact(m)),
+            SpotlightError::InvalidInput(m) => SpotlightError::InvalidInput(redact(m)),
+            SpotlightError::EmptyPrompt => SpotlightError::EmptyPrompt,
+            SpotlightError::Platform(m) => SpotlightError::Platform(redact(m)),
+            SpotlightError::Update(m) => SpotlightError::Update(redact(m)),
+            SpotlightError::CacheExpired(m) => SpotlightError::CacheExpired(redact(m)),
+            SpotlightError::ShortcutTaken(m) => SpotlightError::ShortcutTaken(redact(m)),
+            SpotlightError::MicrophoneUnavailable(m) => {
+                SpotlightError::MicrophoneUnavailable(redact(m))
+            }
+            SpotlightError::SpeechUnavailable(m) => SpotlightError::SpeechUnavailable(redact(m)),
+            SpotlightError::Unsupported(m) => SpotlightError::Unsupported(redact(m)),
+            err @ (SpotlightError::RateLimitedLocally { .. }
+            | SpotlightError::PayloadTooLarge { .. }) => err,
+        }
+    }
+}
+
+impl Serialize for SpotlightError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("SpotlightError", 3)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        match self {
+            SpotlightError::RateLimitedLocally { retry_after_ms } => {
+                state.serialize_field("retryAfterMs", retry_after_ms)?
+            }
+            _ => state.skip_field("retryAfterMs")?,
+        }
+        state.end()
+    }
+}
+
+impl From<tauri_plugin_store::Error> for SpotlightError {
+    fn from(err: tauri_plugin_store::Error) -> Self {
+        SpotlightError::Store(err.to_string())
+    }
+}
+
+impl From<tauri::Error> for SpotlightError {
+    fn from(err: tauri::Error) -> Self {
+        SpotlightError::Platform(err.to_string())
+    }
+}
+
+impl From<reqwest::Error> for SpotlightError {
+    fn from(err: reqwest::Error) -> Self {
+        // Request URLs may carry credentials, so never include them
+        let err = err.without_url();
+        // Checked before timeouts so a connect timeout also reads as offline
+        if err.is_connect() {
+            debug!("Connection failed: {}", err);
+            SpotlightError::Offline
+        } else if err.is_timeout() {
+            SpotlightError::Timeout(err.to_string())
+        } else if err.is_decode() {
+            SpotlightError::Parse(err.to_string())
+        } else {
+            SpotlightError::Network(err.to_string())
+        }
+    }
+}
+
+/// Keeps the non-blocking log writer alive; dropping it flushes and stops it.
+struct LoggingState {
+    _guard: tracing_appender::non_blocking::WorkerGuard,
+}
+
+/// Keeps the settings file watcher alive; dropping it stops the watch.
+struct SettingsWatcherState {
+    _debouncer: Debouncer<RecommendedWatcher>,
+}
+
+/// Models listed for the current API key, fetched once per session.
+#[derive(Default)]
+struct ModelCacheState {
+    models: Mutex<Option<Vec<ModelInfo>>>,
+}
+
+/// Favicons as data URLs keyed by host, fetched once per session. `None`
+/// remembers that a host has no usable icon.
+#[derive(Default)]
+struct FaviconCacheState {
+    icons: Mutex<HashMap<String, Option<String>>>,
+}
+
+/// Source URIs keyed by the ids sent in `sources-ready`, so the frontend can
+/// open a source without holding on to its URI or position.
+#[derive(Default)]
+struct SourceRegistryState {
+    uris: Mutex<HashMap<String, String>>,
+}
+
+/// Holds a query from a `spotlight://` link until the frontend takes it, so a
+/// link that cold-starts the app isn't lost before the webview is listening.
+#[derive(Default)]
+struct DeepLinkState {
+    pending_query: Mutex<Option<String>>,
+}
+
+/// Keeps repeated capture requests from fighting each other: the last shot is
+/// reused within the debounce interval and a capture hotkey press is ignored
+/// while one is already running.
+#[derive(Default)]
+struct CaptureDebounceState {
+    in_progress: AtomicBool,
+    /// When the last shot was taken, whether it shows the cursor, and the image.
+    last: Mutex<Option<(Instant, bool, String)>>,
+}
+
+/// The latest pending write per window; see [`debounce_window_save`].
+#[derive(Default)]
+struct WindowSaveDebounceState {
+    generations: Mutex<HashMap<&'static str, u64>>,
+}
+
+/// Token bucket shared by every Gemini request; see [`throttle_requests`].
+#[derive(Default)]
+struct RateLimiterState {
+    bucket: Mutex<Option<TokenBucket>>,
+}
+
+/// Holds up to a minute's worth of requests and refills continuously, so
+/// short bursts go straight through while sustained use is held to the rate.
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(requests_per_minute: u32, now: Instant) -> Self {
+        TokenBucket {
+            tokens: f64::from(requests_per_minute),
+            refilled_at: now,
+        }
+    }
+
+    /// Takes one token, or returns how long until one is available.
+    fn try_take(&mut self, requests_per_minute: u32, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(requests_per_minute);
+        let per_second = capacity / 60.0;
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_second).min(capacity);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / per_second))
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RateLimitMode {
+    /// Hold the request until a token frees up.
+    #[default]
+    Wait,
+    /// Fail straight away with `RateLimitedLocally`.
+    Reject,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RateLimitSettings {
+    /// Zero turns the limiter off.
+    requests_per_minute: u32,
+    #[serde(default)]
+    mode: RateLimitMode,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        RateLimitSettings {
+            requests_per_minute: DEFAULT_REQUESTS_PER_MINUTE,
+            mode: RateLimitMode::default(),
+        }
+    }
+}
+
+/// The microphone recording in progress, if any. The audio stream itself
+/// lives on the recording thread since streams can't move between threads.
+#[derive(Default)]
+struct RecordingState {
+    active: Mutex<Option<ActiveRecording>>,
+}
+
+struct ActiveRecording {
+    stop: std::sync::mpsc::Sender<()>,
+    worker: std::thread::JoinHandle<Result<RecordedAudio, SpotlightError>>,
+}
+
+/// Mono 16-bit PCM captured from the microphone.
+struct RecordedAudio {
+    samples: Vec<i16>,
+    sample_rate: u32,
+}
+
+#[derive(Default)]
+struct SpeechState {
+    /// Synthesizer process reading the current answer aloud, if any.
+    child: Mutex<Option<std::process::Child>>,
+}
+
+#[derive(Default)]
+struct GlobalShortcutState {
+    current: Mutex<Option<Shortcut>>,
+    capture: Mutex<Option<Shortcut>>,
+}
+
+struct RecentQueriesState {
+    queries: Mutex<VecDeque<String>>,
+    submenu: Submenu<tauri::Wry>,
+}
+
+impl RecentQueriesState {
+    fn rebuild_menu(&self, app: &AppHandle, queries: &VecDeque<String>) -> tauri::Result<()> {
+        for item in self.submenu.items()? {
+            self.submenu.remove(&item)?;
+        }
+
+        if queries.is_empty() {
+            let empty_item = MenuItem::with_id(
+                app,
+                MENU_ITEM_RECENT_EMPTY,
+                "No Recent Queries",
+                false,
+                None::<&str>,
+            )?;
+            return self.submenu.append(&empty_item);
+        }
+
+        for (index, query) in queries.iter().enumerate() {
+            let item = MenuItem::with_id(
+                app,
+                format!("{}{}", MENU_ITEM_RECENT_PREFIX, index),
+                recent_query_label(query),
+                true,
+                None::<&str>,
+            )?;
+            self.submenu.append(&item)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TrayLeftClickBehavior {
+    #[default]
+    Menu,
+    Toggle,
+}
+
+/// Release stream the updater follows. Beta manifests sit next to the
+/// configured stable ones (`latest-beta.json`); dynamic update servers get a
+/// `channel=beta` query parameter instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum UpdateChannel {
+    #[default]
+    Stable,
+    Beta,
+}
+
+/// Which chat API `send_to_gemini` talks to. Gemini is the default so stores
+/// written before backends were configurable keep working unchanged.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum Backend {
+    #[default]
+    Gemini,
+    /// Any server speaking the OpenAI chat-completions format (OpenAI, Azure
+    /// OpenAI, llama.cpp, vLLM, LM Studio, ...).
+    #[serde(rename_all = "camelCase")]
+    OpenAiCompatible { base_url: String },
+    /// A local Ollama instance; responses stream as `gemini-chunk` events.
+    #[serde(rename_all = "camelCase")]
+    Ollama {
+        #[serde(default = "default_ollama_base_url")]
+        base_url: String,
+    },
+}
+
+impl Backend {
+    fn requires_api_key(&self) -> bool {
+        // Local OpenAI-compatible servers and Ollama run without authentication
+        matches!(self, Backend::Gemini)
+    }
+}
+
+fn default_ollama_base_url() -> String {
+    DEFAULT_OLLAMA_BASE_URL.to_string()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TrayStatus {
+    NoApiKey,
+    Ready,
+    Offline,
+}
+
+impl TrayStatus {
+    fn tooltip(self) -> String {
+        let label = match self {
+            TrayStatus::NoApiKey => "No API key",
+            TrayStatus::Ready => "Ready",
+            TrayStatus::Offline => "Offline",
+        };
+        format!("{} — {}", TRAY_TOOLTIP, label)
+    }
+}
+
+#[derive(Clone)]
+struct TrayMenuState {
+    show_item: MenuItem<tauri::Wry>,
+    hide_item: MenuItem<tauri::Wry>,
+    always_on_top_item: CheckMenuItem<tauri::Wry>,
+    autostart_item: CheckMenuItem<tauri::Wry>,
+    tray_icon: TrayIcon<tauri::Wry>,
+    status: Arc<Mutex<TrayStatus>>,
+    left_click_behavior: Arc<Mutex<TrayLeftClickBehavior>>,
+}
+
+impl TrayMenuState {
+    fn set_visibility(&self, is_visible: bool) {
+        if let Err(err) = self.show_item.set_enabled(!is_visible) {
+            error!("Failed to update Show menu item: {err}");
+        }
+        if let Err(err) = self.hide_item.set_enabled(is_visible) {
+            error!("Failed to update Hide menu item: {err}");
+        }
+    }
+
+    fn set_tooltip(&self, tooltip: &str) {
+        if let Err(err) = self.tray_icon.set_tooltip(Some(tooltip)) {
+            error!("Failed to update tray tooltip: {err}");
+        }
+    }
+
+    fn set_status(&self, status: TrayStatus) {
+        if let Ok(mut current) = self.status.lock() {
+            *current = status;
+        }
+        self.set_tooltip(&status.tooltip());
+    }
+
+    fn set_left_click_behavior(&self, behavior: TrayLeftClickBehavior) {
+        if let Ok(mut current) = self.left_click_behavior.lock() {
+            *current = behavior;
+        }
+        let show_menu = behavior == TrayLeftClickBehavior::Menu;
+        if let Err(err) = self.tray_icon.set_show_menu_on_left_click(show_menu) {
+            error!("Failed to update tray left-click behavior: {err}");
+        }
+    }
+
+    fn left_click_toggles_window(&self) -> bool {
+        self.left_click_behavior
+            .lock()
+            .map(|behavior| *behavior == TrayLeftClickBehavior::Toggle)
+            .unwrap_or(false)
+    }
+
+    /// Restores the status tooltip after a temporary message.
+    fn refresh_tooltip(&self) {
+        let status = self
+            .status
+            .lock()
+            .map(|status| *status)
+            .unwrap_or(TrayStatus::Ready);
+        self.set_tooltip(&status.tooltip());
+    }
+}
+
+#[tauri::command]
+async fn capture_screen(
+    app: AppHandle,
+    window: tauri::Window,
+    include_cursor: Option<bool>,
+) -> Result<String, SpotlightError> {
+    debounced_capture(&app, &window, include_cursor.unwrap_or(false))
+}
+
+/// A capture alongside a small preview; see [`capture_with_thumbnail`].
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ThumbnailCapture {
+    full: String,
+    thumbnail: String,
+    width: u32,
+    height: u32,
+}
+
+/// Captures like [`capture_screen`] and also returns a PNG no larger than
+/// [`THUMBNAIL_MAX_DIMENSION`] so the UI can preview the shot immediately.
+/// `width` and `height` describe the full image.
+#[tauri::command]
+async fn capture_with_thumbnail(
+    app: AppHandle,
+    window: tauri::Window,
+    include_cursor: Option<bool>,
+) -> Result<ThumbnailCapture, SpotlightError> {
+    let full = debounced_capture(&app, &window, include_cursor.unwrap_or(false))?;
+    let png_bytes = general_purpose::STANDARD
+        .decode(&full)
+        .map_err(|e| SpotlightError::Capture(format!("Invalid capture data: {}", e)))?;
+    let image = image::load_from_memory_with_format(&png_bytes, image::ImageFormat::Png)
+        .map_err(|e| SpotlightError::Capture(format!("Failed to decode capture: {}", e)))?;
+    let thumbnail = downscale_to_fit(&image, THUMBNAIL_MAX_DIMENSION);
+    let thumbnail_png = encode_rgba_png(thumbnail.as_raw(), thumbnail.width(), thumbnail.height())
+        .map_err(SpotlightError::Capture)?;
+    Ok(ThumbnailCapture {
+        full,
+        thumbnail: general_purpose::STANDARD.encode(thumbnail_png),
+        width: image.width(),
+        height: image.height(),
+    })
+}
+
+/// A capture of the whole virtual desktop; see [`capture_all_displays`].
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PanoramaCapture {
+    image_data: String,
+    width: u32,
+    height: u32,
+}
+
+/// Captures every display into one PNG laid out like the virtual desktop.
+/// Gaps between displays are left transparent.
+#[tauri::command]
+async fn capture_all_displays(window: tauri::Window) -> Result<PanoramaCapture, SpotlightError> {
+    let was_visible = window.is_visible().unwrap_or(false);
+    if was_visible {
+        window.hide()?;
+        // Give the compositor a moment to drop the overlay from the next frame
+        tokio::time::sleep(Duration::from_millis(80)).await;
+    }
+    let excluded = excluded_window_rects(&stored_excluded_windows(window.app_handle()));
+    let stitched = stitch_displays(&excluded).await;
+    if was_visible {
+        if let Err(err) = window.show() {
+            error!("Failed to restore window visibility after capture: {}", err);
+        }
+        if let Err(err) = window.set_focus() {
+            error!("Failed to refocus window after capture: {}", err);
+        }
+    }
+
+    let (png_bytes, width, height) = stitched.map_err(SpotlightError::Capture)?;
+    Ok(PanoramaCapture {
+        image_data: general_purpose::STANDARD.encode(png_bytes),
+        width,
+        height,
+    })
+}
+
+/// Captures every display and lays them out as one PNG, masking the desktop
+/// rects in `excluded` on each display they overlap.
+async fn stitch_displays(excluded: &[(i32, i32, i32, i32)]) -> Result<(Vec<u8>, u32, u32), String> {
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    if screens.is_empty() {
+        return Err("No screens found".to_string());
+    }
+    let bounds: Vec<_> = screens
+        .iter()
+        .map(|screen| {
+            let info = &screen.display_info;
+            (info.x, info.y, info.width, info.height)
+        })
+        .collect();
+
+    let started = Instant::now();
+    let mut captures = if let [screen] = screens.as_slice() {
+        vec![capture_display_rgba(screen)?]
+    } else {
+        // Each display is an independent OS call, so grab them all at once;
+        // try_join_all keeps results in display order
+        futures_util::future::try_join_all(screens.into_iter().map(|screen| async move {
+            tokio::task::spawn_blocking(move || capture_display_rgba(&screen))
+                .await
+                .map_err(|e| format!("Capture task failed: {}", e))?
+        }))
+        .await?
+    };
+    debug!(
+        "Captured {} displays in {:?}",
+        captures.len(),
+        started.elapsed()
+    );
+    for (image, &(x, y, width, _)) in captures.iter_mut().zip(&bounds) {
+        mask_window_rects(image, excluded, (x, y), width)?;
+    }
+
+    // Display bounds may be in logical points; captures are in physical
+    // pixels. Lay out at the densest display's scale so nothing is shrunk.
+    let scale = bounds
+        .iter()
+        .zip(&captures)
+        .map(|(&(_, _, width, _), image)| f64::from(image.width()) / f64::from(width.max(1)))
+        .fold(1.0, f64::max);
+    let (width, height, placements) = panorama_layout(&bounds, scale);
+
+    let mut canvas = image::RgbaImage::new(width, height);
+    for (image, placement) in captures.into_iter().zip(placements) {
+        let image = if image.dimensions() == (placement.width, placement.height) {
+            image
+        } else {
+            image::imageops::resize(
+                &image,
+                placement.width,
+                placement.height,
+                image::imageops::FilterType::Triangle,
+            )
+        };
+        image::imageops::replace(
+            &mut canvas,
+            &image,
+            i64::from(placement.x),
+            i64::from(placement.y),
+        );
+    }
+    let png_bytes = encode_rgba_png(canvas.as_raw(), width, height)?;
+    Ok((png_bytes, width, height))
+}
+
+fn capture_display_rgba(screen: &Screen) -> Result<image::RgbaImage, String> {
+    let png_bytes = screen
+        .capture()
+        .map_err(|e| {
+            format!(
+                "Failed to capture display {}: {}",
+                screen.display_info.id, e
+            )
+        })?
+        .to_png()
+        .map_err(|e| e.to_string())?;
+    image::load_from_memory_with_format(&png_bytes, image::ImageFormat::Png)
+        .map(|image| image.to_rgba8())
+        .map_err(|e| format!("Failed to decode capture: {}", e))
+}
+
+/// Places displays given as `(x, y, width, height)` in desktop coordinates
+/// onto one canvas at `scale` pixels per desktop unit. Returns the canvas
+/// size and each display's area on it; edges that touch on the desktop
+/// touch on the canvas too.
+fn panorama_layout(displays: &[(i32, i32, u32, u32)], scale: f64) -> (u32, u32, Vec<Rect>) {
+    let left = displays.iter().map(|d| i64::from(d.0)).min().unwrap_or(0);
+    let top = displays.iter().map(|d| i64::from(d.1)).min().unwrap_or(0);
+    let to_pixels = |units: i64| (units as f64 * scale).round() as u32;
+    let placements: Vec<Rect> = displays
+        .iter()
+        .map(|&(x, y, width, height)| {
+            let (x, y) = (i64::from(x) - left, i64::from(y) - top);
+            Rect {
+                x: to_pixels(x),
+                y: to_pixels(y),
+                width: to_pixels(x + i64::from(width)) - to_pixels(x),
+                height: to_pixels(y + i64::from(height)) - to_pixels(y),
+            }
+        })
+        .collect();
+    let width = placements.iter().map(|r| r.x + r.width).max().unwrap_or(0);
+    let height = placements.iter().map(|r| r.y + r.height).max().unwrap_or(0);
+    (width, height, placements)
+}
+
+#[tauri::command]
+fn set_capture_debounce_ms(app: AppHandle, debounce_ms: u64) -> Result<(), SpotlightError> {
+    let store = settings_store(&app)?;
+    store.set(CAPTURE_DEBOUNCE_MS_KEY, debounce_ms);
+    store.save().map_err(SpotlightError::from)
+}
+
+#[tauri::command]
+fn capture_screen_delayed(
+    app: AppHandle,
+    window: tauri::Window,
+    delay_ms: u64,
+    include_cursor: Option<bool>,
+) -> Result<(), SpotlightError> {
+    if delay_ms > MAX_CAPTURE_DELAY_MS {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Capture delay of {}ms exceeds the maximum of {}ms",
+            delay_ms, MAX_CAPTURE_DELAY_MS
+        )));
+    }
+
+    tauri::async_runtime::spawn(async move {
+        let mut remaining_ms = delay_ms;
+        while remaining_ms > 0 {
+            let remaining_secs = remaining_ms.div_ceil(1000);
+            set_tray_tooltip(&app, &format!("{} — Capturing in {}s", TRAY_TOOLTIP, remaining_secs));
+            let step_ms = remaining_ms.min(1000);
+            tokio::time::sleep(std::time::Duration::from_millis(step_ms)).await;
+            remaining_ms -= step_ms;
+        }
+        if let Some(state) = app.try_state::<TrayMenuState>() {
+            state.refresh_tooltip();
+        }
+
+        let payload = match capture_screen_inner(&window, include_cursor.unwrap_or(false)) {
+            Ok(image_data) => CaptureReadyPayload {
+                image_data: Some(image_data),
+                error: None,
+            },
+            Err(err) => CaptureReadyPayload {
+                image_data: None,
+                error: Some(err),
+            },
+        };
+        if let Err(err) = app.emit(CAPTURE_READY_EVENT, payload) {
+            error!("Failed to emit capture ready event: {err}");
+        }
+    });
+
+    Ok(())
+}
+
+#[tauri::command]
+fn capture_to_clipboard(app: AppHandle) -> Result<(), SpotlightError> {
+    copy_capture_to_clipboard(&app)
+}
+
+/// Captures the display with each of `regions` blacked out (or blurred) so
+/// masked content never leaves the machine.
+#[tauri::command]
+async fn capture_with_redactions(
+    window: tauri::Window,
+    regions: Vec<Rect>,
+    style: Option<RedactionStyle>,
+) -> Result<String, SpotlightError> {
+    let png_bytes = capture_screen_png(&window).map_err(SpotlightError::Capture)?;
+    let mut image = image::load_from_memory_with_format(&png_bytes, image::ImageFormat::Png)
+        .map_err(|e| SpotlightError::Capture(format!("Failed to decode capture: {}", e)))?
+        .to_rgba8();
+    redact_regions(&mut image, &regions, style.unwrap_or_default())?;
+    let redacted = encode_rgba_png(image.as_raw(), image.width(), image.height())
+        .map_err(SpotlightError::Capture)?;
+    Ok(general_purpose::STANDARD.encode(redacted))
+}
+
+/// A cropped capture and the area it was actually cut from.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RegionCapture {
+    image_data: String,
+    rect: Rect,
+}
+
+/// Captures `rect` of the display. The rect is clipped to the capture and,
+/// with `aspect_ratio` (e.g. `[16, 9]`), narrowed around its centre to that
+/// ratio; the rect used is returned so the UI can reflect the adjustment.
+#[tauri::command]
+async fn capture_region(
+    window: tauri::Window,
+    rect: Rect,
+    aspect_ratio: Option<(u32, u32)>,
+) -> Result<RegionCapture, SpotlightError> {
+    let png_bytes = capture_screen_png(&window).map_err(SpotlightError::Capture)?;
+    let image = image::load_from_memory_with_format(&png_bytes, image::ImageFormat::Png)
+        .map_err(|e| SpotlightError::Capture(format!("Failed to decode capture: {}", e)))?
+        .to_rgba8();
+    let (width, height) = image.dimensions();
+    let mut region = clip_rect(rect, width, height).ok_or_else(|| {
+        SpotlightError::InvalidInput(format!(
+            "Region ({}x{} at {},{}) does not overlap the {}x{} capture",
+            rect.width, rect.height, rect.x, rect.y, width, height
+        ))
+    })?;
+    if let Some(ratio) = aspect_ratio {
+        region = fit_aspect_ratio(region, ratio)?;
+    }
+
+    let cropped =
+        image::imageops::crop_imm(&image, region.x, region.y, region.width, region.height)
+            .to_image();
+    let png_bytes = encode_rgba_png(cropped.as_raw(), cropped.width(), cropped.height())
+        .map_err(SpotlightError::Capture)?;
+    Ok(RegionCapture {
+        image_data: general_purpose::STANDARD.encode(png_bytes),
+        rect: region,
+    })
+}
+
+#[tauri::command]
+fn capture_to_file(path: String) -> Result<String, SpotlightError> {
+    save_capture_to_file(std::path::Path::new(&path))
+}
+
+#[tauri::command]
+fn get_clipboard_image(app: AppHandle) -> Result<Option<CaptureResult>, SpotlightError> {
+    read_clipboard_image(&app)
+}
+
+#[tauri::command]
+async fn attach_image_file(app: AppHandle, path: String) -> Result<CaptureResult, SpotlightError> {
+    let (max_bytes, max_dimension) = stored_image_file_limits(&app);
+    tokio::task::spawn_blocking(move || {
+        load_image_file(std::path::Path::new(&path), max_bytes, max_dimension)
+    })
+    .await
+    .map_err(|e| SpotlightError::Platform(format!("Image loading task failed: {}", e)))?
+}
+
+#[tauri::command]
+async fn ocr_image(data: String) -> Result<String, SpotlightError> {
+    let png_bytes = general_purpose::STANDARD
+        .decode(data.trim())
+        .map_err(|e| SpotlightError::InvalidInput(format!("Invalid base64 image data: {}", e)))?;
+    tokio::task::spawn_blocking(move || recognize_text(&png_bytes))
+        .await
+        .map_err(|e| SpotlightError::Platform(format!("OCR task failed: {}", e)))?
+}
+
+#[tauri::command]
+fn sync_tray_visibility(state: State<'_, TrayMenuState>, visible: bool) {
+    state.set_visibility(visible);
+}
+
+#[tauri::command]
+fn get_global_shortcut(app: AppHandle) -> Result<String, SpotlightError> {
+    let store = settings_store(&app)?;
+    Ok(stored_shortcut(&store, GLOBAL_SHORTCUT_KEY, DEFAULT_GLOBAL_SHORTCUT))
+}
+
+#[tauri::command]
+fn set_global_shortcut(
+    app: AppHandle,
+    state: State<'_, GlobalShortcutState>,
+    accelerator: String,
+) -> Result<(), SpotlightError> {
+    debug!("Setting global shortcut to: {}", accelerator);
+    let accelerator = normalize_accelerator(&accelerator)?;
+    replace_shortcut(&app, &state.current, &accelerator)?;
+
+    let store = settings_store(&app)?;
+    store.set(GLOBAL_SHORTCUT_KEY, accelerator);
+    store.save().map_err(SpotlightError::from)?;
+    Ok(())
+}
+
+#[tauri::command]
+fn get_capture_shortcut(app: AppHandle) -> Result<String, SpotlightError> {
+    let store = settings_store(&app)?;
+    Ok(stored_shortcut(&store, CAPTURE_SHORTCUT_KEY, DEFAULT_CAPTURE_SHORTCUT))
+}
+
+#[tauri::command]
+fn set_capture_shortcut(
+    app: AppHandle,
+    state: State<'_, GlobalShortcutState>,
+    accelerator: String,
+) -> Result<(), SpotlightError> {
+    debug!("Setting capture shortcut to: {}", accelerator);
+    let accelerator = normalize_accelerator(&accelerator)?;
+    replace_shortcut(&app, &state.capture, &accelerator)?;
+
+    let store = settings_store(&app)?;
+    store.set(CAPTURE_SHORTCUT_KEY, accelerator);
+    store.save().map_err(SpotlightError::from)?;
+    Ok(())
+}
+
+#[tauri::command]
+fn set_always_on_top(app: AppHandle, enabled: bool) -> Result<(), SpotlightError> {
+    apply_always_on_top(&app, enabled)?;
+    let store = settings_store(&app)?;
+    store.set(ALWAYS_ON_TOP_KEY, enabled);
+    store.save().map_err(SpotlightError::from)
+}
+
+/// Sets how opaque the main window is, from 0.2 to 1.0.
+#[tauri::command]
+fn set_window_opacity(app: AppHandle, level: f64) -> Result<(), SpotlightError> {
+    if !WINDOW_OPACITY_RANGE.contains(&level) {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Window opacity must be between {} and {}",
+            WINDOW_OPACITY_RANGE.start(),
+            WINDOW_OPACITY_RANGE.end()
+        )));
+    }
+    apply_window_opacity(&app, level)?;
+    let store = settings_store(&app)?;
+    store.set(WINDOW_OPACITY_KEY, level);
+    store.save().map_err(SpotlightError::from)
+}
+
+#[tauri::command]
+fn apply_window_effect(app: AppHandle, r: u8, g: u8, b: u8, a: u8) -> Result<(), SpotlightError> {
+    let tint = WindowTint { r, g, b, a };
+    apply_main_window_effect(&app, Some(tint))?;
+    let store = settings_store(&app)?;
+    let tint_json = serde_json::to_value(tint)
+        .map_err(|e| SpotlightError::Store(format!("Failed to serialize window tint: {}", e)))?;
+    store.set(WINDOW_TINT_KEY, tint_json);
+    store.save().map_err(SpotlightError::from)
+}
+
+#[tauri::command]
+fn set_autostart(app: AppHandle, enabled: bool) -> Result<(), SpotlightError> {
+    apply_autostart(&app, enabled)?;
+    let store = settings_store(&app)?;
+    store.set(AUTOSTART_KEY, enabled);
+    store.save().map_err(SpotlightError::from)
+}
+
+#[tauri::command]
+fn set_notify_on_complete(app: AppHandle, enabled: bool) -> Result<(), SpotlightError> {
+    let store = settings_store(&app)?;
+    store.set(NOTIFY_ON_COMPLETE_KEY, enabled);
+    store.save().map_err(SpotlightError::from)
+}
+
+#[tauri::command]
+fn is_autostart_enabled(app: AppHandle) -> Result<bool, SpotlightError> {
+    app.autolaunch()
+        .is_enabled()
+        .map_err(|e| SpotlightError::Platform(format!("Failed to read launch at login: {}", e)))
+}
+
+#[tauri::command]
+async fn check_for_updates(app: AppHandle) -> Result<UpdateCheckResult, SpotlightError> {
+    let channel = stored_update_channel(&app);
+    debug!("Checking for {:?} updates...", channel);
+    let endpoints = channel_update_endpoints(&configured_update_endpoints(&app), channel);
+    let updater = app
+        .updater_builder()
+        .endpoints(endpoints)
+        .and_then(|builder| {
+            builder
+                .version_comparator(move |current, remote| {
+                    // Leaving beta for stable must offer the latest stable
+                    // release even though the installed beta is newer
+                    if channel == UpdateChannel::Stable && !current.pre.is_empty() {
+                        remote.version != current
+                    } else {
+                        remote.version > current
+                    }
+                })
+                .build()
+        })
+        .map_err(|e| SpotlightError::Update(format!("Updater is not available: {}", e)))?;
+    let update = updater.check().await.map_err(|e| match e {
+        tauri_plugin_updater::Error::Reqwest(_) | tauri_plugin_updater::Error::Network(_) => {
+            SpotlightError::Network(format!("Failed to check for updates: {}", e))
+        }
+        other => SpotlightError::Update(other.to_string()),
+    })?;
+
+    let current_version = app.package_info().version.to_string();
+    match update {
+        Some(update) => {
+            debug!("Update available: {}", update.version);
+            let payload = UpdateAvailablePayload {
+                version: update.version.clone(),
+                body: update.body.clone().unwrap_or_default(),
+                date: update.date.map(|date| date.to_string()).unwrap_or_default(),
+                channel,
+            };
+            if let Err(err) = app.emit(UPDATE_AVAILABLE_EVENT, payload) {
+                error!("Failed to emit update available event: {err}");
+            }
+            Ok(UpdateCheckResult {
+                available: true,
+                version: Some(update.version),
+                current_version,
+                channel,
+            })
+        }
+        None => {
+            debug!("Already up to date");
+            Ok(UpdateCheckResult {
+                available: false,
+                version: None,
+                current_version,
+                channel,
+            })
+        }
+    }
+}
+
+fn stored_update_channel(app: &AppHandle) -> UpdateChannel {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(UPDATE_CHANNEL_KEY))
+        .and_then(|json| serde_json::from_value(json).ok())
+        .unwrap_or_default()
+}
+
+/// Switches release streams and checks the new one right away.
+#[tauri::command]
+fn set_update_channel(app: AppHandle, channel: UpdateChannel) -> Result<(), SpotlightError> {
+    let store = settings_store(&app)?;
+    let channel_json = serde_json::to_value(channel)
+        .map_err(|e| SpotlightError::Store(format!("Failed to serialize update channel: {}", e)))?;
+    store.set(UPDATE_CHANNEL_KEY, channel_json);
+    store.save()?;
+    spawn_update_check(&app);
+    Ok(())
+}
+
+/// The stable endpoints from the updater section of tauri.conf.json.
+fn configured_update_endpoints(app: &AppHandle) -> Vec<tauri::Url> {
+    app.config()
+        .plugins
+        .0
+        .get("updater")
+        .and_then(|updater| updater.get("endpoints"))
+        .and_then(|endpoints| serde_json::from_value::<Vec<String>>(endpoints.clone()).ok())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|endpoint| tauri::Url::parse(endpoint).ok())
+        .collect()
+}
+
+/// Points the configured stable endpoints at the beta channel's manifests.
+fn channel_update_endpoints(stable: &[tauri::Url], channel: UpdateChannel) -> Vec<tauri::Url> {
+    if channel == UpdateChannel::Stable {
+        return stable.to_vec();
+    }
+    stable
+        .iter()
+        .map(|endpoint| {
+            let mut endpoint = endpoint.clone();
+            match endpoint.path().strip_suffix(".json") {
+                Some(stem) => {
+                    let path = format!("{}-beta.json", stem);
+                    endpoint.set_path(&path);
+                }
+                None => {
+                    endpoint.query_pairs_mut().append_pair("channel", "beta");
+                }
+            }
+            endpoint
+        })
+        .collect()
+}
+
+/// Version and platform details for the settings footer and bug reports.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AppInfo {
+    version: String,
+    tauri_version: String,
+    os: String,
+    arch: String,
+    /// `YYYY-MM-DD` the build script last ran.
+    build_date: String,
+}
+
+#[tauri::command]
+fn get_app_info(app: AppHandle) -> AppInfo {
+    AppInfo {
+        // tauri.conf.json's version, which the updater compares against;
+        // the crate version in Cargo.toml is not kept in step with it
+        version: app.package_info().version.to_string(),
+        tauri_version: tauri::VERSION.to_string(),
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        build_date: env!("SPOTLIGHT_BUILD_DATE").to_string(),
+    }
+}
+
+#[tauri::command]
+fn set_tray_behavior(
+    app: AppHandle,
+    state: State<'_, TrayMenuState>,
+    behavior: TrayLeftClickBehavior,
+) -> Result<(), SpotlightError> {
+    state.set_left_click_behavior(behavior);
+    let store = settings_store(&app)?;
+    let behavior_json = serde_json::to_value(behavior)
+        .map_err(|e| SpotlightError::Store(format!("Failed to serialize tray behavior: {}", e)))?;
+    store.set(TRAY_LEFT_CLICK_KEY, behavior_json);
+    store.save().map_err(SpotlightError::from)
+}
+
+#[tauri::command]
+fn take_pending_query(state: State<'_, DeepLinkState>) -> Option<String> {
+    state.pending_query.lock().ok().and_then(|mut pending| pending.take())
+}
+
+#[tauri::command]
+async fn list_models(
+    app: AppHandle,
+    cache: State<'_, ModelCacheState>,
+) -> Result<Vec<ModelInfo>, SpotlightError> {
+    if let Some(models) = cache.models.lock().ok().and_then(|models| models.clone()) {
+        return Ok(models);
+    }
+    let store = settings_store(&app)?;
+    let api_key = active_api_key(&store)
+        .filter(|key| !key.trim().is_empty())
+        .ok_or(SpotlightError::MissingApiKey)?;
+    let models = fetch_generate_content_models(&api_key, &stored_gemini_settings(&app))
+        .await
+        .map_err(|err| err.redacted(&api_key))?;
+    if let Ok(mut cached) = cache.models.lock() {
+        *cached = Some(models.clone());
+    }
+    Ok(models)
+}
+
+/// Returns the favicon of the site behind a source link as a `data:` URL, or
+/// `None` when it can't be found quickly. Grounding links redirect to the
+/// real site, so icons are cached by the host the link lands on. Only public
+/// hosts are contacted, so a source link can't probe the local network.
+#[tauri::command]
+async fn fetch_favicon(
+    uri: String,
+    cache: State<'_, FaviconCacheState>,
+) -> Result<Option<String>, SpotlightError> {
+    let url = parse_http_url(&uri)?;
+    if !is_public_host(&url) {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Favicons are only fetched from public hosts, not '{}'",
+            uri
+        )));
+    }
+    let cached = |host: &str| {
+        cache
+            .icons
+            .lock()
+            .ok()
+            .and_then(|icons| icons.get(host).cloned())
+    };
+    if let Some(icon) = url.host_str().and_then(cached) {
+        return Ok(icon);
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(FAVICON_TIMEOUT)
+        .dns_resolver(Arc::new(PublicDnsResolver))
+        .redirect(reqwest::redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() >= MAX_FAVICON_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if !is_public_host(attempt.url()) {
+                attempt.error("redirected to a non-public host")
+            } else {
+                attempt.follow()
+            }
+        }))
+        .build()?;
+    let Ok(page) = client.get(url).send().await else {
+        return Ok(None);
+    };
+    let page_url = page.url().clone();
+    let Some(host) = page_url.host_str().map(str::to_string) else {
+        return Ok(None);
+    };
+    if let Some(icon) = cached(&host) {
+        return Ok(icon);
+    }
+    let is_html = response_mime_type(&page).is_some_and(|mime_type| mime_type == "text/html");
+    let html = if is_html {
+        read_body_up_to(page, MAX_FAVICON_PAGE_BYTES)
+            .await
+            .map(|(bytes, _)| String::from_utf8_lossy(&bytes).into_owned())
+    } else {
+        None
+    };
+    let icon_url = html
+        .as_deref()
+        .and_then(find_icon_href)
+        .and_then(|href| page_url.join(&href).ok())
+        .or_else(|| page_url.join("/favicon.ico").ok())
+        .filter(is_public_host);
+
+    let icon = match icon_url {
+        Some(icon_url) => download_favicon(&client, icon_url).await,
+        None => None,
+    };
+    if let Ok(mut icons) = cache.icons.lock() {
+        icons.insert(host, icon.clone());
+    }
+    Ok(icon)
+}
+
+/// Downloads an icon, refusing anything that isn't labelled `image/*` or that
+/// runs past `MAX_FAVICON_BYTES`.
+async fn download_favicon(client: &reqwest::Client, url: reqwest::Url) -> Option<String> {
+    let response = client.get(url).send().await.ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    let mime_type =
+        response_mime_type(&response).filter(|mime_type| mime_type.starts_with("image/"))?;
+    let (bytes, complete) = read_body_up_to(response, MAX_FAVICON_BYTES).await?;
+    if bytes.is_empty() || !complete {
+        return None;
+    }
+    Some(format!(
+        "data:{};base64,{}",
+        mime_type,
+        general_purpose::STANDARD.encode(&bytes)
+    ))
+}
+
+/// The response's `Content-Type` without parameters, lowercased.
+fn response_mime_type(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+}
+
+/// Streams a body until it ends or passes `limit` bytes, returning at most
+/// `limit` bytes and whether the whole body fit.
+async fn read_body_up_to(response: reqwest::Response, limit: usize) -> Option<(Vec<u8>, bool)> {
+    use futures_util::StreamExt;
+
+    let mut body = Vec::new();
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.ok()?;
+        let room = limit - body.len();
+        if chunk.len() > room {
+            body.extend_from_slice(&chunk[..room]);
+            return Some((body, false));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Some((body, true))
+}
+
+/// Whether `url` names a host on the public internet: not a loopback,
+/// private, link-local or otherwise reserved address, and not a `localhost`,
+/// `.local` or single-label name that only resolves on the local network.
+fn is_public_host(url: &reqwest::Url) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = host.parse::<std::net::IpAddr>() {
+        return is_public_ip(ip);
+    }
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    host.contains('.')
+        && host != "localhost"
+        && !host.ends_with(".localhost")
+        && !host.ends_with(".local")
+        && !host.ends_with(".internal")
+}
+
+fn is_public_ip(ip: std::net::IpAddr) -> bool {
+    match ip {
+        std::net::IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        std::net::IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_ip(ip.into());
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local, fc00::/7
+                || (first & 0xfe00) == 0xfc00
+                // Link local, fe80::/10
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Resolves names as usual but drops non-public addresses, so a public name
+/// pointing at the local network can't slip past [`is_public_host`].
+struct PublicDnsResolver;
+
+impl reqwest::dns::Resolve for PublicDnsResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs: Vec<std::net::SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// The `href` of the first `<link rel="icon">` (or `shortcut icon`) tag.
+fn find_icon_href(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let mut rest = lower.as_str();
+    while let Some(start) = rest.find("<link") {
+        let offset = lower.len() - rest.len() + start;
+        let end = lower[offset..]
+            .find('>')
+            .map_or(lower.len(), |end| offset + end);
+        // Attribute names come from the lowercased copy, values from the original
+        let attributes = parse_tag_attributes(
+            &lower[offset + "<link".len()..end],
+            &html[offset + "<link".len()..end],
+        );
+        let is_icon = attributes.get("rel").is_some_and(|rel| {
+            rel.split_ascii_whitespace()
+                .any(|token| token.eq_ignore_ascii_case("icon"))
+        });
+        if let Some(href) = attributes
+            .get("href")
+            .filter(|href| is_icon && !href.is_empty())
+        {
+            return Some(href.to_string());
+        }
+        rest = &lower[end..];
+    }
+    None
+}
+
+fn parse_tag_attributes<'a>(lower: &str, original: &'a str) -> HashMap<String, &'a str> {
+    let mut attributes = HashMap::new();
+    let bytes = lower.as_bytes();
+    let mut index = 0;
+    while index < bytes.len() {
+        while index < bytes.len() && (bytes[index].is_ascii_whitespace() || bytes[index] == b'/') {
+            index += 1;
+        }
+        let name_start = index;
+        while index < bytes.len()
+            && !bytes[index].is_ascii_whitespace()
+            && !matches!(bytes[index], b'=' | b'/')
+        {
+            index += 1;
+        }
+        let name = &lower[name_start..index];
+        if index < bytes.len() && bytes[index] == b'=' {
+            index += 1;
+            let (value_start, value_end) = match bytes.get(index) {
+                Some(&quote @ (b'"' | b'\'')) => {
+                    let start = index + 1;
+                    let end = lower[start..]
+                        .find(quote as char)
+                        .map_or(bytes.len(), |end| start + end);
+                    index = (end + 1).min(bytes.len());
+                    (start, end)
+                }
+                _ => {
+                    let start = index;
+                    while index < bytes.len() && !bytes[index].is_ascii_whitespace() {
+                        index += 1;
+                    }
+                    (start, index)
+                }
+            };
+            attributes.insert(name.to_string(), original[value_start..value_end].trim());
+        } else if !name.is_empty() {
+            attributes.insert(name.to_string(), "");
+        } else {
+            index += 1;
+        }
+    }
+    attributes
+}
+
+/// Result of [`health_check`]. `None` means the check doesn't apply to the
+/// configured backend or couldn't get that far.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HealthStatus {
+    network_ok: bool,
+    auth_ok: Option<bool>,
+    latency_ms: Option<u64>,
+    model_available: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<SpotlightError>,
+}
+
+/// Confirms the backend is reachable and, for Gemini, that the key works and
+/// the model exists, using a single metadata GET so it is cheap to run often.
+#[tauri::command]
+async fn health_check(app: AppHandle) -> Result<HealthStatus, SpotlightError> {
+    let store = settings_store(&app)?;
+    let api_key = active_api_key(&store).unwrap_or_default();
+    let backend = stored_backend(&app);
+    let client = reqwest::Client::builder()
+        .timeout(HEALTH_CHECK_TIMEOUT)
+        .build()?;
+
+    let started = Instant::now();
+    let response = match &backend {
+        Backend::Gemini => {
+            let gemini = stored_gemini_settings(&app);
+            let model = gemini_model(active_profile_model(&app));
+            let url = gemini.endpoint(&format!("models/{}", model));
+            gemini_get(&client, &url, &api_key, &gemini.headers).send().await
+        }
+        Backend::OpenAiCompatible { base_url } | Backend::Ollama { base_url } => {
+            client.get(base_url.as_str()).send().await
+        }
+    };
+    let latency_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+
+    let response = match response {
+        Ok(response) => response,
+        Err(err) => {
+            return Ok(HealthStatus {
+                network_ok: false,
+                auth_ok: None,
+                latency_ms: None,
+                model_available: None,
+                error: Some(SpotlightError::from(err).redacted(&api_key)),
+            });
+        }
+    };
+    // Any HTTP answer proves the server is reachable; only Gemini is probed further
+    if backend != Backend::Gemini {
+        return Ok(HealthStatus {
+            network_ok: true,
+            auth_ok: None,
+            latency_ms: Some(latency_ms),
+            model_available: None,
+            error: None,
+        });
+    }
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(HealthStatus {
+            network_ok: true,
+            auth_ok: Some(true),
+            latency_ms: Some(latency_ms),
+            model_available: Some(true),
+            error: None,
+        });
+    }
+    let error_text = response.text().await.unwrap_or_default();
+    let error = classify_api_error(status, error_text).redacted(&api_key);
+    let auth_ok = !matches!(error, SpotlightError::AuthFailed(_));
+    Ok(HealthStatus {
+        network_ok: true,
+        auth_ok: Some(auth_ok),
+        latency_ms: Some(latency_ms),
+        model_available: (status == reqwest::StatusCode::NOT_FOUND).then_some(false),
+        error: Some(error),
+    })
+}
+
+#[tauri::command]
+fn get_log_path(app: AppHandle) -> Result<String, SpotlightError> {
+    let log_dir = app.path().app_log_dir()?;
+    Ok(log_dir.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+fn open_source(app: AppHandle, uri: String) -> Result<(), SpotlightError> {
+    let url = parse_http_url(&uri)?;
+    app.opener()
+        .open_url(url.as_str(), None::<&str>)
+        .map_err(|e| SpotlightError::Platform(format!("Failed to open source: {}", e)))
+}
+
+/// Opens a source announced by `sources-ready`.
+#[tauri::command]
+fn open_source_by_id(
+    app: AppHandle,
+    state: State<'_, SourceRegistryState>,
+    id: String,
+) -> Result<(), SpotlightError> {
+    let uri = state
+        .uris
+        .lock()
+        .map_err(|_| SpotlightError::Platform("Source registry is poisoned".to_string()))?
+        .get(&id)
+        .cloned()
+        .ok_or_else(|| SpotlightError::InvalidInput(format!("Unknown source id: {}", id)))?;
+    open_source(app, uri)
+}
+
+#[tauri::command]
+fn open_api_settings_window(app: AppHandle) -> Result<(), SpotlightError> {
+    open_settings_window(&app).map_err(SpotlightError::from)
+}
+
+#[tauri::command]
+fn close_api_settings_window(app: AppHandle) -> Result<(), SpotlightError> {
+    if let Some(window) = app.get_webview_window(SETTINGS_WINDOW_LABEL) {
+        window.close().map_err(SpotlightError::from)
+    } else {
+        // Window is already closed or doesn't exist
+        Ok(())
+    }
+}
+
+/// Captures the display as base64 PNG, drawing the pointer onto it when
+/// `include_cursor` is set (no platform's capture path includes it natively).
+fn capture_screen_inner(window: &tauri::Window, include_cursor: bool) -> Result<String, String> {
+    let png_bytes = capture_screen_png(window)?;
+    if !include_cursor {
+        return Ok(general_purpose::STANDARD.encode(png_bytes));
+    }
+    match overlay_cursor(window, &png_bytes) {
+        Ok(with_cursor) => Ok(general_purpose::STANDARD.encode(with_cursor)),
+        Err(err) => {
+            warn!("Capturing without cursor: {}", err);
+            Ok(general_purpose::STANDARD.encode(png_bytes))
+        }
+    }
+}
+
+fn capture_screen_png(_window: &tauri::Window) -> Result<Vec<u8>, String> {
+    #[cfg(target_os = "macos")]
+    {
+        match capture_screen_without_overlay_mac(_window) {
+            Ok(png_bytes) => return Ok(png_bytes),
+            // The regular capture can't keep excluded windows out, so fail closed
+            Err(err) if !stored_excluded_windows(_window.app_handle()).is_empty() => {
+                return Err(format!(
+                    "Capture failed and excluded windows can't be kept out of a regular capture: {}",
+                    err
+                ));
+            }
+            Err(err) => {
+                warn!("Falling back to regular capture: {}", err);
+            }
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        match capture_screen_without_overlay_windows(_window) {
+            Ok(png_bytes) => return Ok(png_bytes),
+            // The regular capture can't keep excluded windows out, so fail closed
+            Err(err) if !stored_excluded_windows(_window.app_handle()).is_empty() => {
+                return Err(format!(
+                    "Capture failed and excluded windows can't be kept out of a regular capture: {}",
+                    err
+                ));
+            }
+            Err(err) => {
+                warn!("Falling back to regular capture: {}", err);
+            }
+        }
+    }
+
+    capture_full_display_png()
+}
+
+/// Draws the system cursor at the pointer's current position on a capture of
+/// the primary monitor, or [`CURSOR_SPRITE`] where the cursor's image can't be
+/// read. A pointer on another monitor leaves the image as is.
+fn overlay_cursor(window: &tauri::Window, png_bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let cursor = window
+        .cursor_position()
+        .map_err(|e| format!("Failed to read cursor position: {}", e))?;
+    let monitor = window
+        .primary_monitor()
+        .map_err(|e| format!("Failed to read primary monitor: {}", e))?
+        .ok_or("No primary monitor found")?;
+    let mut image = image::load_from_memory_with_format(png_bytes, image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to decode capture: {}", e))?
+        .to_rgba8();
+    let (width, height) = image.dimensions();
+
+    // Both are in physical pixels, but the capture may be scaled differently
+    // from the monitor, so map through the pointer's relative position
+    let origin = monitor.position();
+    let size = monitor.size();
+    let x = (cursor.x - f64::from(origin.x)) / f64::from(size.width) * f64::from(width);
+    let y = (cursor.y - f64::from(origin.y)) / f64::from(size.height) * f64::from(height);
+    if x < 0.0 || y < 0.0 || x >= f64::from(width) || y >= f64::from(height) {
+        return Ok(png_bytes.to_vec());
+    }
+    let capture_scale = f64::from(width) / f64::from(size.width);
+
+    match system_cursor_image(monitor.scale_factor()) {
+        Some(cursor) => {
+            let scale = cursor.physical_pixels * capture_scale;
+            let cursor_width = (f64::from(cursor.rgba.width()) * scale).round().max(1.0) as u32;
+            let cursor_height = (f64::from(cursor.rgba.height()) * scale).round().max(1.0) as u32;
+            let rgba = image::imageops::resize(
+                &cursor.rgba,
+                cursor_width,
+                cursor_height,
+                image::imageops::FilterType::Triangle,
+            );
+            let left = x - f64::from(cursor.hotspot.0) * scale;
+            let top = y - f64::from(cursor.hotspot.1) * scale;
+            image::imageops::overlay(&mut image, &rgba, left as i64, top as i64);
+        }
+        None => {
+            let scale = (monitor.scale_factor() * capture_scale).round().max(1.0) as u32;
+            draw_cursor_sprite(&mut image, width, height, x as i64, y as i64, scale);
+        }
+    }
+    encode_rgba_png(image.as_raw(), width, height)
+}
+
+/// The pointer's current image as the OS draws it.
+struct CursorImage {
+    rgba: image::RgbaImage,
+    /// The click point, in `rgba` pixels from the top left.
+    hotspot: (u32, u32),
+    /// How many physical screen pixels one `rgba` pixel covers.
+    physical_pixels: f64,
+}
+
+/// Reads `NSCursor.currentSystemCursor`, which reflects the cursor of whatever
+/// app is frontmost. `scale_factor` is the primary monitor's.
+#[cfg(target_os = "macos")]
+#[allow(unexpected_cfgs)]
+fn system_cursor_image(scale_factor: f64) -> Option<CursorImage> {
+    use core_graphics::geometry::{CGPoint, CGSize};
+    use objc::runtime::{Class, Object};
+
+    // NSBitmapImageFileTypePNG
+    const PNG_FILE_TYPE: u64 = 4;
+
+    unsafe {
+        let cursor: *mut Object = msg_send![Class::get("NSCursor")?, currentSystemCursor];
+        if cursor.is_null() {
+            return None;
+        }
+        let ns_image: *mut Object = msg_send![cursor, image];
+        if ns_image.is_null() {
+            return None;
+        }
+        let hotspot: CGPoint = msg_send![cursor, hotSpot];
+        let size: CGSize = msg_send![ns_image, size];
+        let tiff: *mut Object = msg_send![ns_image, TIFFRepresentation];
+        if tiff.is_null() {
+            return None;
+        }
+        let rep: *mut Object = msg_send![Class::get("NSBitmapImageRep")?, imageRepWithData: tiff];
+        if rep.is_null() {
+            return None;
+        }
+        let properties: *mut Object = msg_send![Class::get("NSDictionary")?, dictionary];
+        let png: *mut Object =
+            msg_send![rep, representationUsingType: PNG_FILE_TYPE properties: properties];
+        if png.is_null() {
+            return None;
+        }
+        let bytes: *const u8 = msg_send![png, bytes];
+        let length: usize = msg_send![png, length];
+        let png_bytes = std::slice::from_raw_parts(bytes, length);
+        let rgba = image::load_from_memory_with_format(png_bytes, image::ImageFormat::Png)
+            .ok()?
+            .to_rgba8();
+        if rgba.width() == 0 || size.width <= 0.0 {
+            return None;
+        }
+        // The image and hotspot are in points; the bitmap may be @2x
+        let pixels_per_point = f64::from(rgba.width()) / size.width;
+        Some(CursorImage {
+            hotspot: (
+                (hotspot.x * pixels_per_point).max(0.0) as u32,
+                (hotspot.y * pixels_per_point).max(0.0) as u32,
+            ),
+            physical_pixels: scale_factor / pixels_per_point,
+            rgba,
+        })
+    }
+}
+
+/// Reads the current cursor with `GetCursorInfo` and `GetIconInfo`, drawing it
+/// once on black and once on white to recover its colors and transparency.
+#[cfg(target_os = "windows")]
+fn system_cursor_image(_scale_factor: f64) -> Option<CursorImage> {
+    use windows_sys::Win32::Graphics::Gdi::{DeleteObject, GetObjectW, BITMAP};
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        GetCursorInfo, GetIconInfo, CURSORINFO, CURSOR_SHOWING, ICONINFO,
+    };
+
+    unsafe {
+        let mut info: CURSORINFO = std::mem::zeroed();
+        info.cbSize = std::mem::size_of::<CURSORINFO>() as u32;
+        if GetCursorInfo(&mut info) == 0 || info.flags & CURSOR_SHOWING == 0 {
+            return None;
+        }
+        if info.hCursor.is_null() {
+            return None;
+        }
+        let mut icon: ICONINFO = std::mem::zeroed();
+        if GetIconInfo(info.hCursor, &mut icon) == 0 {
+            return None;
+        }
+        let mut mask: BITMAP = std::mem::zeroed();
+        let read = GetObjectW(
+            icon.hbmMask,
+            std::mem::size_of::<BITMAP>() as i32,
+            &mut mask as *mut BITMAP as *mut std::ffi::c_void,
+        );
+        let monochrome = icon.hbmColor.is_null();
+        DeleteObject(icon.hbmMask);
+        if !monochrome {
+            DeleteObject(icon.hbmColor);
+        }
+        // A monochrome cursor stacks its AND and XOR masks in one bitmap
+        let width = mask.bmWidth;
+        let height = if monochrome {
+            mask.bmHeight / 2
+        } else {
+            mask.bmHeight
+        };
+        if read == 0 || width <= 0 || height <= 0 {
+            return None;
+        }
+
+        let on_black = render_windows_cursor(info.hCursor, width, height, 0x00)?;
+        let on_white = render_windows_cursor(info.hCursor, width, height, 0xff)?;
+        Some(CursorImage {
+            rgba: image::RgbaImage::from_raw(
+                width as u32,
+                height as u32,
+                cursor_from_backgrounds(&on_black, &on_white),
+            )?,
+            hotspot: (icon.xHotspot, icon.yHotspot),
+            physical_pixels: 1.0,
+        })
+    }
+}
+
+/// Draws `cursor` onto a `width` x `height` top-down BGRA bitmap filled with
+/// `background` and returns its pixels.
+#[cfg(target_os = "windows")]
+unsafe fn render_windows_cursor(
+    cursor: windows_sys::Win32::UI::WindowsAndMessaging::HCURSOR,
+    width: i32,
+    height: i32,
+    background: u8,
+) -> Option<Vec<u8>> {
+    use windows_sys::Win32::Graphics::Gdi::{
+        CreateCompatibleDC, CreateDIBSection, DeleteDC, DeleteObject, GdiFlush, SelectObject,
+        BITMAPINFO, BI_RGB, DIB_RGB_COLORS,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::{DrawIconEx, DI_NORMAL};
+
+    let dc = CreateCompatibleDC(std::ptr::null_mut());
+    if dc.is_null() {
+        return None;
+    }
+    let mut bitmap_info: BITMAPINFO = std::mem::zeroed();
+    bitmap_info.bmiHeader.biSize = std::mem::size_of_val(&bitmap_info.bmiHeader) as u32;
+    bitmap_info.bmiHeader.biWidth = width;
+    // Negative height makes the rows run top-down
+    bitmap_info.bmiHeader.biHeight = -height;
+    bitmap_info.bmiHeader.biPlanes = 1;
+    bitmap_info.bmiHeader.biBitCount = 32;
+    bitmap_info.bmiHeader.biCompression = BI_RGB;
+    let mut bits: *mut std::ffi::c_void = std::ptr::null_mut();
+    let bitmap = CreateDIBSection(
+        dc,
+        &bitmap_info,
+        DIB_RGB_COLORS,
+        &mut bits,
+        std::ptr::null_mut(),
+        0,
+    );
+    if bitmap.is_null() || bits.is_null() {
+        DeleteDC(dc);
+        return None;
+    }
+    let len = width as usize * height as usize * 4;
+    let pixels = std::slice::from_raw_parts_mut(bits as *mut u8, len);
+    pixels.fill(background);
+    let previous = SelectObject(dc, bitmap);
+    let drawn = DrawIconEx(
+        dc,
+        0,
+        0,
+        cursor,
+        width,
+        height,
+        0,
+        std::ptr::null_mut(),
+        DI_NORMAL,
+    );
+    GdiFlush();
+    // BGRA to RGBA
+    let rendered: Vec<u8> = pixels
+        .chunks_exact(4)
+        .flat_map(|px| [px[2], px[1], px[0], 255])
+        .collect();
+    SelectObject(dc, previous);
+    DeleteObject(bitmap);
+    DeleteDC(dc);
+    (drawn != 0).then_some(rendered)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn system_cursor_image(_scale_factor: f64) -> Option<CursorImage> {
+    None
+}
+
+/// Recovers a cursor's straight RGBA from two opaque renders of it, one on
+/// black and one on white: a pixel that lets `t` of the background through
+/// differs by `255 * t` between them. Inverting pixels come out as black.
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+fn cursor_from_backgrounds(on_black: &[u8], on_white: &[u8]) -> Vec<u8> {
+    on_black
+        .chunks_exact(4)
+        .zip(on_white.chunks_exact(4))
+        .flat_map(|(black, white)| {
+            let see_through = (0..3)
+                .map(|i| u32::from(white[i].saturating_sub(black[i])))
+                .sum::<u32>()
+                / 3;
+            let alpha = 255 - see_through;
+            if alpha == 0 {
+                return [0, 0, 0, 0];
+            }
+            let channel = |i: usize| (u32::from(black[i]) * 255 / alpha).min(255) as u8;
+            [channel(0), channel(1), channel(2), alpha as u8]
+        })
+        .collect()
+}
+
+/// Paints [`CURSOR_SPRITE`] with its tip at `(x, y)` onto an RGBA buffer,
+/// enlarging each sprite pixel to `scale` pixels and clipping at the edges.
+fn draw_cursor_sprite(rgba: &mut [u8], width: u32, height: u32, x: i64, y: i64, scale: u32) {
+    let scale = i64::from(scale);
+    for (row, line) in CURSOR_SPRITE.iter().enumerate() {
+        for (col, cell) in line.bytes().enumerate() {
+            let color = match cell {
+                b'B' => [0, 0, 0, 255],
+                b'W' => [255, 255, 255, 255],
+                _ => continue,
+            };
+            for dy in 0..scale {
+                for dx in 0..scale {
+                    let px = x + col as i64 * scale + dx;
+                    let py = y + row as i64 * scale + dy;
+                    if px < 0 || py < 0 || px >= i64::from(width) || py >= i64::from(height) {
+                        continue;
+                    }
+                    let offset = ((py as usize) * width as usize + px as usize) * 4;
+                    rgba[offset..offset + 4].copy_from_slice(&color);
+                }
+            }
+        }
+    }
+}
+
+fn stored_excluded_windows(app: &AppHandle) -> Vec<String> {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(EXCLUDED_WINDOWS_KEY))
+        .and_then(|json| serde_json::from_value(json).ok())
+        .unwrap_or_default()
+}
+
+#[tauri::command]
+fn get_excluded_windows(app: AppHandle) -> Vec<String> {
+    stored_excluded_windows(&app)
+}
+
+/// Sets the app names or window titles kept out of captures. Patterns match
+/// case-insensitively and may use `*` and `?`; one without wildcards matches
+/// anywhere in the name or title.
+#[tauri::command]
+fn set_excluded_windows(app: AppHandle, patterns: Vec<String>) -> Result<(), SpotlightError> {
+    let patterns: Vec<String> = patterns
+        .into_iter()
+        .map(|pattern| pattern.trim().to_string())
+        .filter(|pattern| !pattern.is_empty())
+        .collect();
+    let store = settings_store(&app)?;
+    store.set(EXCLUDED_WINDOWS_KEY, patterns);
+    store.save().map_err(SpotlightError::from)
+}
+
+/// Whether a window belonging to `app_name` and titled `title` matches any
+/// of the exclusion `patterns`.
+fn is_window_excluded(patterns: &[String], app_name: &str, title: &str) -> bool {
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.to_lowercase();
+        let pattern = if pattern.contains(['*', '?']) {
+            pattern
+        } else {
+            format!("*{}*", pattern)
+        };
+        let pattern: Vec<char> = pattern.chars().collect();
+        [app_name, title].iter().any(|text| {
+            !text.is_empty()
+                && wildcard_match(&pattern, &text.to_lowercase().chars().collect::<Vec<_>>())
+        })
+    })
+}
+
+/// Glob match where `*` spans any run of characters and `?` exactly one.
+fn wildcard_match(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was seen and how much text it has swallowed so far
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Maps a window's desktop rect onto a capture of the display at `origin`,
+/// `scale` capture pixels per desktop unit, clipped to the capture.
+fn window_rect_in_capture(
+    (left, top, right, bottom): (i32, i32, i32, i32),
+    origin: (i32, i32),
+    scale: f64,
+    (width, height): (u32, u32),
+) -> Option<Rect> {
+    let to_pixels = |value: i32, origin: i32, limit: u32| {
+        ((f64::from(value) - f64::from(origin)) * scale)
+            .round()
+            .clamp(0.0, f64::from(limit)) as u32
+    };
+    let (x0, x1) = (
+        to_pixels(left, origin.0, width),
+        to_pixels(right, origin.0, width),
+    );
+    let (y0, y1) = (
+        to_pixels(top, origin.1, height),
+        to_pixels(bottom, origin.1, height),
+    );
+    (x1 > x0 && y1 > y0).then_some(Rect {
+        x: x0,
+        y: y0,
+        width: x1 - x0,
+        height: y1 - y0,
+    })
+}
+
+/// The part of `rect` inside a `width`x`height` image, if any.
+fn clip_rect(rect: Rect, width: u32, height: u32) -> Option<Rect> {
+    let right = rect.x.saturating_add(rect.width).min(width);
+    let bottom = rect.y.saturating_add(rect.height).min(height);
+    (right > rect.x && bottom > rect.y).then(|| Rect {
+        x: rect.x,
+        y: rect.y,
+        width: right - rect.x,
+        height: bottom - rect.y,
+    })
+}
+
+/// Shrinks the longer side of `rect` (relative to the ratio) so it matches
+/// `width:height`, keeping it centred on the original area.
+fn fit_aspect_ratio(rect: Rect, (width, height): (u32, u32)) -> Result<Rect, SpotlightError> {
+    if width == 0 || height == 0 {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Aspect ratio {}:{} must be positive",
+            width, height
+        )));
+    }
+    let (rect_width, rect_height) = (u64::from(rect.width), u64::from(rect.height));
+    let (ratio_width, ratio_height) = (u64::from(width), u64::from(height));
+    // Both results are at most the original side, so they fit back in a u32
+    let (fitted_width, fitted_height) = if rect_width * ratio_height > rect_height * ratio_width {
+        (rect_height * ratio_width / ratio_height, rect_height)
+    } else {
+        (rect_width, rect_width * ratio_height / ratio_width)
+    };
+    if fitted_width == 0 || fitted_height == 0 {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Region {}x{} is too small for a {}:{} aspect ratio",
+            rect.width, rect.height, width, height
+        )));
+    }
+    let (fitted_width, fitted_height) = (fitted_width as u32, fitted_height as u32);
+    Ok(Rect {
+        x: rect.x + (rect.width - fitted_width) / 2,
+        y: rect.y + (rect.height - fitted_height) / 2,
+        width: fitted_width,
+        height: fitted_height,
+    })
+}
+
+fn stored_capture_debounce(app: &AppHandle) -> Duration {
+    let debounce_ms = settings_store(app)
+        .ok()
+        .and_then(|store| store.get(CAPTURE_DEBOUNCE_MS_KEY))
+        .and_then(|json| json.as_u64())
+        .unwrap_or(DEFAULT_CAPTURE_DEBOUNCE_MS);
+    Duration::from_millis(debounce_ms)
+}
+
+/// Captures like [`capture_screen_inner`] but returns the previous shot when
+/// it was taken within the debounce interval. The lock is held for the whole
+/// capture so concurrent requests coalesce onto one result.
+fn debounced_capture(
+    app: &AppHandle,
+    window: &tauri::Window,
+    include_cursor: bool,
+) -> Result<String, SpotlightError> {
+    let Some(state) = app.try_state::<CaptureDebounceState>() else {
+        return capture_screen_inner(window, include_cursor).map_err(SpotlightError::Capture);
+    };
+    let mut last = state
+        .last
+        .lock()
+        .map_err(|_| SpotlightError::Platform("Capture state is poisoned".to_string()))?;
+    if let Some((taken_at, with_cursor, image_data)) = last.as_ref() {
+        if *with_cursor == include_cursor && taken_at.elapsed() < stored_capture_debounce(app) {
+            debug!("Reusing capture taken {:?} ago", taken_at.elapsed());
+            return Ok(image_data.clone());
+        }
+    }
+    let image_data = capture_screen_inner(window, include_cursor).map_err(SpotlightError::Capture)?;
+    *last = Some((Instant::now(), include_cursor, image_data.clone()));
+    Ok(image_data)
+}
+
+fn capture_full_display_png() -> Result<Vec<u8>, String> {
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    let screen = screens.first().ok_or("No screens found")?;
+
+    let image = screen.capture().map_err(|e| e.to_string())?;
+    image.to_png().map_err(|e| e.to_string())
+}
+
+fn encode_rgba_png(rgba: &[u8], width: u32, height: u32) -> Result<Vec<u8>, String> {
+    use png::{BitDepth, ColorType, Encoder};
+
+    let mut png_bytes = Vec::new();
+    {
+        let mut encoder = Encoder::new(&mut png_bytes, width, height);
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .map_err(|e| format!("Failed to write PNG header: {}", e))?;
+        writer
+            .write_image_data(rgba)
+            .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    }
+
+    Ok(png_bytes)
+}
+
+#[cfg(feature = "ocr")]
+fn recognize_text(image_bytes: &[u8]) -> Result<String, SpotlightError> {
+    let mut tesseract = leptess::LepTess::new(None, "eng")
+        .map_err(|e| SpotlightError::Platform(format!("Failed to initialize Tesseract: {}", e)))?;
+    tesseract
+        .set_image_from_mem(image_bytes)
+        .map_err(|e| SpotlightError::InvalidInput(format!("Failed to decode image for OCR: {}", e)))?;
+    let text = tesseract
+        .get_utf8_text()
+        .map_err(|e| SpotlightError::Parse(format!("OCR produced invalid UTF-8: {}", e)))?;
+    Ok(text.trim().to_string())
+}
+
+#[cfg(not(feature = "ocr"))]
+fn recognize_text(_image_bytes: &[u8]) -> Result<String, SpotlightError> {
+    Err(SpotlightError::Platform(
+        "OCR is not available in this build; rebuild with `--features ocr`".to_string(),
+    ))
+}
+
+/// Identifies the image formats Gemini accepts from their leading magic bytes.
+fn sniff_image_mime(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// Returns the byte limit and optional downscale dimension for attached files.
+fn stored_image_file_limits(app: &AppHandle) -> (u64, Option<u32>) {
+    let store = settings_store(app).ok();
+    let max_bytes = store
+        .as_ref()
+        .and_then(|store| store.get(MAX_IMAGE_FILE_BYTES_KEY))
+        .and_then(|json| json.as_u64())
+        .unwrap_or(DEFAULT_MAX_IMAGE_FILE_BYTES);
+    let max_dimension = store
+        .as_ref()
+        .and_then(|store| store.get(CAPTURE_MAX_DIMENSION_KEY))
+        .and_then(|json| json.as_u64())
+        .and_then(|max| u32::try_from(max).ok())
+        .filter(|max| *max > 0);
+    (max_bytes, max_dimension)
+}
+
+/// Encodes files dropped on the main window off the event loop, then reports
+/// the images and any rejected files to the frontend.
+fn handle_dropped_files(app: &AppHandle, paths: Vec<std::path::PathBuf>) {
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        let (max_bytes, max_dimension) = stored_image_file_limits(&app);
+        let mut images = Vec::new();
+        let mut rejected = Vec::new();
+        for path in paths {
+            match load_image_file(&path, max_bytes, max_dimension) {
+                Ok(image) => images.push(image),
+                Err(err) => rejected.push(RejectedDrop {
+                    path: path.to_string_lossy().to_string(),
+                    reason: err.to_string(),
+                }),
+            }
+        }
+        debug!("Dropped files: {} images, {} rejected", images.len(), rejected.len());
+        if !images.is_empty() {
+            if let Err(err) = app.emit(IMAGES_DROPPED_EVENT, ImagesDroppedPayload { images }) {
+                error!("Failed to emit images dropped event: {err}");
+            }
+        }
+        if !rejected.is_empty() {
+            if let Err(err) = app.emit(DROP_REJECTED_EVENT, DropRejectedPayload { rejected }) {
+                error!("Failed to emit drop rejected event: {err}");
+            }
+        }
+    });
+}
+
+/// Normalizes the clip's MIME type and rejects formats Gemini cannot transcribe.
+fn validate_audio(audio: InlineData) -> Result<InlineData, SpotlightError> {
+    let mime_type = audio.mime_type.trim().to_ascii_lowercase();
+    if !SUPPORTED_AUDIO_MIME_TYPES.contains(&mime_type.as_str()) {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Unsupported audio type '{}'; expected one of {}",
+            audio.mime_type,
+            SUPPORTED_AUDIO_MIME_TYPES.join(", ")
+        )));
+    }
+    if audio.data.trim().is_empty() {
+        return Err(SpotlightError::InvalidInput("Audio clip is empty".to_string()));
+    }
+    Ok(InlineData {
+        mime_type,
+        data: audio.data,
+    })
+}
+
+/// Normalizes the document's MIME type and rejects formats Gemini cannot read.
+fn validate_document(document: InlineData) -> Result<InlineData, SpotlightError> {
+    // Browsers sometimes append parameters, e.g. `text/plain;charset=utf-8`
+    let mime_type = document
+        .mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if !SUPPORTED_DOCUMENT_MIME_TYPES.contains(&mime_type.as_str()) {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Unsupported document type '{}'; expected one of {}",
+            document.mime_type,
+            SUPPORTED_DOCUMENT_MIME_TYPES.join(", ")
+        )));
+    }
+    if document.data.trim().is_empty() {
+        return Err(SpotlightError::InvalidInput(
+            "Document is empty".to_string(),
+        ));
+    }
+    Ok(InlineData {
+        mime_type,
+        data: document.data,
+    })
+}
+
+/// Wraps a base64 screenshot or upload, sniffing its MIME type.
+fn captured_image(data: String) -> ImageInput {
+    ImageInput {
+        mime_type: base64_image_mime(&data),
+        data,
+    }
+}
+
+/// MIME type of base64 image data, assuming PNG (what captures produce) when
+/// the header is unrecognized.
+fn base64_image_mime(data: &str) -> String {
+    // 16 base64 characters decode to the 12 header bytes sniffing looks at
+    let header: String = data.chars().filter(|c| !c.is_whitespace()).take(16).collect();
+    general_purpose::STANDARD
+        .decode(header)
+        .ok()
+        .and_then(|bytes| sniff_image_mime(&bytes))
+        .map(|mime| mime.to_string())
+        .unwrap_or_else(default_image_mime_type)
+}
+
+fn load_image_file(
+    path: &std::path::Path,
+    max_bytes: u64,
+    max_dimension: Option<u32>,
+) -> Result<CaptureResult, SpotlightError> {
+    let metadata = std::fs::metadata(path).map_err(|e| {
+        SpotlightError::InvalidInput(format!("Cannot read {}: {}", path.display(), e))
+    })?;
+    if !metadata.is_file() {
+        return Err(SpotlightError::InvalidInput(format!(
+            "{} is not a file",
+            path.display()
+        )));
+    }
+    if metadata.len() > max_bytes {
+        return Err(SpotlightError::InvalidInput(format!(
+            "{} is {} bytes, larger than the {} byte limit",
+            path.display(),
+            metadata.len(),
+            max_bytes
+        )));
+    }
+
+    let bytes = std::fs::read(path).map_err(|e| {
+        SpotlightError::Capture(format!("Failed to read {}: {}", path.display(), e))
+    })?;
+    let mime_type = sniff_image_mime(&bytes).ok_or_else(|| {
+        SpotlightError::InvalidInput(format!(
+            "{} is not a supported image (PNG, JPEG, GIF or WebP)",
+            path.display()
+        ))
+    })?;
+
+    let image = image::load_from_memory(&bytes).map_err(|e| {
+        SpotlightError::Capture(format!("Failed to decode {}: {}", path.display(), e))
+    })?;
+    let needs_downscale =
+        max_dimension.is_some_and(|max| image.width() > max || image.height() > max);
+    if !needs_downscale {
+        return Ok(CaptureResult {
+            image_data: general_purpose::STANDARD.encode(&bytes),
+            width: image.width(),
+            height: image.height(),
+            mime_type: mime_type.to_string(),
+        });
+    }
+
+    // Re-encoded as PNG since the original encoder settings are not preserved
+    let resized = downscale_to_fit(&image, max_dimension.unwrap_or(u32::MAX));
+    let png_bytes = encode_rgba_png(resized.as_raw(), resized.width(), resized.height())
+        .map_err(SpotlightError::Capture)?;
+    Ok(CaptureResult {
+        image_data: general_purpose::STANDARD.encode(png_bytes),
+        width: resized.width(),
+        height: resized.height(),
+        mime_type: default_image_mime_type(),
+    })
+}
+
+/// Shrinks `image` so neither side exceeds `max_dimension`, keeping its
+/// aspect ratio. Images that already fit are returned unchanged.
+fn downscale_to_fit(image: &image::DynamicImage, max_dimension: u32) -> image::RgbaImage {
+    if image.width() <= max_dimension && image.height() <= max_dimension {
+        return image.to_rgba8();
+    }
+    image
+        .resize(
+            max_dimension,
+            max_dimension,
+            image::imageops::FilterType::Triangle,
+        )
+        .to_rgba8()
+}
+
+/// Reads an image off the system clipboard as base64 PNG. A clipboard holding
+/// text or nothing at all is not an error.
+fn read_clipboard_image(app: &AppHandle) -> Result<Option<CaptureResult>, SpotlightError> {
+    let image = match app.clipboard().read_image() {
+        Ok(image) => image,
+        Err(err) => {
+            debug!("No image on clipboard: {}", err);
+            return Ok(None);
+        }
+    };
+    let png_bytes = encode_rgba_png(image.rgba(), image.width(), image.height())
+        .map_err(SpotlightError::Capture)?;
+    Ok(Some(CaptureResult {
+        image_data: general_purpose::STANDARD.encode(png_bytes),
+        width: image.width(),
+        height: image.height(),
+        mime_type: default_image_mime_type(),
+    }))
+}
+
+/// Masks every region, checking them all against the image bounds first so a
+/// bad rect never leaves the image half redacted.
+fn redact_regions(
+    image: &mut image::RgbaImage,
+    regions: &[Rect],
+    style: RedactionStyle,
+) -> Result<(), SpotlightError> {
+    let (width, height) = image.dimensions();
+    for (index, region) in regions.iter().enumerate() {
+        let fits = region.width > 0
+            && region.height > 0
+            && region.x.checked_add(region.width).is_some_and(|right| right <= width)
+            && region.y.checked_add(region.height).is_some_and(|bottom| bottom <= height);
+        if !fits {
+            return Err(SpotlightError::InvalidInput(format!(
+                "Redaction {} ({}x{} at {},{}) must be non-empty and within the {}x{} capture",
+                index + 1,
+                region.width,
+                region.height,
+                region.x,
+                region.y,
+                width,
+                height
+            )));
+        }
+    }
+
+    for region in regions {
+        match style {
+            RedactionStyle::Fill => {
+                for y in region.y..region.y + region.height {
+                    for x in region.x..region.x + region.width {
+                        image.put_pixel(x, y, image::Rgba([0, 0, 0, 255]));
+                    }
+                }
+            }
+            RedactionStyle::Blur => {
+                let area = image::imageops::crop_imm(
+                    &*image,
+                    region.x,
+                    region.y,
+                    region.width,
+                    region.height,
+                )
+                .to_image();
+                let blurred = image::imageops::blur(&area, REDACTION_BLUR_SIGMA);
+                image::imageops::replace(image, &blurred, i64::from(region.x), i64::from(region.y));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn save_capture_to_file(path: &std::path::Path) -> Result<String, SpotlightError> {
+    validate_export_path(path, &["png"])?;
+    create_parent_dir(path)?;
+
+    let png_bytes = capture_full_display_png().map_err(SpotlightError::Capture)?;
+    std::fs::write(path, png_bytes).map_err(|e| {
+        SpotlightError::Capture(format!("Failed to write {}: {}", path.display(), e))
+    })?;
+
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Checks a path the frontend wants a file written to. It must be absolute,
+/// since a relative path would land wherever the app happened to be launched
+/// from, and end in one of `extensions` (compared case-insensitively).
+fn validate_export_path(path: &std::path::Path, extensions: &[&str]) -> Result<(), SpotlightError> {
+    let has_extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            extensions
+                .iter()
+                .any(|allowed| ext.eq_ignore_ascii_case(allowed))
+        });
+    if !has_extension {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Unsupported file extension for {}: expected .{}",
+            path.display(),
+            extensions.join(" or .")
+        )));
+    }
+    if !path.is_absolute() {
+        return Err(SpotlightError::InvalidInput(format!(
+            "{} is not an absolute path",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+fn create_parent_dir(path: &std::path::Path) -> Result<(), SpotlightError> {
+    let Some(parent) = path.parent() else {
+        return Ok(());
+    };
+    std::fs::create_dir_all(parent).map_err(|e| {
+        SpotlightError::Platform(format!(
+            "Failed to create directory {}: {}",
+            parent.display(),
+            e
+        ))
+    })
+}
+
+fn copy_capture_to_clipboard(app: &AppHandle) -> Result<(), SpotlightError> {
+    let png_bytes = capture_full_display_png().map_err(SpotlightError::Capture)?;
+    let image = tauri::image::Image::from_bytes(&png_bytes).map_err(|e| {
+        SpotlightError::Capture(format!("Failed to decode captured image: {}", e))
+    })?;
+    app.clipboard().write_image(&image).map_err(|e| {
+        SpotlightError::Platform(format!("Clipboard rejected image data: {}", e))
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn capture_screen_without_overlay_mac(window: &tauri::Window) -> Result<Vec<u8>, String> {
+    use core_foundation::array::CFArray;
+    use core_graphics::window::{
+        create_image, create_image_from_array, kCGWindowImageDefault,
+        kCGWindowListOptionOnScreenBelowWindow,
+    };
+    use objc::runtime::Object;
+
+    let ns_window_ptr = window
+        .ns_window()
+        .map_err(|e| format!("Failed to access native window: {}", e))?;
+    let ns_window = ns_window_ptr as *mut Object;
+
+    #[allow(unexpected_cfgs)]
+    let window_number: u32 = unsafe { msg_send![ns_window, windowNumber] };
+
+    let bounds = CGDisplay::main().bounds();
+    let patterns = stored_excluded_windows(window.app_handle());
+    let included = if patterns.is_empty() {
+        None
+    } else {
+        windows_below_except_excluded(window_number, &patterns)
+    };
+    let cg_image = match included {
+        // Composite only the windows that survived the exclusion list
+        Some(window_ids) => {
+            let window_ids: Vec<*const std::ffi::c_void> = window_ids
+                .into_iter()
+                .map(|id| id as usize as *const std::ffi::c_void)
+                .collect();
+            create_image_from_array(
+                bounds,
+                CFArray::from_copyable(&window_ids),
+                kCGWindowImageDefault,
+            )
+        }
+        None => create_image(
+            bounds,
+            kCGWindowListOptionOnScreenBelowWindow,
+            window_number,
+            kCGWindowImageDefault,
+        ),
+    }
+    .ok_or_else(|| "CGWindowListCreateImage returned null".to_string())?;
+
+    let width = cg_image.width() as usize;
+    let height = cg_image.height() as usize;
+    let bytes_per_row = cg_image.bytes_per_row() as usize;
+
+    let cf_data: CFData = cg_image.data();
+    let data: &[u8] = cf_data.as_ref();
+
+    if data.len() < bytes_per_row * height {
+        return Err("Unexpected pixel buffer length".to_string());
+    }
+
+    let mut rgba = vec![0u8; width * height * 4];
+    for y in 0..height {
+        let src_offset = y * bytes_per_row;
+        let dst_offset = y * width * 4;
+        let src_row = &data[src_offset..src_offset + width * 4];
+        let dst_row = &mut rgba[dst_offset..dst_offset + width * 4];
+
+        for (dst_px, src_px) in dst_row.chunks_exact_mut(4).zip(src_row.chunks_exact(4)) {
+            // Convert BGRA -> RGBA
+            dst_px[0] = src_px[2];
+            dst_px[1] = src_px[1];
+            dst_px[2] = src_px[0];
+            dst_px[3] = src_px[3];
+        }
+    }
+
+    encode_rgba_png(&rgba, width as u32, height as u32)
+}
+
+/// IDs of the on-screen windows below `window_number`, front to back, minus
+/// those matching `patterns`. `None` when nothing matched, so the caller can
+/// use the cheaper whole-screen path.
+#[cfg(target_os = "macos")]
+fn windows_below_except_excluded(window_number: u32, patterns: &[String]) -> Option<Vec<u32>> {
+    use core_foundation::base::{CFType, TCFType};
+    use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+    use core_foundation::number::CFNumber;
+    use core_foundation::string::CFString;
+    use core_graphics::window::{copy_window_info, kCGWindowListOptionOnScreenBelowWindow};
+
+    let windows = copy_window_info(kCGWindowListOptionOnScreenBelowWindow, window_number)?;
+    let mut included = Vec::new();
+    let mut excluded_any = false;
+    for entry in windows.iter() {
+        let info: CFDictionary<CFString, CFType> =
+            unsafe { CFDictionary::wrap_under_get_rule(*entry as CFDictionaryRef) };
+        let string = |key: &'static str| {
+            info.find(CFString::from_static_string(key))
+                .and_then(|value| value.downcast::<CFString>())
+                .map(|value| value.to_string())
+                .unwrap_or_default()
+        };
+        let Some(id) = info
+            .find(CFString::from_static_string("kCGWindowNumber"))
+            .and_then(|value| value.downcast::<CFNumber>())
+            .and_then(|number| number.to_i64())
+            .and_then(|id| u32::try_from(id).ok())
+        else {
+            continue;
+        };
+        let (app_name, title) = (string("kCGWindowOwnerName"), string("kCGWindowName"));
+        if is_window_excluded(patterns, &app_name, &title) {
+            info!("Excluding window '{}' of {} from capture", title, app_name);
+            excluded_any = true;
+        } else {
+            included.push(id);
+        }
+    }
+    excluded_any.then_some(included)
+}
+
+#[cfg(target_os = "windows")]
+fn capture_screen_without_overlay_windows(window: &tauri::Window) -> Result<Vec<u8>, String> {
+    use std::thread;
+
+    let was_visible = window
+        .is_visible()
+        .map_err(|e| format!("Failed to determine window visibility: {}", e))?;
+
+    if was_visible {
+        window
+            .hide()
+            .map_err(|e| format!("Failed to hide window before capture: {}", e))?;
+        // Reduced delay for better UX - modern compositors are fast
+        thread::sleep(Duration::from_millis(80));
+    }
+
+    let patterns = stored_excluded_windows(window.app_handle());
+    let capture_result = capture_full_display_png().and_then(|png_bytes| {
+        if patterns.is_empty() {
+            Ok(png_bytes)
+        } else {
+            mask_excluded_windows(png_bytes, &patterns)
+        }
+    });
+
+    if was_visible {
+        if let Err(err) = window.show() {
+            error!("Failed to restore window visibility after capture: {}", err);
+        } else {
+            // Reduced delay - window redraws quickly on modern systems
+            thread::sleep(Duration::from_millis(30));
+        }
+
+        if let Err(err) = window.set_focus() {
+            error!("Failed to refocus window after capture: {}", err);
+        }
+    }
+
+    capture_result
+}
+
+/// Blacks out visible windows matching `patterns` on a capture of the first
+/// display. Windows can't be left out of another process's capture, so the
+/// regions they cover are masked instead.
+#[cfg(target_os = "windows")]
+fn mask_excluded_windows(png_bytes: Vec<u8>, patterns: &[String]) -> Result<Vec<u8>, String> {
+    let excluded = excluded_window_rects(patterns);
+    if excluded.is_empty() {
+        return Ok(png_bytes);
+    }
+    let screens = Screen::all().map_err(|e| e.to_string())?;
+    let display = &screens.first().ok_or("No screens found")?.display_info;
+    let mut image = image::load_from_memory_with_format(&png_bytes, image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to decode capture: {}", e))?
+        .to_rgba8();
+    if !mask_window_rects(&mut image, &excluded, (display.x, display.y), display.width)? {
+        return Ok(png_bytes);
+    }
+    let (width, height) = image.dimensions();
+    encode_rgba_png(image.as_raw(), width, height)
+}
+
+/// Blacks out the desktop rects in `excluded` on a capture of the display at
+/// `origin` that is `display_width` desktop units wide. Returns whether any
+/// of them overlapped the display.
+fn mask_window_rects(
+    image: &mut image::RgbaImage,
+    excluded: &[(i32, i32, i32, i32)],
+    origin: (i32, i32),
+    display_width: u32,
+) -> Result<bool, String> {
+    let scale = f64::from(image.width()) / f64::from(display_width.max(1));
+    let regions: Vec<Rect> = excluded
+        .iter()
+        .filter_map(|&rect| window_rect_in_capture(rect, origin, scale, image.dimensions()))
+        .collect();
+    if regions.is_empty() {
+        return Ok(false);
+    }
+    redact_regions(image, &regions, RedactionStyle::Fill).map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// Desktop rects of the visible windows matching `patterns`. Windows aren't
+/// enumerated on Linux, so nothing is excluded there.
+fn excluded_window_rects(patterns: &[String]) -> Vec<(i32, i32, i32, i32)> {
+    if patterns.is_empty() {
+        return Vec::new();
+    }
+    #[cfg(any(target_os = "macos", target_os = "windows"))]
+    let windows = visible_windows();
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    let windows: Vec<DesktopWindow> = Vec::new();
+
+    windows
+        .into_iter()
+        .filter(|window| is_window_excluded(patterns, &window.app_name, &window.title))
+        .map(|window| {
+            info!(
+                "Masking window '{}' of {} in capture",
+                window.title, window.app_name
+            );
+            window.rect
+        })
+        .collect()
+}
+
+#[cfg_attr(not(any(target_os = "macos", target_os = "windows")), allow(dead_code))]
+struct DesktopWindow {
+    app_name: String,
+    title: String,
+    /// `(left, top, right, bottom)` in desktop units: pixels on Windows,
+    /// points on macOS.
+    rect: (i32, i32, i32, i32),
+}
+
+/// On-screen windows with their owning app's name, bounds in global points.
+#[cfg(target_os = "macos")]
+fn visible_windows() -> Vec<DesktopWindow> {
+    use core_foundation::base::{CFType, TCFType};
+    use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+    use core_foundation::number::CFNumber;
+    use core_foundation::string::CFString;
+    use core_graphics::window::{
+        copy_window_info, kCGNullWindowID, kCGWindowListExcludeDesktopElements,
+        kCGWindowListOptionOnScreenOnly,
+    };
+
+    let Some(windows) = copy_window_info(
+        kCGWindowListOptionOnScreenOnly | kCGWindowListExcludeDesktopElements,
+        kCGNullWindowID,
+    ) else {
+        return Vec::new();
+    };
+    windows
+        .iter()
+        .filter_map(|entry| {
+            let info: CFDictionary<CFString, CFType> =
+                unsafe { CFDictionary::wrap_under_get_rule(*entry as CFDictionaryRef) };
+            let string = |key: &'static str| {
+                info.find(CFString::from_static_string(key))
+                    .and_then(|value| value.downcast::<CFString>())
+                    .map(|value| value.to_string())
+                    .unwrap_or_default()
+            };
+            let bounds = info
+                .find(CFString::from_static_string("kCGWindowBounds"))
+                .and_then(|value| value.downcast::<CFDictionary>())?;
+            let bounds: CFDictionary<CFString, CFNumber> =
+                unsafe { CFDictionary::wrap_under_get_rule(bounds.as_concrete_TypeRef()) };
+            let number = |key: &'static str| {
+                bounds
+                    .find(CFString::from_static_string(key))
+                    .and_then(|value| value.to_f64())
+                    .map(|value| value.round() as i32)
+            };
+            let (x, y) = (number("X")?, number("Y")?);
+            let (width, height) = (number("Width")?, number("Height")?);
+            Some(DesktopWindow {
+                app_name: string("kCGWindowOwnerName"),
+                title: string("kCGWindowName"),
+                rect: (x, y, x + width, y + height),
+            })
+        })
+        .collect()
+}
+
+/// Visible, non-minimized top-level windows with their owning executable's name.
+#[cfg(target_os = "windows")]
+fn visible_windows() -> Vec<DesktopWindow> {
+    use windows_sys::Win32::Foundation::{CloseHandle, BOOL, HWND, LPARAM, RECT};
+    use windows_sys::Win32::System::Threading::{
+        OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32,
+        PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        EnumWindows, GetWindowRect, GetWindowTextLengthW, GetWindowTextW, GetWindowThreadProcessId,
+        IsIconic, IsWindowVisible,
+    };
+
+    unsafe fn process_name(hwnd: HWND) -> String {
+        let mut pid = 0u32;
+        GetWindowThreadProcessId(hwnd, &mut pid);
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process.is_null() {
+            return String::new();
+        }
+        let mut buffer = [0u16; 1024];
+        let mut len = buffer.len() as u32;
+        let ok =
+            QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, buffer.as_mut_ptr(), &mut len);
+        CloseHandle(process);
+        if ok == 0 {
+            return String::new();
+        }
+        let path = String::from_utf16_lossy(&buffer[..len as usize]);
+        std::path::Path::new(&path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    unsafe extern "system" fn collect(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let windows = &mut *(lparam as *mut Vec<DesktopWindow>);
+        if IsWindowVisible(hwnd) == 0 || IsIconic(hwnd) != 0 {
+            return 1;
+        }
+        let mut rect = RECT {
+            left: 0,
+            top: 0,
+            right: 0,
+            bottom: 0,
+        };
+        if GetWindowRect(hwnd, &mut rect) == 0 {
+            return 1;
+        }
+        let mut title = vec![0u16; GetWindowTextLengthW(hwnd).max(0) as usize + 1];
+        let len = GetWindowTextW(hwnd, title.as_mut_ptr(), title.len() as i32);
+        windows.push(DesktopWindow {
+            app_name: process_name(hwnd),
+            title: String::from_utf16_lossy(&title[..len.max(0) as usize]),
+            rect: (rect.left, rect.top, rect.right, rect.bottom),
+        });
+        1
+    }
+
+    let mut windows: Vec<DesktopWindow> = Vec::new();
+    unsafe {
+        EnumWindows(
+            Some(collect),
+            &mut windows as *mut Vec<DesktopWindow> as LPARAM,
+        );
+    }
+    windows
+}
+
+#[derive(Serialize, Deserialize)]
+struct GeminiPart {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inline_data: Option<InlineData>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct InlineData {
+    /// Also accepts `mimeType`, the casing the frontend uses elsewhere.
+    #[serde(alias = "mimeType")]
+    mime_type: String,
+    data: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GeminiContent {
+    role: String,
+    parts: Vec<GeminiPart>,
+}
+
+fn default_image_mime_type() -> String {
+    "image/png".to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ImageInput {
+    #[serde(default = "default_image_mime_type")]
+    mime_type: String,
+    data: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ChatMessage {
+    role: String,
+    content: String,
+    #[serde(default)]
+    images: Vec<ImageInput>,
+    /// Copied from the `GeminiResult` that produced an answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    #[serde(rename = "createdAt", default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<String>,
+    /// The toggles a user turn was sent with, reused when it is regenerated
+    /// or edited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    options: Option<TurnOptions>,
+}
+
+/// Per-request toggles worth replaying with a user turn.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct TurnOptions {
+    grounding_enabled: bool,
+    dynamic_threshold: Option<f32>,
+    thinking_enabled: Option<bool>,
+    code_execution_enabled: bool,
+    url_context_enabled: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GoogleSearch {}
+
+/// Search tool that only grounds when the model's predicted benefit of
+/// searching reaches `dynamic_threshold`.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleSearchRetrieval {
+    dynamic_retrieval_config: DynamicRetrievalConfig,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DynamicRetrievalConfig {
+    mode: String,
+    dynamic_threshold: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UrlContext {}
+
+#[derive(Serialize, Deserialize)]
+struct CodeExecution {}
+
+#[derive(Serialize, Deserialize)]
+struct Tool {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    google_search: Option<GoogleSearch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    google_search_retrieval: Option<GoogleSearchRetrieval>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url_context: Option<UrlContext>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code_execution: Option<CodeExecution>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ThinkingConfig {
+    #[serde(rename = "thinkingBudget")]
+    thinking_budget: i32,
+    #[serde(rename = "includeThoughts")]
+    include_thoughts: bool,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct GenerationConfig {
+    #[serde(rename = "thinkingConfig", skip_serializing_if = "Option::is_none")]
+    thinking_config: Option<ThinkingConfig>,
+    #[serde(rename = "responseMimeType", skip_serializing_if = "Option::is_none")]
+    response_mime_type: Option<String>,
+    #[serde(rename = "responseSchema", skip_serializing_if = "Option::is_none")]
+    response_schema: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(rename = "stopSequences", skip_serializing_if = "Vec::is_empty")]
+    stop_sequences: Vec<String>,
+    #[serde(rename = "candidateCount", skip_serializing_if = "Option::is_none")]
+    candidate_count: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i32>,
+    #[serde(rename = "presencePenalty", skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(rename = "frequencyPenalty", skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+}
+
+impl GenerationConfig {
+    fn is_empty(&self) -> bool {
+        self.thinking_config.is_none()
+            && self.response_mime_type.is_none()
+            && self.response_schema.is_none()
+            && self.temperature.is_none()
+            && self.stop_sequences.is_empty()
+            && self.candidate_count.is_none()
+            && self.seed.is_none()
+            && self.presence_penalty.is_none()
+            && self.frequency_penalty.is_none()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SystemInstruction {
+    parts: Vec<GeminiPart>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GeminiRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     system_instruction: Option<SystemInstruction>,
     contents: Vec<GeminiContent>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tools: Option<Vec<Tool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    #[serde(rename = "generationConfig")]
-    generation_config: Option<GenerationConfig>,
+    #[serde(rename = "generationConfig")]
+    generation_config: Option<GenerationConfig>,
+    #[serde(rename = "cachedContent", skip_serializing_if = "Option::is_none")]
+    cached_content: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CachedContentRequest {
+    model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<SystemInstruction>,
+    contents: Vec<GeminiContent>,
+    /// Duration in the API's `"<seconds>s"` form.
+    ttl: String,
+}
+
+#[derive(Deserialize)]
+struct CachedContentResponse {
+    name: String,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct WebInfo {
+    uri: Option<String>,
+    title: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct GroundingChunk {
+    web: Option<WebInfo>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct Segment {
+    #[serde(rename = "startIndex", default)]
+    start_index: usize,
+    #[serde(rename = "endIndex", default)]
+    end_index: usize,
+    text: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct GroundingSupport {
+    segment: Option<Segment>,
+    #[serde(rename = "groundingChunkIndices")]
+    grounding_chunk_indices: Option<Vec<usize>>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+struct GroundingMetadata {
+    #[serde(rename = "groundingChunks")]
+    grounding_chunks: Option<Vec<GroundingChunk>>,
+    #[serde(rename = "groundingSupports")]
+    grounding_supports: Option<Vec<GroundingSupport>>,
+    #[serde(rename = "webSearchQueries")]
+    web_search_queries: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct PromptFeedback {
+    #[serde(rename = "blockReason")]
+    block_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GeminiResponse {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    #[serde(rename = "groundingMetadata")]
+    grounding_metadata: Option<GroundingMetadata>,
+    #[serde(rename = "promptFeedback")]
+    prompt_feedback: Option<PromptFeedback>,
+    #[serde(rename = "usageMetadata")]
+    usage_metadata: Option<UsageMetadata>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    total_token_count: usize,
+}
+
+#[derive(Deserialize)]
+struct Candidate {
+    #[serde(default)]
+    content: Content,
+    #[serde(rename = "groundingMetadata")]
+    grounding_metadata: Option<GroundingMetadata>,
+    #[serde(rename = "finishReason")]
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct Content {
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Deserialize)]
+struct ExecutableCode {
+    #[serde(default)]
+    language: String,
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct CodeExecutionResult {
+    #[serde(default)]
+    outcome: String,
+    output: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Part {
+    #[serde(default)]
+    text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    thought: Option<bool>,
+    #[serde(rename = "executableCode")]
+    executable_code: Option<ExecutableCode>,
+    #[serde(rename = "codeExecutionResult")]
+    code_execution_result: Option<CodeExecutionResult>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct SourceInfo {
+    title: String,
+    uri: String,
+}
+
+/// A [`SourceInfo`] with an id that stays the same for the same page, even
+/// across responses; see [`source_id`].
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct IdentifiedSource {
+    id: String,
+    #[serde(flatten)]
+    source: SourceInfo,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SourcesReadyPayload {
+    sources: Vec<IdentifiedSource>,
+}
+
+/// A span of the response text (UTF-8 byte offsets) backed by entries in
+/// `GeminiResult::sources`.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct Citation {
+    start_index: usize,
+    end_index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    source_indices: Vec<usize>,
+}
+
+/// Code the model ran with the code-execution tool, paired with its result.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CodeBlock {
+    language: String,
+    code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outcome: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+}
+
+/// Toggle states applied when a request doesn't set them itself.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DefaultToggles {
+    grounding: bool,
+    /// `None` leaves thinking at the model's own default.
+    thinking: Option<bool>,
+}
+
+/// One `batch_ask` answer; exactly one of the fields is set.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchAnswer {
+    result: Option<GeminiResult>,
+    error: Option<SpotlightError>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiResult {
+    text: String,
+    sources: Option<Vec<SourceInfo>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    citations: Option<Vec<Citation>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code_blocks: Option<Vec<CodeBlock>>,
+    /// Set when the text is structured output (e.g. raw JSON) rather than markdown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<String>,
+    model: String,
+    /// RFC 3339 time the response arrived.
+    created_at: String,
+    /// Prompt plus response tokens, when the backend reports usage.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_tokens: Option<usize>,
+    /// Every candidate's answer when more than one was requested; `text` is
+    /// always the first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    candidates: Option<Vec<String>>,
+    /// The unsanitized `text`, set when output sanitization is enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw_text: Option<String>,
+    /// What Google Search was asked on the model's behalf, for grounded answers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    search_queries: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct OpenAiRequest {
+    model: String,
+    messages: Vec<OpenAiMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stop: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+}
+
+#[derive(Serialize)]
+struct OpenAiMessage {
+    role: String,
+    content: OpenAiContent,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum OpenAiContent {
+    Text(String),
+    Parts(Vec<OpenAiContentPart>),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OpenAiContentPart {
+    Text { text: String },
+    ImageUrl { image_url: OpenAiImageUrl },
+}
+
+#[derive(Serialize)]
+struct OpenAiImageUrl {
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct OpenAiResponse {
+    #[serde(default)]
+    choices: Vec<OpenAiChoice>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChoice {
+    message: OpenAiResponseMessage,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiResponseMessage {
+    content: Option<String>,
+}
+
+#[derive(Serialize)]
+struct OllamaRequest {
+    model: String,
+    messages: Vec<OllamaMessage>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+}
+
+#[derive(Serialize)]
+struct OllamaOptions {
+    temperature: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OllamaMessage {
+    #[serde(default)]
+    role: String,
+    #[serde(default)]
+    content: String,
+    /// Base64 images without a data-URL prefix, for vision models like llava.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+}
+
+/// One line of Ollama's newline-delimited streaming response.
+#[derive(Deserialize)]
+struct OllamaChunk {
+    #[serde(default)]
+    message: Option<OllamaMessage>,
+    #[serde(default)]
+    done: bool,
+    error: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ModelInfo {
+    name: String,
+    #[serde(default)]
+    display_name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    supported_generation_methods: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ModelListResponse {
+    #[serde(default)]
+    models: Vec<ModelInfo>,
+    next_page_token: Option<String>,
+}
+
+/// Starts an authenticated Generative Language API request. The key travels in
+/// the `x-goog-api-key` header so it never appears in URLs, proxy logs or
+/// request errors; every Gemini call should go through here. `headers` are the
+/// user's custom headers for proxies and gateways.
+fn gemini_post(
+    client: &reqwest::Client,
+    url: &str,
+    api_key: &str,
+    headers: &HeaderMap,
+) -> reqwest::RequestBuilder {
+    client
+        .post(url)
+        .headers(headers.clone())
+        .header(GEMINI_API_KEY_HEADER, api_key.trim())
+}
+
+/// GET counterpart of [`gemini_post`].
+fn gemini_get(
+    client: &reqwest::Client,
+    url: &str,
+    api_key: &str,
+    headers: &HeaderMap,
+) -> reqwest::RequestBuilder {
+    client
+        .get(url)
+        .headers(headers.clone())
+        .header(GEMINI_API_KEY_HEADER, api_key.trim())
+}
+
+/// Validates user-supplied headers: names and values must be ASCII and valid
+/// HTTP, and restricted headers are refused.
+fn parse_custom_headers(headers: &BTreeMap<String, String>) -> Result<HeaderMap, SpotlightError> {
+    let mut parsed = HeaderMap::new();
+    for (name, value) in headers {
+        let name = name.trim();
+        if !name.is_ascii() || !value.is_ascii() {
+            return Err(SpotlightError::InvalidInput(format!(
+                "Header '{}' must use ASCII characters only",
+                name
+            )));
+        }
+        if RESTRICTED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+            return Err(SpotlightError::InvalidInput(format!(
+                "Header '{}' cannot be overridden",
+                name
+            )));
+        }
+        let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| {
+            SpotlightError::InvalidInput(format!("Invalid header name '{}': {}", name, e))
+        })?;
+        let header_value = HeaderValue::from_str(value.trim()).map_err(|e| {
+            SpotlightError::InvalidInput(format!("Invalid value for header '{}': {}", name, e))
+        })?;
+        parsed.insert(header_name, header_value);
+    }
+    Ok(parsed)
+}
+
+/// Accepts versions shaped like `v1`, `v1beta` or `v2alpha`.
+fn is_valid_api_version(version: &str) -> bool {
+    let Some(rest) = version.strip_prefix('v') else {
+        return false;
+    };
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    digits > 0 && matches!(&rest[digits..], "" | "alpha" | "beta")
+}
+
+/// Per-user settings applied to every Generative Language API call.
+struct GeminiSettings {
+    api_version: String,
+    headers: HeaderMap,
+}
+
+impl GeminiSettings {
+    /// Builds settings from saved JSON values, falling back to defaults for
+    /// anything missing or invalid.
+    fn from_json(
+        api_version: Option<&serde_json::Value>,
+        headers: Option<&serde_json::Value>,
+    ) -> Self {
+        let api_version = api_version
+            .and_then(|json| json.as_str())
+            .filter(|version| is_valid_api_version(version))
+            .unwrap_or(DEFAULT_GEMINI_API_VERSION)
+            .to_string();
+        let headers = headers
+            .and_then(|json| serde_json::from_value::<BTreeMap<String, String>>(json.clone()).ok())
+            .unwrap_or_default();
+        let headers = parse_custom_headers(&headers).unwrap_or_else(|err| {
+            error!("Ignoring saved custom headers: {err}");
+            HeaderMap::new()
+        });
+        GeminiSettings {
+            api_version,
+            headers,
+        }
+    }
+
+    /// Full URL of `path` under the configured API version, e.g. `models`.
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/{}/{}", GEMINI_API_BASE_URL, self.api_version, path)
+    }
+}
+
+fn stored_gemini_settings(app: &AppHandle) -> GeminiSettings {
+    let store = settings_store(app).ok();
+    let api_version = store.as_ref().and_then(|store| store.get(API_VERSION_KEY));
+    let headers = store.as_ref().and_then(|store| store.get(CUSTOM_HEADERS_KEY));
+    GeminiSettings::from_json(api_version.as_ref(), headers.as_ref())
+}
+
+#[tauri::command]
+fn get_api_version(app: AppHandle) -> Result<String, SpotlightError> {
+    Ok(stored_gemini_settings(&app).api_version)
+}
+
+#[tauri::command]
+fn set_api_version(app: AppHandle, version: String) -> Result<(), SpotlightError> {
+    let version = version.trim();
+    if !is_valid_api_version(version) {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Invalid API version '{}': expected something like v1, v1beta or v1alpha",
+            version
+        )));
+    }
+    let store = settings_store(&app)?;
+    store.set(API_VERSION_KEY, version);
+    store.save().map_err(SpotlightError::from)
+}
+
+#[tauri::command]
+fn get_custom_headers(app: AppHandle) -> Result<BTreeMap<String, String>, SpotlightError> {
+    let store = settings_store(&app)?;
+    Ok(store
+        .get(CUSTOM_HEADERS_KEY)
+        .and_then(|json| serde_json::from_value(json).ok())
+        .unwrap_or_default())
+}
+
+#[tauri::command]
+fn set_custom_headers(
+    app: AppHandle,
+    headers: BTreeMap<String, String>,
+) -> Result<(), SpotlightError> {
+    parse_custom_headers(&headers)?;
+    let headers: BTreeMap<String, String> = headers
+        .into_iter()
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let headers_json = serde_json::to_value(&headers)
+        .map_err(|e| SpotlightError::Store(format!("Failed to serialize headers: {}", e)))?;
+    let store = settings_store(&app)?;
+    store.set(CUSTOM_HEADERS_KEY, headers_json);
+    store.save().map_err(SpotlightError::from)
+}
+
+async fn fetch_generate_content_models(
+    api_key: &str,
+    gemini: &GeminiSettings,
+) -> Result<Vec<ModelInfo>, SpotlightError> {
+    let client = reqwest::Client::new();
+    let url = gemini.endpoint("models");
+    let mut models = Vec::new();
+    let mut page_token: Option<String> = None;
+    loop {
+        let mut request = gemini_get(&client, &url, api_key, &gemini.headers)
+            .query(&[("pageSize", GEMINI_MODELS_PAGE_SIZE.to_string())]);
+        if let Some(token) = &page_token {
+            request = request.query(&[("pageToken", token)]);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(classify_api_error(status, error_text));
+        }
+        let page: ModelListResponse = response.json().await?;
+        models.extend(page.models.into_iter().filter(|model| {
+            model
+                .supported_generation_methods
+                .iter()
+                .any(|method| method == GENERATE_CONTENT_METHOD)
+        }));
+        page_token = page.next_page_token.filter(|token| !token.is_empty());
+        if page_token.is_none() {
+            break;
+        }
+    }
+    debug!("Fetched {} models supporting generateContent", models.len());
+    Ok(models)
+}
+
+fn classify_api_error(status: reqwest::StatusCode, body: String) -> SpotlightError {
+    let invalid_key = body.contains("API_KEY_INVALID") || body.contains("API key not valid");
+    match status.as_u16() {
+        401 | 403 => SpotlightError::AuthFailed(body),
+        400 if invalid_key => SpotlightError::AuthFailed(body),
+        429 => SpotlightError::RateLimited(body),
+        408 | 504 => SpotlightError::Timeout(body),
+        _ => SpotlightError::Api(format!("{}: {}", status, body)),
+    }
+}
+
+fn parse_http_url(uri: &str) -> Result<reqwest::Url, SpotlightError> {
+    let url = reqwest::Url::parse(uri.trim())
+        .map_err(|e| SpotlightError::InvalidInput(format!("Invalid URL '{}': {}", uri, e)))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Unsupported URL scheme '{}' in '{}'",
+            url.scheme(),
+            uri
+        )));
+    }
+    Ok(url)
+}
+
+fn estimate_text_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(ESTIMATED_CHARS_PER_TOKEN)
+}
+
+fn estimate_message_tokens(message: &ChatMessage) -> usize {
+    estimate_text_tokens(&message.content) + message.images.len() * ESTIMATED_TOKENS_PER_IMAGE
+}
+
+/// Drops the oldest history messages until the estimated prompt size fits the
+/// budget, always keeping the most recent turns. Returns how many were dropped.
+fn trim_history_to_budget(
+    history: &mut Vec<ChatMessage>,
+    reserved_tokens: usize,
+    token_budget: usize,
+) -> usize {
+    let mut total_tokens =
+        reserved_tokens + history.iter().map(estimate_message_tokens).sum::<usize>();
+    let mut dropped = 0;
+    while total_tokens > token_budget && history.len() - dropped > MIN_RETAINED_HISTORY_MESSAGES {
+        total_tokens -= estimate_message_tokens(&history[dropped]);
+        dropped += 1;
+    }
+    // Never start the trimmed conversation on a model turn
+    while dropped > 0 && dropped < history.len() && history[dropped].role != "user" {
+        dropped += 1;
+    }
+    history.drain(..dropped);
+    dropped
+}
+
+/// Approximate wire size of a request; base64 image data dominates, so the
+/// JSON framing around each field is ignored.
+fn estimate_request_bytes(request: &ChatRequest) -> usize {
+    let history_bytes: usize = request
+        .chat_history
+        .iter()
+        .map(|msg| {
+            msg.content.len() + msg.images.iter().map(|image| image.data.len()).sum::<usize>()
+        })
+        .sum();
+    history_bytes
+        + request.message.len()
+        + request
+            .images
+            .iter()
+            .map(|image| image.data.len())
+            .sum::<usize>()
+        + request.audio.as_ref().map_or(0, |audio| audio.data.len())
+        + request
+            .document
+            .as_ref()
+            .map_or(0, |document| document.data.len())
+        + request.system_instructions.as_ref().map_or(0, String::len)
+}
+
+/// Fails fast with `PayloadTooLarge` instead of waiting for the API to reject
+/// an oversized request.
+fn ensure_request_fits(app: &AppHandle, request: &ChatRequest) -> Result<(), SpotlightError> {
+    let limit = stored_max_request_bytes(app);
+    let size = estimate_request_bytes(request);
+    if size > limit {
+        return Err(SpotlightError::PayloadTooLarge { size, limit });
+    }
+    Ok(())
+}
+
+fn stored_history_token_budget(app: &AppHandle) -> usize {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(HISTORY_TOKEN_BUDGET_KEY))
+        .and_then(|json| json.as_u64())
+        .map(|budget| budget as usize)
+        .unwrap_or(DEFAULT_HISTORY_TOKEN_BUDGET)
+}
+
+#[tauri::command]
+fn set_history_token_budget(app: AppHandle, budget: usize) -> Result<(), SpotlightError> {
+    if budget == 0 {
+        return Err(SpotlightError::InvalidInput(
+            "History token budget must be greater than zero".to_string(),
+        ));
+    }
+    let store = settings_store(&app)?;
+    store.set(HISTORY_TOKEN_BUDGET_KEY, budget);
+    store.save().map_err(SpotlightError::from)
+}
+
+fn stored_max_request_bytes(app: &AppHandle) -> usize {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(MAX_REQUEST_BYTES_KEY))
+        .and_then(|json| json.as_u64())
+        .map(|limit| limit as usize)
+        .unwrap_or(DEFAULT_MAX_REQUEST_BYTES)
+}
+
+#[tauri::command]
+fn set_max_request_bytes(app: AppHandle, limit: usize) -> Result<(), SpotlightError> {
+    if limit == 0 {
+        return Err(SpotlightError::InvalidInput(
+            "Maximum request size must be greater than zero".to_string(),
+        ));
+    }
+    let store = settings_store(&app)?;
+    store.set(MAX_REQUEST_BYTES_KEY, limit);
+    store.save().map_err(SpotlightError::from)
+}
+
+/// Instruction sent with an image when the user typed nothing; empty sends the
+/// image on its own.
+fn stored_image_only_prompt(app: &AppHandle) -> String {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(IMAGE_ONLY_PROMPT_KEY))
+        .and_then(|json| json.as_str().map(|s| s.to_string()))
+        .unwrap_or_else(|| DEFAULT_IMAGE_ONLY_PROMPT.to_string())
+}
+
+#[tauri::command]
+fn set_image_only_prompt(app: AppHandle, prompt: String) -> Result<(), SpotlightError> {
+    let store = settings_store(&app)?;
+    store.set(IMAGE_ONLY_PROMPT_KEY, prompt.trim());
+    store.save().map_err(SpotlightError::from)
+}
+
+fn stored_context_warning_threshold(app: &AppHandle) -> f64 {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(CONTEXT_WARNING_THRESHOLD_KEY))
+        .and_then(|json| json.as_f64())
+        .unwrap_or(DEFAULT_CONTEXT_WARNING_THRESHOLD)
+}
+
+#[tauri::command]
+fn set_context_warning_threshold(app: AppHandle, threshold: f64) -> Result<(), SpotlightError> {
+    if !(threshold > 0.0 && threshold <= 1.0) {
+        return Err(SpotlightError::InvalidInput(
+            "Context warning threshold must be between 0 and 1".to_string(),
+        ));
+    }
+    let store = settings_store(&app)?;
+    store.set(CONTEXT_WARNING_THRESHOLD_KEY, threshold);
+    store.save().map_err(SpotlightError::from)
+}
+
+/// Emits `context-warning` once the conversation uses the configured share of
+/// the usable context: the model's window, or the history token budget when
+/// that is smaller since older turns are trimmed beyond it.
+fn warn_if_near_context_limit(
+    app: &AppHandle,
+    backend: &Backend,
+    used_tokens: usize,
+    token_budget: usize,
+) {
+    let limit_tokens = match backend {
+        Backend::Gemini => GEMINI_CONTEXT_WINDOW_TOKENS.min(token_budget),
+        _ => token_budget,
+    };
+    let threshold = stored_context_warning_threshold(app);
+    if (used_tokens as f64) < limit_tokens as f64 * threshold {
+        return;
+    }
+    debug!("Conversation uses {} of {} tokens", used_tokens, limit_tokens);
+    let payload = ContextWarningPayload {
+        used_tokens,
+        limit_tokens,
+    };
+    if let Err(err) = app.emit(CONTEXT_WARNING_EVENT, payload) {
+        error!("Failed to emit context warning event: {err}");
+    }
+}
+
+/// Remembers the sources of a grounded answer and announces them with ids
+/// the frontend can pass to `open_source_by_id`.
+fn emit_sources_ready(app: &AppHandle, sources: &[SourceInfo]) {
+    if sources.is_empty() {
+        return;
+    }
+    let sources: Vec<IdentifiedSource> = sources
+        .iter()
+        .map(|source| IdentifiedSource {
+            id: source_id(&source.uri),
+            source: source.clone(),
+        })
+        .collect();
+    if let Some(state) = app.try_state::<SourceRegistryState>() {
+        match state.uris.lock() {
+            Ok(mut uris) => uris.extend(
+                sources
+                    .iter()
+                    .map(|source| (source.id.clone(), source.source.uri.clone())),
+            ),
+            Err(_) => error!("Source registry is poisoned"),
+        }
+    }
+    if let Err(err) = app.emit(SOURCES_READY_EVENT, SourcesReadyPayload { sources }) {
+        error!("Failed to emit sources ready event: {err}");
+    }
+}
+
+/// A stable id for a source: an FNV-1a hash of its normalized URI, so the
+/// same page keeps its id across responses and app restarts.
+fn source_id(uri: &str) -> String {
+    let hash = normalize_source_uri(uri)
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        });
+    format!("src-{:016x}", hash)
+}
+
+/// Normalizes a source URI for duplicate detection by lowercasing the scheme
+/// and host and dropping trailing slashes.
+fn normalize_source_uri(uri: &str) -> String {
+    let trimmed = uri.trim().trim_end_matches('/');
+    match trimmed.split_once("://") {
+        Some((scheme, rest)) => {
+            let host_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+            let (host, path) = rest.split_at(host_end);
+            format!("{}://{}{}", scheme.to_lowercase(), host.to_lowercase(), path)
+        }
+        None => trimmed.to_string(),
+    }
+}
+
+/// Strips HTML that could run in the webview from model output while leaving
+/// fenced and inline code untouched, so code that mentions `<script>` still
+/// renders as written. Markdown formatting is not HTML and passes through.
+fn sanitize_markdown(text: &str) -> String {
+    let mut sanitized = String::with_capacity(text.len());
+    let mut prose = String::new();
+    let mut fence: Option<&str> = None;
+    for line in text.split_inclusive('\n') {
+        let marker = ["```", "~~~"]
+            .into_iter()
+            .find(|marker| line.trim_start().starts_with(marker));
+        match (fence, marker) {
+            (None, Some(marker)) => {
+                sanitized.push_str(&sanitize_prose(&prose));
+                prose.clear();
+                fence = Some(marker);
+                sanitized.push_str(line);
+            }
+            (Some(open), Some(marker)) if open == marker => {
+                fence = None;
+                sanitized.push_str(line);
+            }
+            (Some(_), _) => sanitized.push_str(line),
+            (None, None) => prose.push_str(line),
+        }
+    }
+    sanitized.push_str(&sanitize_prose(&prose));
+    sanitized
+}
+
+/// Sanitizes text outside code fences, copying inline code spans verbatim.
+fn sanitize_prose(text: &str) -> String {
+    let mut sanitized = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('`') {
+        let ticks = rest[start..].len() - rest[start..].trim_start_matches('`').len();
+        let delimiter = &rest[start..start + ticks];
+        let Some(close) = rest[start + ticks..].find(delimiter) else {
+            break;
+        };
+        let end = start + ticks + close + ticks;
+        sanitized.push_str(&sanitize_html(&rest[..start]));
+        sanitized.push_str(&rest[start..end]);
+        rest = &rest[end..];
+    }
+    sanitized.push_str(&sanitize_html(rest));
+    sanitized
+}
+
+/// Removes HTML tags and comments, dropping dangerous elements with their
+/// contents, and neutralizes script URLs in Markdown links. A `<` that does
+/// not start a tag (as in `a < b`) is kept.
+fn sanitize_html(text: &str) -> String {
+    let mut sanitized = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        sanitized.push_str(&rest[..start]);
+        let candidate = &rest[start..];
+        if let Some(comment) = candidate.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let is_tag = candidate[1..]
+            .trim_start_matches('/')
+            .starts_with(|c: char| c.is_ascii_alphabetic());
+        let Some(end) = candidate.find('>').filter(|_| is_tag) else {
+            sanitized.push('<');
+            rest = &candidate[1..];
+            continue;
+        };
+        let name: String = candidate[1..end]
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        rest = &candidate[end + 1..];
+        if !candidate.starts_with("</") && DANGEROUS_HTML_ELEMENTS.contains(&name.as_str()) {
+            let closing = format!("</{}", name);
+            rest = match rest.to_ascii_lowercase().find(&closing) {
+                Some(close) => rest[close..].find('>').map_or("", |gt| &rest[close + gt + 1..]),
+                None => "",
+            };
+        }
+    }
+    sanitized.push_str(rest);
+    neutralize_link_urls(&neutralize_link_definitions(&sanitized))
+}
+
+/// Points reference-style link definitions (`[1]: javascript:…`) with a
+/// script-capable scheme at `#` instead.
+fn neutralize_link_definitions(text: &str) -> String {
+    text.split_inclusive('\n')
+        .map(|line| {
+            let trimmed = line.trim_start();
+            let label_end = trimmed
+                .starts_with('[')
+                .then(|| trimmed.find("]:"))
+                .flatten();
+            let Some(label_end) = label_end else {
+                return line.to_string();
+            };
+            let target_start = line.len() - trimmed.len() + label_end + "]:".len();
+            let target = line[target_start..]
+                .trim_start()
+                .trim_start_matches('<')
+                .to_ascii_lowercase();
+            if DANGEROUS_URL_SCHEMES
+                .iter()
+                .any(|scheme| target.starts_with(scheme))
+            {
+                let newline = if line.ends_with('\n') { "\n" } else { "" };
+                format!("{} #{}", &line[..target_start], newline)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect()
+}
+
+/// Points Markdown links with a script-capable scheme at `#` instead.
+fn neutralize_link_urls(text: &str) -> String {
+    let mut neutralized = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("](") {
+        let (before, after) = rest.split_at(start + 2);
+        neutralized.push_str(before);
+        let target_end = after.find(')').unwrap_or(after.len());
+        let target = after[..target_end].trim_start().to_ascii_lowercase();
+        if DANGEROUS_URL_SCHEMES.iter().any(|scheme| target.starts_with(scheme)) {
+            neutralized.push('#');
+            rest = &after[target_end..];
+        } else {
+            rest = after;
+        }
+    }
+    neutralized.push_str(rest);
+    neutralized
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TtsSettings {
+    enabled: bool,
+    voice: Option<String>,
+    /// Words per minute; `None` uses the system voice's own rate.
+    rate: Option<u32>,
+}
+
+fn stored_tts_settings(app: &AppHandle) -> TtsSettings {
+    let store = settings_store(app).ok();
+    let get = |key: &str| store.as_ref().and_then(|store| store.get(key));
+    TtsSettings {
+        enabled: get(TTS_ENABLED_KEY)
+            .and_then(|json| json.as_bool())
+            .unwrap_or(false),
+        voice: get(TTS_VOICE_KEY).and_then(|json| json.as_str().map(|s| s.to_string())),
+        rate: get(TTS_RATE_KEY)
+            .and_then(|json| json.as_u64())
+            .and_then(|rate| u32::try_from(rate).ok()),
+    }
+}
+
+#[tauri::command]
+fn get_tts_settings(app: AppHandle) -> TtsSettings {
+    stored_tts_settings(&app)
+}
+
+#[tauri::command]
+fn set_tts_enabled(
+    app: AppHandle,
+    state: State<'_, SpeechState>,
+    enabled: bool,
+) -> Result<(), SpotlightError> {
+    let store = settings_store(&app)?;
+    store.set(TTS_ENABLED_KEY, enabled);
+    store.save()?;
+    if !enabled {
+        stop_speaking(state)?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn set_tts_voice(app: AppHandle, voice: Option<String>) -> Result<(), SpotlightError> {
+    let store = settings_store(&app)?;
+    match voice.as_deref().map(str::trim).filter(|voice| !voice.is_empty()) {
+        Some(voice) => store.set(TTS_VOICE_KEY, voice),
+        None => {
+            store.delete(TTS_VOICE_KEY);
+        }
+    }
+    store.save().map_err(SpotlightError::from)
+}
+
+#[tauri::command]
+fn set_tts_rate(app: AppHandle, rate: Option<u32>) -> Result<(), SpotlightError> {
+    let store = settings_store(&app)?;
+    match rate {
+        Some(rate) if !TTS_RATE_RANGE.contains(&rate) => {
+            return Err(SpotlightError::InvalidInput(format!(
+                "Speech rate must be between {} and {} words per minute",
+                TTS_RATE_RANGE.start(),
+                TTS_RATE_RANGE.end()
+            )));
+        }
+        Some(rate) => store.set(TTS_RATE_KEY, rate),
+        None => {
+            store.delete(TTS_RATE_KEY);
+        }
+    }
+    store.save().map_err(SpotlightError::from)
+}
+
+/// Synthesizers to try in order, each reading the text from stdin.
+#[cfg(target_os = "macos")]
+fn speech_commands(settings: &TtsSettings) -> Vec<std::process::Command> {
+    let mut command = std::process::Command::new("say");
+    if let Some(voice) = &settings.voice {
+        command.args(["-v", voice]);
+    }
+    if let Some(rate) = settings.rate {
+        command.args(["-r", &rate.to_string()]);
+    }
+    vec![command]
+}
+
+/// Synthesizers to try in order, each reading the text from stdin.
+#[cfg(target_os = "windows")]
+fn speech_commands(settings: &TtsSettings) -> Vec<std::process::Command> {
+    use std::os::windows::process::CommandExt;
+    const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+    // Settings go through the environment so a voice name is never parsed as script
+    const SCRIPT: &str = "Add-Type -AssemblyName System.Speech; \
+        $s = New-Object System.Speech.Synthesis.SpeechSynthesizer; \
+        if ($env:SPOTLIGHT_TTS_VOICE) { $s.SelectVoice($env:SPOTLIGHT_TTS_VOICE) }; \
+        if ($env:SPOTLIGHT_TTS_RATE) { $s.Rate = [int]$env:SPOTLIGHT_TTS_RATE }; \
+        $s.Speak([Console]::In.ReadToEnd())";
+
+    let mut command = std::process::Command::new("powershell");
+    command
+        .args(["-NoProfile", "-NonInteractive", "-Command", SCRIPT])
+        .creation_flags(CREATE_NO_WINDOW);
+    if let Some(voice) = &settings.voice {
+        command.env("SPOTLIGHT_TTS_VOICE", voice);
+    }
+    if let Some(rate) = settings.rate {
+        // SAPI rates run from -10 to 10 around roughly 175 words per minute
+        let sapi_rate = ((i64::from(rate) - 175) / 20).clamp(-10, 10);
+        command.env("SPOTLIGHT_TTS_RATE", sapi_rate.to_string());
+    }
+    vec![command]
+}
+
+/// Synthesizers to try in order, each reading the text from stdin.
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn speech_commands(settings: &TtsSettings) -> Vec<std::process::Command> {
+    ["espeak-ng", "espeak"]
+        .into_iter()
+        .map(|program| {
+            let mut command = std::process::Command::new(program);
+            command.arg("--stdin");
+            if let Some(voice) = &settings.voice {
+                command.args(["-v", voice]);
+            }
+            if let Some(rate) = settings.rate {
+                command.args(["-s", &rate.to_string()]);
+            }
+            command
+        })
+        .collect()
+}
+
+fn spawn_synthesizer(settings: &TtsSettings) -> Result<std::process::Child, SpotlightError> {
+    use std::process::Stdio;
+
+    let mut last_error = None;
+    for mut command in speech_commands(settings) {
+        let spawned = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match spawned {
+            Ok(child) => return Ok(child),
+            Err(err) => {
+                debug!("Speech synthesizer {:?} unavailable: {}", command.get_program(), err);
+                last_error = Some(err);
+            }
+        }
+    }
+    Err(SpotlightError::SpeechUnavailable(match last_error {
+        Some(err) => format!("no speech synthesizer could be started ({})", err),
+        None => "not supported on this platform".to_string(),
+    }))
+}
+
+fn stop_synthesizer(mut child: std::process::Child) {
+    // Already exited when kill fails, which is fine
+    let _ = child.kill();
+    if let Err(err) = child.wait() {
+        warn!("Failed to reap speech synthesizer: {}", err);
+    }
+}
+
+/// Reads `text` aloud with the system voice, interrupting anything already
+/// being spoken.
+#[tauri::command]
+fn speak(
+    app: AppHandle,
+    state: State<'_, SpeechState>,
+    text: String,
+) -> Result<(), SpotlightError> {
+    let settings = stored_tts_settings(&app);
+    if !settings.enabled {
+        return Err(SpotlightError::SpeechUnavailable(
+            "turn on text-to-speech in settings first".to_string(),
+        ));
+    }
+    if text.trim().is_empty() {
+        return Err(SpotlightError::InvalidInput(
+            "There is no text to read aloud".to_string(),
+        ));
+    }
+
+    let mut current = state
+        .child
+        .lock()
+        .map_err(|_| SpotlightError::Platform("Speech state is poisoned".to_string()))?;
+    if let Some(previous) = current.take() {
+        stop_synthesizer(previous);
+    }
+    let mut child = spawn_synthesizer(&settings)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Written off-thread so a long answer cannot block on a full pipe;
+        // dropping stdin afterwards tells the synthesizer the text is complete
+        std::thread::spawn(move || {
+            use std::io::Write;
+            if let Err(err) = stdin.write_all(text.as_bytes()) {
+                debug!("Speech synthesizer closed its input early: {}", err);
+            }
+        });
+    }
+    *current = Some(child);
+    Ok(())
+}
+
+#[tauri::command]
+fn stop_speaking(state: State<'_, SpeechState>) -> Result<(), SpotlightError> {
+    let mut current = state
+        .child
+        .lock()
+        .map_err(|_| SpotlightError::Platform("Speech state is poisoned".to_string()))?;
+    if let Some(child) = current.take() {
+        stop_synthesizer(child);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn set_microphone_enabled(app: AppHandle, enabled: bool) -> Result<(), SpotlightError> {
+    let store = settings_store(&app)?;
+    store.set(MICROPHONE_ENABLED_KEY, enabled);
+    store.save().map_err(SpotlightError::from)
+}
+
+fn stored_microphone_enabled(app: &AppHandle) -> bool {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(MICROPHONE_ENABLED_KEY))
+        .and_then(|json| json.as_bool())
+        .unwrap_or(false)
+}
+
+/// Starts recording from the default microphone, emitting `recording-level`
+/// events until [`stop_recording`] is called.
+#[tauri::command]
+fn start_recording(app: AppHandle, state: State<'_, RecordingState>) -> Result<(), SpotlightError> {
+    if !stored_microphone_enabled(&app) {
+        return Err(SpotlightError::MicrophoneUnavailable(
+            "turn on microphone access in settings first".to_string(),
+        ));
+    }
+    let mut active = state
+        .active
+        .lock()
+        .map_err(|_| SpotlightError::Platform("Recording state is poisoned".to_string()))?;
+    if active.is_some() {
+        return Err(SpotlightError::InvalidInput(
+            "Already recording".to_string(),
+        ));
+    }
+
+    let (stop, stop_rx) = std::sync::mpsc::channel();
+    let (ready, ready_rx) = std::sync::mpsc::channel();
+    let worker = std::thread::spawn(move || record_until_stopped(app, stop_rx, ready));
+    // Opening the device can fail (no microphone, permission denied); report
+    // that from this call rather than from stop_recording
+    match ready_rx.recv() {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => {
+            let _ = worker.join();
+            return Err(err);
+        }
+        Err(_) => {
+            return Err(match worker.join() {
+                Ok(Err(err)) => err,
+                _ => SpotlightError::Platform("Recording thread exited unexpectedly".to_string()),
+            });
+        }
+    }
+    *active = Some(ActiveRecording { stop, worker });
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RecordingResult {
+    /// The recording as a WAV clip, ready to attach as `audio`.
+    audio: InlineData,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transcript: Option<String>,
+}
+
+/// Stops the recording and returns it as WAV, optionally transcribed by Gemini.
+#[tauri::command]
+async fn stop_recording(
+    app: AppHandle,
+    state: State<'_, RecordingState>,
+    transcribe: Option<bool>,
+) -> Result<RecordingResult, SpotlightError> {
+    let recording = state
+        .active
+        .lock()
+        .map_err(|_| SpotlightError::Platform("Recording state is poisoned".to_string()))?
+        .take()
+        .ok_or_else(|| SpotlightError::InvalidInput("Not recording".to_string()))?;
+    // Dropping the sender would stop it too; an explicit send reads clearer
+    let _ = recording.stop.send(());
+    let recorded = tokio::task::spawn_blocking(move || recording.worker.join())
+        .await
+        .map_err(|e| SpotlightError::Platform(format!("Recording task failed: {}", e)))?
+        .map_err(|_| SpotlightError::Platform("Recording thread panicked".to_string()))??;
+    if recorded.samples.is_empty() {
+        return Err(SpotlightError::InvalidInput(
+            "The recording is empty".to_string(),
+        ));
+    }
+
+    let audio = InlineData {
+        mime_type: "audio/wav".to_string(),
+        data: general_purpose::STANDARD.encode(encode_wav(&recorded.samples, recorded.sample_rate)),
+    };
+    if !transcribe.unwrap_or(false) {
+        return Ok(RecordingResult {
+            audio,
+            transcript: None,
+        });
+    }
+
+    let backend = stored_backend(&app);
+    if backend != Backend::Gemini {
+        return Err(SpotlightError::InvalidInput(
+            "Transcription needs the Gemini backend; attach the audio instead".to_string(),
+        ));
+    }
+    let store = settings_store(&app)?;
+    let api_key = active_api_key(&store).unwrap_or_default();
+    if api_key.trim().is_empty() {
+        return Err(SpotlightError::MissingApiKey);
+    }
+    let request = ChatRequest {
+        message: TRANSCRIBE_PROMPT.to_string(),
+        images: Vec::new(),
+        chat_history: Vec::new(),
+        system_instructions: None,
+        grounding_enabled: false,
+        dynamic_threshold: None,
+        thinking_enabled: Some(false),
+        code_execution_enabled: false,
+        url_context_enabled: false,
+        response_mime_type: None,
+        response_schema: None,
+        temperature: None,
+        stop_sequences: Vec::new(),
+        candidate_count: None,
+        seed: None,
+        presence_penalty: None,
+        frequency_penalty: None,
+        audio: Some(InlineData {
+            mime_type: audio.mime_type.clone(),
+            data: audio.data.clone(),
+        }),
+        document: None,
+        cached_content: None,
+    };
+    ensure_request_fits(&app, &request)?;
+    throttle_requests(&app, &backend).await?;
+    // No app handle: the transcript must not stream into the visible chat
+    let result = generate_with_backend(
+        None,
+        &backend,
+        &api_key,
+        &stored_gemini_settings(&app),
+        active_profile_model(&app),
+        request,
+    )
+    .await
+    .map_err(|err| {
+        let err = err.redacted(&api_key);
+        error!("Transcription failed: {}", err);
+        err
+    })?;
+    Ok(RecordingResult {
+        audio,
+        transcript: Some(result.text.trim().to_string()),
+    })
+}
+
+/// Wraps mono 16-bit PCM in a minimal RIFF/WAVE container.
+fn encode_wav(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+    const CHANNELS: u16 = 1;
+    const BITS_PER_SAMPLE: u16 = 16;
+    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+    let data_len = (samples.len() * 2) as u32;
+
+    let mut wav = Vec::with_capacity(44 + samples.len() * 2);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&CHANNELS.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * u32::from(block_align)).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+/// RMS of a block of samples, scaled to 0.0..=1.0.
+#[cfg_attr(not(feature = "microphone"), allow(dead_code))]
+fn rms_level(samples: &[i16]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f64 = samples
+        .iter()
+        .map(|&sample| {
+            let sample = f64::from(sample) / f64::from(i16::MAX);
+            sample * sample
+        })
+        .sum();
+    ((sum / samples.len() as f64).sqrt() as f32).min(1.0)
+}
+
+/// Body of the recording thread: opens the default input device, reports
+/// through `ready` whether that worked, then records until `stop` fires.
+#[cfg(feature = "microphone")]
+fn record_until_stopped(
+    app: AppHandle,
+    stop: std::sync::mpsc::Receiver<()>,
+    ready: std::sync::mpsc::Sender<Result<(), SpotlightError>>,
+) -> Result<RecordedAudio, SpotlightError> {
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+    type OpenedStream = (cpal::Stream, Arc<Mutex<Vec<i16>>>, u32);
+
+    fn open_input_stream(app: &AppHandle) -> Result<OpenedStream, SpotlightError> {
+        let device = cpal::default_host().default_input_device().ok_or_else(|| {
+            SpotlightError::MicrophoneUnavailable("no microphone found".to_string())
+        })?;
+        // Denied microphone permission typically surfaces here
+        let supported = device.default_input_config().map_err(|e| {
+            SpotlightError::MicrophoneUnavailable(format!(
+                "cannot open the microphone (is access allowed?): {}",
+                e
+            ))
+        })?;
+        let sample_format = supported.sample_format();
+        let config: cpal::StreamConfig = supported.into();
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        let stream = match sample_format {
+            cpal::SampleFormat::F32 => build_stream::<f32>(app, &device, &config, &samples),
+            cpal::SampleFormat::I16 => build_stream::<i16>(app, &device, &config, &samples),
+            cpal::SampleFormat::U16 => build_stream::<u16>(app, &device, &config, &samples),
+            other => {
+                return Err(SpotlightError::MicrophoneUnavailable(format!(
+                    "unsupported sample format {:?}",
+                    other
+                )))
+            }
+        }?;
+        Ok((stream, samples, config.sample_rate.0))
+    }
+
+    /// Downmixes each frame to mono i16, appending until `MAX_RECORDING_SECS`
+    /// and emitting throttled level events along the way.
+    fn build_stream<T>(
+        app: &AppHandle,
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        samples: &Arc<Mutex<Vec<i16>>>,
+    ) -> Result<cpal::Stream, SpotlightError>
+    where
+        T: cpal::SizedSample,
+        i16: cpal::FromSample<T>,
+    {
+        use cpal::Sample;
+
+        let channels = usize::from(config.channels.max(1));
+        let max_samples = (config.sample_rate.0 * MAX_RECORDING_SECS) as usize;
+        let samples = Arc::clone(samples);
+        let app = app.clone();
+        let mut last_level_at = Instant::now();
+        let mut block = Vec::new();
+        device
+            .build_input_stream(
+                config,
+                move |data: &[T], _: &cpal::InputCallbackInfo| {
+                    block.clear();
+                    block.extend(data.chunks(channels).map(|frame| {
+                        let sum: i32 = frame
+                            .iter()
+                            .map(|&sample| i32::from(i16::from_sample(sample)))
+                            .sum();
+                        (sum / frame.len() as i32) as i16
+                    }));
+                    if let Ok(mut samples) = samples.lock() {
+                        let room = max_samples.saturating_sub(samples.len());
+                        samples.extend(block.iter().take(room));
+                    }
+                    if last_level_at.elapsed() >= RECORDING_LEVEL_INTERVAL {
+                        last_level_at = Instant::now();
+                        emit_if_attached(
+                            Some(&app),
+                            RECORDING_LEVEL_EVENT,
+                            RecordingLevelPayload {
+                                level: rms_level(&block),
+                            },
+                        );
+                    }
+                },
+                |err| error!("Microphone stream error: {}", err),
+                None,
+            )
+            .map_err(|e| {
+                SpotlightError::MicrophoneUnavailable(format!(
+                    "cannot record from the microphone: {}",
+                    e
+                ))
+            })
+    }
+
+    let opened = open_input_stream(&app);
+    let (stream, samples, sample_rate) = match opened {
+        Ok(opened) => opened,
+        Err(err) => {
+            let _ = ready.send(Err(err.clone()));
+            return Err(err);
+        }
+    };
+    if let Err(err) = stream.play() {
+        let err =
+            SpotlightError::MicrophoneUnavailable(format!("failed to start recording: {}", err));
+        let _ = ready.send(Err(err.clone()));
+        return Err(err);
+    }
+    let _ = ready.send(Ok(()));
+
+    // Either an explicit stop or the sender being dropped ends the recording
+    let _ = stop.recv();
+    drop(stream);
+    let samples = samples
+        .lock()
+        .map(|mut samples| std::mem::take(&mut *samples))
+        .map_err(|_| SpotlightError::Platform("Recording buffer is poisoned".to_string()))?;
+    Ok(RecordedAudio {
+        samples,
+        sample_rate,
+    })
+}
+
+#[cfg(not(feature = "microphone"))]
+fn record_until_stopped(
+    _app: AppHandle,
+    _stop: std::sync::mpsc::Receiver<()>,
+    ready: std::sync::mpsc::Sender<Result<(), SpotlightError>>,
+) -> Result<RecordedAudio, SpotlightError> {
+    let err = SpotlightError::MicrophoneUnavailable(
+        "not available in this build; rebuild with `--features microphone`".to_string(),
+    );
+    let _ = ready.send(Err(err.clone()));
+    Err(err)
+}
+
+fn stored_sanitize_output(app: &AppHandle) -> bool {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(SANITIZE_OUTPUT_KEY))
+        .and_then(|json| json.as_bool())
+        .unwrap_or(false)
+}
+
+#[tauri::command]
+fn set_sanitize_output(app: AppHandle, enabled: bool) -> Result<(), SpotlightError> {
+    let store = settings_store(&app)?;
+    store.set(SANITIZE_OUTPUT_KEY, enabled);
+    store.save().map_err(SpotlightError::from)
+}
+
+/// Sanitizes the answer in place when `SANITIZE_OUTPUT` is on, keeping the
+/// original in `raw_text`. Structured output is left alone since it is not
+/// rendered as Markdown.
+fn apply_output_sanitization(app: &AppHandle, result: &mut GeminiResult) {
+    if !stored_sanitize_output(app) || result.response_mime_type.is_some() {
+        return;
+    }
+    let sanitized = sanitize_markdown(&result.text);
+    result.raw_text = Some(std::mem::replace(&mut result.text, sanitized));
+    if let Some(candidates) = &mut result.candidates {
+        for candidate in candidates.iter_mut() {
+            *candidate = sanitize_markdown(candidate);
+        }
+    }
+}
+
+/// Renders a conversation as Markdown: one heading per turn, the message text
+/// untouched, and a numbered list of grounding sources at the end. `citations`
+/// belong to the last assistant message and become footnotes into `sources`.
+fn render_conversation_markdown(
+    messages: &[ChatMessage],
+    sources: Option<&[SourceInfo]>,
+    citations: Option<&[Citation]>,
+) -> String {
+    let sources = sources.unwrap_or_default();
+    let cited_message = citations.and_then(|_| {
+        messages
+            .iter()
+            .rposition(|message| matches!(message.role.as_str(), "assistant" | "model"))
+    });
+    let mut cited_sources = Vec::new();
+    let mut markdown = String::new();
+    for (message_index, message) in messages.iter().enumerate() {
+        let heading = match message.role.as_str() {
+            "user" => "User",
+            "assistant" | "model" => "Assistant",
+            "system" => "System",
+            other => other,
+        };
+        markdown.push_str(&format!("## {}\n\n", heading));
+        let attribution: Vec<&str> = [&message.model, &message.created_at]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        if !attribution.is_empty() {
+            markdown.push_str(&format!("_{}_\n\n", attribution.join(" · ")));
+        }
+
+        let footnoted = match citations {
+            Some(citations) if cited_message == Some(message_index) => {
+                let (text, cited) =
+                    insert_footnote_markers(&message.content, citations, sources.len());
+                cited_sources = cited;
+                Some(text)
+            }
+            _ => None,
+        };
+        let content = footnoted.as_deref().unwrap_or(&message.content).trim_end();
+        markdown.push_str(content);
+        markdown.push('\n');
+        // A truncated reply can leave a code fence open, which would swallow
+        // every heading that follows it
+        let open_fences = content
+            .lines()
+            .filter(|line| line.trim_start().starts_with("```"))
+            .count();
+        if open_fences % 2 == 1 {
+            markdown.push_str("```\n");
+        }
+
+        if !message.images.is_empty() {
+            let noun = if message.images.len() == 1 { "image" } else { "images" };
+            markdown.push_str(&format!(
+                "\n> _{} {} attached (not included in this export)_\n",
+                message.images.len(),
+                noun
+            ));
+        }
+        markdown.push('\n');
+    }
+
+    let escape_title = |title: &str| title.replace('[', "\\[").replace(']', "\\]");
+    if !sources.is_empty() {
+        markdown.push_str("## Sources\n\n");
+        for (index, source) in sources.iter().enumerate() {
+            let title = escape_title(&source.title);
+            markdown.push_str(&format!("{}. [{}]({})\n", index + 1, title, source.uri));
+        }
+        markdown.push('\n');
+    }
+    for index in cited_sources {
+        let source = &sources[index];
+        let title = escape_title(&source.title);
+        markdown.push_str(&format!("[^{}]: [{}]({})\n", index + 1, title, source.uri));
+    }
+
+    markdown.trim_end().to_string() + "\n"
+}
+
+/// Puts a `[^n]` footnote reference (numbered like the Sources list) after
+/// each cited span. Citations ending at the same offset share one run of
+/// references, so overlapping spans never nest or repeat a source. Offsets
+/// off a character boundary and unknown sources are skipped. Returns the
+/// text and the source indices referenced, in order.
+fn insert_footnote_markers(
+    text: &str,
+    citations: &[Citation],
+    source_count: usize,
+) -> (String, Vec<usize>) {
+    let mut markers: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for citation in citations {
+        let offset = citation.end_index.min(text.len());
+        if !text.is_char_boundary(offset) {
+            continue;
+        }
+        let at_offset = markers.entry(offset).or_default();
+        for &index in &citation.source_indices {
+            if index < source_count && !at_offset.contains(&index) {
+                at_offset.push(index);
+            }
+        }
+    }
+
+    let mut footnoted = String::with_capacity(text.len());
+    let mut cited = Vec::new();
+    let mut copied = 0;
+    for (offset, mut indices) in markers {
+        if indices.is_empty() {
+            continue;
+        }
+        indices.sort_unstable();
+        footnoted.push_str(&text[copied..offset]);
+        copied = offset;
+        for index in indices {
+            footnoted.push_str(&format!("[^{}]", index + 1));
+            if !cited.contains(&index) {
+                cited.push(index);
+            }
+        }
+    }
+    footnoted.push_str(&text[copied..]);
+    cited.sort_unstable();
+    (footnoted, cited)
+}
+
+#[tauri::command]
+fn export_conversation_markdown(
+    messages: Vec<ChatMessage>,
+    sources: Option<Vec<SourceInfo>>,
+    citations: Option<Vec<Citation>>,
+) -> String {
+    render_conversation_markdown(&messages, sources.as_deref(), citations.as_deref())
+}
+
+#[tauri::command]
+fn copy_text(app: AppHandle, text: String) -> Result<(), SpotlightError> {
+    app.clipboard()
+        .write_text(text)
+        .map_err(|e| SpotlightError::Platform(format!("Clipboard rejected text: {}", e)))
+}
+
+#[tauri::command]
+fn copy_as_markdown(
+    app: AppHandle,
+    messages: Vec<ChatMessage>,
+    sources: Option<Vec<SourceInfo>>,
+    citations: Option<Vec<Citation>>,
+) -> Result<(), SpotlightError> {
+    copy_text(
+        app,
+        render_conversation_markdown(&messages, sources.as_deref(), citations.as_deref()),
+    )
+}
+
+#[tauri::command]
+fn save_markdown(path: String, content: String) -> Result<String, SpotlightError> {
+    let path = std::path::Path::new(&path);
+    validate_export_path(path, &["md", "markdown"])?;
+    create_parent_dir(path)?;
+    std::fs::write(path, content).map_err(|e| {
+        SpotlightError::Platform(format!("Failed to write {}: {}", path.display(), e))
+    })?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// A backend-neutral chat turn. Features a backend cannot express (grounding,
+/// thinking, code execution) are ignored by that backend.
+struct ChatRequest {
+    message: String,
+    images: Vec<ImageInput>,
+    chat_history: Vec<ChatMessage>,
+    system_instructions: Option<String>,
+    grounding_enabled: bool,
+    /// With grounding enabled, only search when the model predicts searching
+    /// helps at least this much (0.0 to 1.0); always search when `None`.
+    /// Only Gemini 1.5 models accept a threshold.
+    dynamic_threshold: Option<f32>,
+    thinking_enabled: Option<bool>,
+    code_execution_enabled: bool,
+    url_context_enabled: bool,
+    response_mime_type: Option<String>,
+    response_schema: Option<serde_json::Value>,
+    /// Backend default when `None`.
+    temperature: Option<f32>,
+    /// Generation halts at the first of these; empty means none.
+    stop_sequences: Vec<String>,
+    /// Alternatives to generate; backend default (one) when `None`.
+    candidate_count: Option<i32>,
+    /// Makes sampling repeatable across identical requests. Determinism is
+    /// best-effort: the API does not guarantee identical output.
+    seed: Option<i32>,
+    /// Discourage reusing tokens that already appeared, at all or by count.
+    presence_penalty: Option<f32>,
+    frequency_penalty: Option<f32>,
+    /// Recorded voice clip sent alongside `message`.
+    audio: Option<InlineData>,
+    /// File (e.g. a PDF) sent alongside `message`, already validated.
+    document: Option<InlineData>,
+    /// Gemini context cache holding earlier turns, created by `create_cache`.
+    cached_content: Option<String>,
+}
+
+trait ChatBackend {
+    async fn generate(&self, request: ChatRequest) -> Result<GeminiResult, SpotlightError>;
+}
+
+struct GeminiBackend<'a> {
+    /// Receives upload progress events; headless runs have no frontend to notify.
+    app: Option<&'a AppHandle>,
+    api_key: &'a str,
+    settings: &'a GeminiSettings,
+    /// Bare model id, without the `models/` prefix.
+    model: String,
+}
+
+struct OpenAiCompatibleBackend<'a> {
+    base_url: &'a str,
+    api_key: &'a str,
+    model: String,
+}
+
+impl OpenAiCompatibleBackend<'_> {
+    fn message(role: &str, text: String, images: &[ImageInput]) -> OpenAiMessage {
+        // Gemini calls the assistant "model"; chat-completions does not
+        let role = if role == "model" { "assistant" } else { role };
+        let content = if images.is_empty() {
+            OpenAiContent::Text(text)
+        } else {
+            let mut parts = vec![OpenAiContentPart::Text { text }];
+            parts.extend(images.iter().map(|image| OpenAiContentPart::ImageUrl {
+                image_url: OpenAiImageUrl {
+                    url: format!("data:{};base64,{}", image.mime_type, image.data),
+                },
+            }));
+            OpenAiContent::Parts(parts)
+        };
+        OpenAiMessage {
+            role: role.to_string(),
+            content,
+        }
+    }
+}
+
+impl ChatBackend for OpenAiCompatibleBackend<'_> {
+    async fn generate(&self, request: ChatRequest) -> Result<GeminiResult, SpotlightError> {
+        let mut messages = Vec::new();
+        if let Some(instructions) = request
+            .system_instructions
+            .filter(|instructions| !instructions.trim().is_empty())
+        {
+            messages.push(Self::message("system", instructions, &[]));
+        }
+        messages.extend(
+            request
+                .chat_history
+                .into_iter()
+                .map(|msg| Self::message(&msg.role, msg.content, &msg.images)),
+        );
+        messages.push(Self::message("user", request.message, &request.images));
+
+        let response_format = match (&request.response_schema, request.response_mime_type.as_deref()) {
+            (Some(schema), _) => Some(serde_json::json!({
+                "type": "json_schema",
+                "json_schema": { "name": "response", "schema": schema },
+            })),
+            (None, Some(JSON_MIME_TYPE)) => Some(serde_json::json!({ "type": "json_object" })),
+            _ => None,
+        };
+
+        let openai_request = OpenAiRequest {
+            model: self.model.clone(),
+            messages,
+            response_format,
+            temperature: request.temperature,
+            stop: request.stop_sequences,
+            seed: request.seed,
+            presence_penalty: request.presence_penalty,
+            frequency_penalty: request.frequency_penalty,
+        };
+
+        let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+        let client = reqwest::Client::new();
+        let mut builder = client.post(&url).json(&openai_request);
+        if !self.api_key.trim().is_empty() {
+            builder = builder.bearer_auth(self.api_key.trim());
+        }
+        let response = builder.send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(classify_api_error(status, error_text));
+        }
+
+        let response_text = response.text().await?;
+        debug!("Raw OpenAI-compatible Response: {}", response_text);
+
+        let openai_response: OpenAiResponse = serde_json::from_str(&response_text)
+            .map_err(|e| SpotlightError::Parse(e.to_string()))?;
+        let choice = openai_response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| SpotlightError::Api("No response from backend".to_string()))?;
+        let text = match choice.message.content.filter(|text| !text.is_empty()) {
+            Some(text) => text,
+            None if choice.finish_reason.as_deref() == Some(OPENAI_BLOCKED_FINISH_REASON) => {
+                return Err(SpotlightError::Blocked(OPENAI_BLOCKED_FINISH_REASON.to_string()));
+            }
+            None => return Err(SpotlightError::Api("No response from backend".to_string())),
+        };
+
+        Ok(GeminiResult {
+            text,
+            thinking: None,
+            sources: None,
+            citations: None,
+            code_blocks: None,
+            response_mime_type: request.response_mime_type,
+            model: self.model.clone(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            total_tokens: None,
+            candidates: None,
+            raw_text: None,
+            search_queries: None,
+        })
+    }
+}
+
+/// Emits `event` to the frontend when there is one to receive it.
+fn emit_if_attached<S: Serialize + Clone>(app: Option<&AppHandle>, event: &str, payload: S) {
+    let Some(app) = app else {
+        return;
+    };
+    if let Err(err) = app.emit(event, payload) {
+        error!("Failed to emit {event}: {err}");
+    }
+}
+
+/// Streams `body` in fixed-size chunks, reporting each one as it is handed to
+/// the connection and emitting `request-sent` once the last one has gone out.
+fn upload_body(app: Option<&AppHandle>, body: Vec<u8>) -> reqwest::Body {
+    use futures_util::{future, stream, StreamExt};
+
+    // Slices share the one buffer, so large screenshots aren't copied again
+    let body = bytes::Bytes::from(body);
+    let total_bytes = body.len();
+    let chunks = (0..total_bytes)
+        .step_by(UPLOAD_CHUNK_BYTES)
+        .map(move |start| body.slice(start..(start + UPLOAD_CHUNK_BYTES).min(total_bytes)));
+    let app = app.cloned();
+    let progress_app = app.clone();
+    let mut sent_bytes = 0;
+    let progress = stream::iter(chunks).map(move |chunk| {
+        sent_bytes += chunk.len();
+        emit_if_attached(
+            progress_app.as_ref(),
+            REQUEST_PROGRESS_EVENT,
+            RequestProgressPayload {
+                sent_bytes,
+                total_bytes,
+            },
+        );
+        Ok::<_, std::io::Error>(chunk)
+    });
+    // Only polled after the final chunk has been consumed by the connection.
+    let sent = stream::once(async move {
+        emit_if_attached(
+            app.as_ref(),
+            REQUEST_SENT_EVENT,
+            RequestProgressPayload {
+                sent_bytes: total_bytes,
+                total_bytes,
+            },
+        );
+    })
+    .filter_map(|()| future::ready(None));
+    reqwest::Body::wrap_stream(progress.chain(sent))
+}
+
+struct OllamaBackend<'a> {
+    /// Receives `gemini-chunk` events; headless runs have no frontend to stream to.
+    app: Option<&'a AppHandle>,
+    base_url: &'a str,
+    model: String,
+    /// Streamed text is sanitized like the final answer (`SANITIZE_OUTPUT`).
+    sanitize: bool,
+}
+
+impl OllamaBackend<'_> {
+    fn emit_chunk(&self, text: &str, replace: bool) {
+        let Some(app) = self.app else {
+            return;
+        };
+        let payload = GeminiChunkPayload {
+            text: text.to_string(),
+            replace,
+        };
+        if let Err(err) = app.emit(GEMINI_CHUNK_EVENT, payload) {
+            error!("Failed to emit response chunk: {err}");
+        }
+    }
+
+    /// Parses one NDJSON line, streams its text to the frontend and returns
+    /// whether Ollama marked the response as finished. With `sanitize`, the
+    /// sanitized answer so far replaces what was streamed, since a tag or link
+    /// can span several chunks.
+    fn handle_line(
+        &self,
+        line: &[u8],
+        text: &mut String,
+        sanitize: bool,
+    ) -> Result<bool, SpotlightError> {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(false);
+        }
+        let chunk: OllamaChunk =
+            serde_json::from_slice(line).map_err(|e| SpotlightError::Parse(e.to_string()))?;
+        if let Some(error) = chunk.error {
+            return Err(SpotlightError::Api(error));
+        }
+        if let Some(message) = chunk.message.filter(|message| !message.content.is_empty()) {
+            text.push_str(&message.content);
+            if sanitize {
+                self.emit_chunk(&sanitize_markdown(text), true);
+            } else {
+                self.emit_chunk(&message.content, false);
+            }
+        }
+        Ok(chunk.done)
+    }
+}
+
+impl ChatBackend for OllamaBackend<'_> {
+    async fn generate(&self, request: ChatRequest) -> Result<GeminiResult, SpotlightError> {
+        let mut messages = Vec::new();
+        if let Some(instructions) = request
+            .system_instructions
+            .filter(|instructions| !instructions.trim().is_empty())
+        {
+            messages.push(OllamaMessage {
+                role: "system".to_string(),
+                content: instructions,
+                images: Vec::new(),
+            });
+        }
+        messages.extend(request.chat_history.into_iter().map(|msg| OllamaMessage {
+            role: if msg.role == "model" {
+                "assistant".to_string()
+            } else {
+                msg.role
+            },
+            content: msg.content,
+            images: msg.images.into_iter().map(|image| image.data).collect(),
+        }));
+        messages.push(OllamaMessage {
+            role: "user".to_string(),
+            content: request.message,
+            images: request.images.into_iter().map(|image| image.data).collect(),
+        });
+
+        let format = match (request.response_schema, request.response_mime_type.as_deref()) {
+            (Some(schema), _) => Some(schema),
+            (None, Some(JSON_MIME_TYPE)) => Some(serde_json::Value::from("json")),
+            _ => None,
+        };
+
+        // Structured output is not rendered as Markdown, so it is not sanitized
+        let sanitize = self.sanitize && format.is_none();
+        let ollama_request = OllamaRequest {
+            model: self.model.clone(),
+            messages,
+            stream: true,
+            format,
+            options: request
+                .temperature
+                .map(|temperature| OllamaOptions { temperature }),
+        };
+
+        let url = format!("{}/api/chat", self.base_url.trim_end_matches('/'));
+        let client = reqwest::Client::new();
+        let mut response = client.post(&url).json(&ollama_request).send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(classify_api_error(status, error_text));
+        }
+
+        // Lines can be split across network chunks, so buffer until a newline
+        let mut text = String::new();
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut done = false;
+        while let Some(bytes) = response.chunk().await? {
+            buffer.extend_from_slice(&bytes);
+            while let Some(newline) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=newline).collect();
+                done |= self.handle_line(&line, &mut text, sanitize)?;
+            }
+        }
+        if !done {
+            done = self.handle_line(&buffer, &mut text, sanitize)?;
+        }
+        debug!("Ollama response finished: done={}, length={}", done, text.len());
+
+        if text.is_empty() {
+            return Err(SpotlightError::Api("No response from Ollama".to_string()));
+        }
+
+        Ok(GeminiResult {
+            text,
+            thinking: None,
+            sources: None,
+            citations: None,
+            code_blocks: None,
+            response_mime_type: request.response_mime_type,
+            model: self.model.clone(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            total_tokens: None,
+            candidates: None,
+            raw_text: None,
+            search_queries: None,
+        })
+    }
+}
+
+fn stored_backend(app: &AppHandle) -> Backend {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(BACKEND_KEY))
+        .and_then(|json| serde_json::from_value::<Backend>(json).ok())
+        .unwrap_or_default()
+}
+
+/// The model named by the active profile, if any; each backend falls back to
+/// its own default when this is `None`.
+fn active_profile_model(app: &AppHandle) -> Option<String> {
+    settings_store(app)
+        .ok()
+        .and_then(|store| {
+            let active = active_profile_name(&store)?;
+            load_profiles(&store).remove(&active)?.model
+        })
+        .filter(|model| !model.trim().is_empty())
+}
+
+/// Resolves a profile's model to a bare Gemini model id, accepting ids copied
+/// from `list_models` with their `models/` prefix.
+fn gemini_model(model: Option<String>) -> String {
+    model
+        .map(|model| model.trim().trim_start_matches("models/").to_string())
+        .filter(|model| !model.is_empty())
+        .unwrap_or_else(|| GEMINI_MODEL.to_string())
+}
+
+/// `googleSearchRetrieval` with a dynamic threshold is a Gemini 1.5 tool;
+/// later models only accept plain `googleSearch` and reject it with a 400.
+fn supports_dynamic_retrieval(model: &str) -> bool {
+    model.starts_with("gemini-1.5")
+}
+
+#[tauri::command]
+fn get_backend(app: AppHandle) -> Backend {
+    stored_backend(&app)
+}
+
+#[tauri::command]
+fn set_backend(app: AppHandle, backend: Backend) -> Result<(), SpotlightError> {
+    if let Backend::OpenAiCompatible { base_url } | Backend::Ollama { base_url } = &backend {
+        parse_http_url(base_url)?;
+    }
+    let store = settings_store(&app)?;
+    let backend_json = serde_json::to_value(&backend)
+        .map_err(|e| SpotlightError::Store(format!("Failed to serialize backend: {}", e)))?;
+    store.set(BACKEND_KEY, backend_json);
+    store.save().map_err(SpotlightError::from)
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn send_to_gemini(
+    app: AppHandle,
+    message: String,
+    image_data: Option<String>,
+    api_key: String,
+    grounding_enabled: Option<bool>,
+    dynamic_threshold: Option<f32>,
+    thinking_enabled: Option<bool>,
+    code_execution_enabled: Option<bool>,
+    chat_history: Vec<ChatMessage>,
+    system_instructions: Option<String>,
+    context_urls: Option<Vec<String>>,
+    response_mime_type: Option<String>,
+    response_schema: Option<serde_json::Value>,
+    cached_content: Option<String>,
+    stop_sequences: Option<Vec<String>>,
+    audio: Option<InlineData>,
+    document: Option<InlineData>,
+    conversation_id: Option<String>,
+    candidate_count: Option<i32>,
+    seed: Option<i32>,
+    presence_penalty: Option<f32>,
+    frequency_penalty: Option<f32>,
+    include_history: Option<bool>,
+) -> Result<String, SpotlightError> {
+    // One-off questions send only the current turn, whatever history was passed
+    let chat_history = if include_history.unwrap_or(true) {
+        chat_history
+    } else {
+        debug!(
+            "History excluded from request ({} messages dropped)",
+            chat_history.len()
+        );
+        Vec::new()
+    };
+    // A conversation's own system prompt takes precedence over the global one
+    let system_instructions = conversation_id
+        .as_deref()
+        .and_then(|id| {
+            conversations_store(&app)
+                .ok()
+                .and_then(|store| conversation_system_prompt(&store, id))
+        })
+        .or(system_instructions);
+    let result = send_to_gemini_inner(
+        &app,
+        message,
+        image_data,
+        &api_key,
+        grounding_enabled,
+        dynamic_threshold,
+        thinking_enabled,
+        code_execution_enabled,
+        chat_history,
+        system_instructions,
+        context_urls,
+        response_mime_type,
+        response_schema,
+        cached_content,
+        stop_sequences,
+        audio,
+        document,
+        candidate_count,
+        seed,
+        presence_penalty,
+        frequency_penalty,
+    )
+    .await
+    .map_err(|err| {
+        // Redact before logging so the key never reaches the log file
+        let err = err.redacted(&api_key);
+        error!("Chat request failed: {}", err);
+        err
+    })?;
+    serde_json::to_string(&result)
+        .map_err(|e| SpotlightError::Parse(format!("Failed to serialize result: {}", e)))
+}
+
+/// Captures the display (excluding the overlay) and asks about it in one
+/// round-trip, so a single shortcut can run the whole "screenshot and explain"
+/// flow.
+#[tauri::command]
+async fn capture_and_ask(
+    app: AppHandle,
+    window: tauri::Window,
+    prompt: String,
+    grounding_enabled: Option<bool>,
+    include_cursor: Option<bool>,
+) -> Result<GeminiResult, SpotlightError> {
+    let image_data = capture_screen_inner(&window, include_cursor.unwrap_or(false))
+        .map_err(SpotlightError::Capture)?;
+
+    let store = settings_store(&app)?;
+    let api_key = active_api_key(&store).unwrap_or_default();
+    let system_instructions = store
+        .get(SYSTEM_INSTRUCTIONS_KEY)
+        .and_then(|json| json.as_str().map(|s| s.to_string()));
+
+    send_to_gemini_inner(
+        &app,
+        prompt,
+        Some(image_data),
+        &api_key,
+        grounding_enabled,
+        None,
+        None,
+        None,
+        Vec::new(),
+        system_instructions,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    )
+    .await
+    .map_err(|err| {
+        let err = err.redacted(&api_key);
+        error!("Capture and ask failed: {}", err);
+        err
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn send_to_gemini_inner(
+    app: &AppHandle,
+    message: String,
+    image_data: Option<String>,
+    api_key: &str,
+    grounding_enabled: Option<bool>,
+    dynamic_threshold: Option<f32>,
+    thinking_enabled: Option<bool>,
+    code_execution_enabled: Option<bool>,
+    mut chat_history: Vec<ChatMessage>,
+    system_instructions: Option<String>,
+    context_urls: Option<Vec<String>>,
+    response_mime_type: Option<String>,
+    response_schema: Option<serde_json::Value>,
+    cached_content: Option<String>,
+    stop_sequences: Option<Vec<String>>,
+    audio: Option<InlineData>,
+    document: Option<InlineData>,
+    candidate_count: Option<i32>,
+    seed: Option<i32>,
+    presence_penalty: Option<f32>,
+    frequency_penalty: Option<f32>,
+) -> Result<GeminiResult, SpotlightError> {
+    // Image-, voice- or document-only prompts are fine; a prompt with nothing is not
+    if message.trim().is_empty() && image_data.is_none() && audio.is_none() && document.is_none() {
+        return Err(SpotlightError::EmptyPrompt);
+    }
+    record_recent_query(app, &message);
+    let message = if message.trim().is_empty() && image_data.is_some() {
+        stored_image_only_prompt(app)
+    } else {
+        message
+    };
+
+    let backend = stored_backend(app);
+    if backend.requires_api_key() && api_key.trim().is_empty() {
+        return Err(SpotlightError::MissingApiKey);
+    }
+
+    let defaults = stored_default_toggles(app);
+    let code_execution_enabled = code_execution_enabled.unwrap_or(false);
+    // A default never conflicts with tools the request asked for explicitly
+    let grounding_enabled = grounding_enabled
+        .unwrap_or(defaults.grounding && !code_execution_enabled && cached_content.is_none());
+    let thinking_enabled = thinking_enabled.or(defaults.thinking);
+    if grounding_enabled && code_execution_enabled {
+        return Err(SpotlightError::InvalidInput(
+            "Code execution cannot be combined with web grounding".to_string(),
+        ));
+    }
+
+    let cached_content = cached_content
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+    if cached_content.is_some() && (grounding_enabled || code_execution_enabled) {
+        return Err(SpotlightError::InvalidInput(
+            "Tools cannot be combined with cached content".to_string(),
+        ));
+    }
+
+    let response_mime_type = response_mime_type
+        .map(|mime_type| mime_type.trim().to_string())
+        .filter(|mime_type| !mime_type.is_empty());
+    if response_schema.is_some() && response_mime_type.as_deref() != Some(JSON_MIME_TYPE) {
+        return Err(SpotlightError::InvalidInput(format!(
+            "A response schema requires the response MIME type to be {}",
+            JSON_MIME_TYPE
+        )));
+    }
+
+    let stop_sequences: Vec<String> = stop_sequences
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|sequence| !sequence.is_empty())
+        .collect();
+    if stop_sequences.len() > MAX_STOP_SEQUENCES {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Too many stop sequences: {} provided, at most {} are allowed",
+            stop_sequences.len(),
+            MAX_STOP_SEQUENCES
+        )));
+    }
+
+    if let Some(count) = candidate_count {
+        if !(1..=MAX_CANDIDATE_COUNT).contains(&count) {
+            return Err(SpotlightError::InvalidInput(format!(
+                "Candidate count must be between 1 and {}, got {}",
+                MAX_CANDIDATE_COUNT, count
+            )));
+        }
+    }
+
+    for (name, penalty) in [
+        ("Presence penalty", presence_penalty),
+        ("Frequency penalty", frequency_penalty),
+    ] {
+        if let Some(penalty) = penalty.filter(|penalty| !PENALTY_RANGE.contains(penalty)) {
+            return Err(SpotlightError::InvalidInput(format!(
+                "{} must be at least {} and below {}, got {}",
+                name, PENALTY_RANGE.start, PENALTY_RANGE.end, penalty
+            )));
+        }
+    }
+
+    if let Some(threshold) =
+        dynamic_threshold.filter(|threshold| !DYNAMIC_THRESHOLD_RANGE.contains(threshold))
+    {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Dynamic grounding threshold must be between {} and {}, got {}",
+            DYNAMIC_THRESHOLD_RANGE.start(),
+            DYNAMIC_THRESHOLD_RANGE.end(),
+            threshold
+        )));
+    }
+
+    let audio = audio.map(validate_audio).transpose()?;
+    let document = document.map(validate_document).transpose()?;
+
+    let context_urls = context_urls.unwrap_or_default();
+    if context_urls.len() > MAX_CONTEXT_URLS {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Too many context URLs: {} provided, at most {} are allowed",
+            context_urls.len(),
+            MAX_CONTEXT_URLS
+        )));
+    }
+    if cached_content.is_some() && !context_urls.is_empty() {
+        return Err(SpotlightError::InvalidInput(
+            "Context URLs cannot be combined with cached content".to_string(),
+        ));
+    }
+    let context_urls = context_urls
+        .iter()
+        .map(|url| parse_http_url(url).map(|url| url.to_string()))
+        .collect::<Result<Vec<String>, SpotlightError>>()?;
+
+    let token_budget = stored_history_token_budget(app);
+    let reserved_tokens = estimate_text_tokens(&message)
+        + system_instructions.as_deref().map_or(0, estimate_text_tokens)
+        + if image_data.is_some() { ESTIMATED_TOKENS_PER_IMAGE } else { 0 };
+    let dropped_messages = trim_history_to_budget(&mut chat_history, reserved_tokens, token_budget);
+    if dropped_messages > 0 {
+        debug!("Trimmed {} history messages to fit token budget", dropped_messages);
+        let payload = HistoryTrimmedPayload {
+            dropped_messages,
+            estimated_tokens: reserved_tokens
+                + chat_history.iter().map(estimate_message_tokens).sum::<usize>(),
+            token_budget,
+        };
+        if let Err(err) = app.emit(HISTORY_TRIMMED_EVENT, payload) {
+            error!("Failed to emit history trimmed event: {err}");
+        }
+    }
+    let estimated_prompt_tokens =
+        reserved_tokens + chat_history.iter().map(estimate_message_tokens).sum::<usize>();
+
+    // The url_context tool reads the pages referenced in the prompt itself
+    let url_context_enabled = !context_urls.is_empty();
+    let message = if url_context_enabled {
+        format!("{}\n\n{}", message, context_urls.join("\n"))
+    } else {
+        message
+    };
+
+    let request = ChatRequest {
+        message,
+        images: image_data.map(captured_image).into_iter().collect(),
+        chat_history,
+        system_instructions,
+        grounding_enabled,
+        dynamic_threshold,
+        thinking_enabled,
+        code_execution_enabled,
+        url_context_enabled,
+        response_mime_type,
+        response_schema,
+        temperature: None,
+        stop_sequences,
+        candidate_count,
+        seed,
+        presence_penalty,
+        frequency_penalty,
+        audio,
+        document,
+        cached_content,
+    };
+    ensure_request_fits(app, &request)?;
+    throttle_requests(app, &backend).await?;
+    let mut result = generate_with_backend(
+        Some(app),
+        &backend,
+        api_key,
+        &stored_gemini_settings(app),
+        active_profile_model(app),
+        request,
+    )
+    .await;
+    match &result {
+        Err(SpotlightError::Network(_) | SpotlightError::Offline) => {
+            set_tray_status(app, TrayStatus::Offline)
+        }
+        _ => set_tray_status(app, TrayStatus::Ready),
+    }
+    if let Ok(result) = &mut result {
+        apply_output_sanitization(app, result);
+        cache_last_response(app, result);
+        record_usage(app, grounding_enabled, result.total_tokens);
+        if let Some(sources) = &result.sources {
+            emit_sources_ready(app, sources);
+        }
+        notify_response_ready(app, &result.text);
+        let used_tokens = result
+            .total_tokens
+            .unwrap_or_else(|| estimated_prompt_tokens + estimate_text_tokens(&result.text));
+        warn_if_near_context_limit(app, &backend, used_tokens, token_budget);
+    }
+    result
+}
+
+/// The part of a [`GeminiResult`] worth re-reading while offline.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedResponse {
+    text: String,
+    sources: Option<Vec<SourceInfo>>,
+    model: String,
+    created_at: String,
+}
+
+fn response_cache_store(
+    app: &AppHandle,
+) -> Result<Arc<tauri_plugin_store::Store<tauri::Wry>>, tauri_plugin_store::Error> {
+    StoreBuilder::new(app, app_data_file_path(app, RESPONSE_CACHE_FILE_NAME)).build()
+}
+
+fn stored_cache_last_response(app: &AppHandle) -> bool {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(CACHE_LAST_RESPONSE_KEY))
+        .and_then(|json| json.as_bool())
+        .unwrap_or(true)
+}
+
+fn cache_last_response(app: &AppHandle, result: &GeminiResult) {
+    if !stored_cache_last_response(app) {
+        return;
+    }
+    let cached = CachedResponse {
+        text: result.text.clone(),
+        sources: result.sources.clone(),
+        model: result.model.clone(),
+        created_at: result.created_at.clone(),
+    };
+    let saved = serde_json::to_value(cached)
+        .map_err(|e| e.to_string())
+        .and_then(|json| {
+            let store = response_cache_store(app).map_err(|e| e.to_string())?;
+            store.set(LAST_RESPONSE_KEY, json);
+            store.save().map_err(|e| e.to_string())
+        });
+    if let Err(err) = saved {
+        error!("Failed to cache last response: {err}");
+    }
+}
+
+fn clear_response_cache(app: &AppHandle) -> Result<(), SpotlightError> {
+    let store = response_cache_store(app)?;
+    store.clear();
+    store.save().map_err(SpotlightError::from)
+}
+
+/// Counters for one local calendar day.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DailyUsage {
+    requests: u64,
+    /// Only tokens the API reported in `usageMetadata`.
+    total_tokens: u64,
+    grounded_requests: u64,
+    ungrounded_requests: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageDay {
+    /// `YYYY-MM-DD` in local time.
+    date: String,
+    #[serde(flatten)]
+    usage: DailyUsage,
+}
+
+fn usage_stats_store(
+    app: &AppHandle,
+) -> Result<Arc<tauri_plugin_store::Store<tauri::Wry>>, tauri_plugin_store::Error> {
+    StoreBuilder::new(app, app_data_file_path(app, USAGE_STATS_FILE_NAME)).build()
+}
+
+fn stored_usage_days(store: &tauri_plugin_store::Store<tauri::Wry>) -> HashMap<String, DailyUsage> {
+    store
+        .get(USAGE_DAYS_KEY)
+        .and_then(|json| serde_json::from_value(json).ok())
+        .unwrap_or_default()
+}
+
+/// Counts a successful request towards today's usage, dropping days older
+/// than [`MAX_USAGE_STATS_DAYS`].
+fn record_usage(app: &AppHandle, grounded: bool, total_tokens: Option<usize>) {
+    let today = chrono::Local::now().date_naive();
+    let saved = usage_stats_store(app)
+        .map_err(|e| e.to_string())
+        .and_then(|store| {
+            let mut days = stored_usage_days(&store);
+            let usage = days.entry(usage_date_key(today)).or_default();
+            usage.requests += 1;
+            usage.total_tokens += total_tokens.unwrap_or(0) as u64;
+            if grounded {
+                usage.grounded_requests += 1;
+            } else {
+                usage.ungrounded_requests += 1;
+            }
+            let oldest = usage_series_start(today, MAX_USAGE_STATS_DAYS);
+            days.retain(|date, _| {
+                chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok_and(|date| date >= oldest)
+            });
+            let json = serde_json::to_value(days).map_err(|e| e.to_string())?;
+            store.set(USAGE_DAYS_KEY, json);
+            store.save().map_err(|e| e.to_string())
+        });
+    if let Err(err) = saved {
+        error!("Failed to record usage stats: {err}");
+    }
+}
+
+fn usage_date_key(date: chrono::NaiveDate) -> String {
+    date.format("%Y-%m-%d").to_string()
+}
+
+/// The first day of a `days`-long series ending today.
+fn usage_series_start(today: chrono::NaiveDate, days: u32) -> chrono::NaiveDate {
+    today
+        .checked_sub_days(chrono::Days::new(u64::from(days.saturating_sub(1))))
+        .unwrap_or(chrono::NaiveDate::MIN)
+}
+
+/// One entry per day, oldest first and ending today, with zeroes for days
+/// without requests so charts need no gap filling.
+fn usage_series(
+    recorded: &HashMap<String, DailyUsage>,
+    today: chrono::NaiveDate,
+    days: u32,
+) -> Vec<UsageDay> {
+    usage_series_start(today, days)
+        .iter_days()
+        .take_while(|date| *date <= today)
+        .map(|date| {
+            let date = usage_date_key(date);
+            let usage = recorded.get(&date).copied().unwrap_or_default();
+            UsageDay { date, usage }
+        })
+        .collect()
+}
+
+/// Daily request, token and grounding counts for the last `days` days
+/// (including today), read from the local stats file.
+#[tauri::command]
+fn get_usage_stats(app: AppHandle, days: u32) -> Result<Vec<UsageDay>, SpotlightError> {
+    if !(1..=MAX_USAGE_STATS_DAYS).contains(&days) {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Usage stats cover between 1 and {} days",
+            MAX_USAGE_STATS_DAYS
+        )));
+    }
+    let store = usage_stats_store(&app)?;
+    Ok(usage_series(
+        &stored_usage_days(&store),
+        chrono::Local::now().date_naive(),
+        days,
+    ))
+}
+
+/// The last successful answer, for showing while offline. `None` when
+/// caching is turned off or nothing has been answered yet.
+#[tauri::command]
+fn get_last_response(app: AppHandle) -> Result<Option<CachedResponse>, SpotlightError> {
+    if !stored_cache_last_response(&app) {
+        return Ok(None);
+    }
+    let store = response_cache_store(&app)?;
+    Ok(store
+        .get(LAST_RESPONSE_KEY)
+        .and_then(|json| serde_json::from_value(json).ok()))
+}
+
+/// Turning caching off also deletes the answer already on disk.
+#[tauri::command]
+fn set_cache_last_response(app: AppHandle, enabled: bool) -> Result<(), SpotlightError> {
+    let store = settings_store(&app)?;
+    store.set(CACHE_LAST_RESPONSE_KEY, enabled);
+    store.save()?;
+    if !enabled {
+        clear_response_cache(&app)?;
+    }
+    Ok(())
+}
+
+fn stored_default_toggles(app: &AppHandle) -> DefaultToggles {
+    let store = settings_store(app).ok();
+    let get = |key: &str| {
+        store
+            .as_ref()
+            .and_then(|store| store.get(key))
+            .and_then(|json| json.as_bool())
+    };
+    DefaultToggles {
+        grounding: get(DEFAULT_GROUNDING_KEY).unwrap_or(false),
+        thinking: get(DEFAULT_THINKING_KEY),
+    }
+}
+
+fn emit_default_toggles(app: &AppHandle) {
+    if let Err(err) = app.emit(DEFAULT_TOGGLES_EVENT, stored_default_toggles(app)) {
+        error!("Failed to emit default toggles event: {err}");
+    }
+}
+
+#[tauri::command]
+fn get_default_toggles(app: AppHandle) -> DefaultToggles {
+    stored_default_toggles(&app)
+}
+
+#[tauri::command]
+fn set_default_grounding(app: AppHandle, enabled: bool) -> Result<(), SpotlightError> {
+    let store = settings_store(&app)?;
+    store.set(DEFAULT_GROUNDING_KEY, enabled);
+    store.save()?;
+    emit_default_toggles(&app);
+    Ok(())
+}
+
+/// `None` goes back to the model's default thinking behavior.
+#[tauri::command]
+fn set_default_thinking(app: AppHandle, enabled: Option<bool>) -> Result<(), SpotlightError> {
+    let store = settings_store(&app)?;
+    match enabled {
+        Some(enabled) => store.set(DEFAULT_THINKING_KEY, enabled),
+        None => {
+            store.delete(DEFAULT_THINKING_KEY);
+        }
+    }
+    store.save()?;
+    emit_default_toggles(&app);
+    Ok(())
+}
+
+/// Asks again for the last answer in `chat_history`, reusing the user turn (with
+/// its images and options) that prompted it.
+#[tauri::command]
+async fn regenerate(
+    app: AppHandle,
+    mut chat_history: Vec<ChatMessage>,
+) -> Result<GeminiResult, SpotlightError> {
+    let ends_with_answer = chat_history
+        .last()
+        .is_some_and(|msg| msg.role == "assistant" || msg.role == "model");
+    if !ends_with_answer {
+        return Err(SpotlightError::InvalidInput(
+            "Nothing to regenerate: the conversation does not end with a response".to_string(),
+        ));
+    }
+    chat_history.pop();
+    let user_turn = chat_history
+        .pop()
+        .filter(|msg| msg.role == "user")
+        .ok_or_else(|| {
+            SpotlightError::InvalidInput(
+                "Nothing to regenerate: the response has no preceding question".to_string(),
+            )
+        })?;
+    answer_user_turn(&app, user_turn, chat_history, Some(REGENERATE_TEMPERATURE))
+        .await
+        .map_err(|err| {
+            error!("Regenerate request failed: {}", err);
+            err
+        })
+}
+
+/// The answer to an edited question and the history it now follows.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResendResult {
+    result: GeminiResult,
+    /// Everything before the edited turn plus the edited turn itself; the
+    /// frontend replaces the conversation with this before appending `result`.
+    chat_history: Vec<ChatMessage>,
+}
+
+/// Replaces the user message at `index` with `new_content`, drops every turn
+/// after it and asks again from there. Images on the edited turn are kept.
+#[tauri::command]
+async fn edit_and_resend(
+    app: AppHandle,
+    mut chat_history: Vec<ChatMessage>,
+    index: usize,
+    new_content: String,
+) -> Result<ResendResult, SpotlightError> {
+    let mut user_turn = take_edited_turn(&mut chat_history, index, new_content)?;
+    user_turn.created_at = Some(chrono::Utc::now().to_rfc3339());
+    let prior_history = chat_history.clone();
+    chat_history.push(user_turn.clone());
+
+    let result = answer_user_turn(&app, user_turn, prior_history, None)
+        .await
+        .map_err(|err| {
+            error!("Edit and resend request failed: {}", err);
+            err
+        })?;
+    Ok(ResendResult {
+        result,
+        chat_history,
+    })
+}
+
+/// Cuts `messages` back to the turns before the user message at `index` and
+/// returns that message with its content replaced by `new_content`.
+fn take_edited_turn(
+    messages: &mut Vec<ChatMessage>,
+    index: usize,
+    new_content: String,
+) -> Result<ChatMessage, SpotlightError> {
+    match messages.get(index) {
+        Some(message) if message.role == "user" => {}
+        Some(message) => {
+            return Err(SpotlightError::InvalidInput(format!(
+                "Message {} is a {} message; only user messages can be edited",
+                index, message.role
+            )));
+        }
+        None => {
+            return Err(SpotlightError::InvalidInput(format!(
+                "Message {} does not exist in a conversation of {} messages",
+                index,
+                messages.len()
+            )));
+        }
+    }
+    if new_content.trim().is_empty() && messages[index].images.is_empty() {
+        return Err(SpotlightError::EmptyPrompt);
+    }
+
+    messages.truncate(index + 1);
+    let mut user_turn = messages.remove(index);
+    user_turn.content = new_content;
+    Ok(user_turn)
+}
+
+/// Sends `user_turn` with all of its images after `chat_history`, using the
+/// stored key and instructions and the toggles the turn was first sent with.
+/// Errors come back redacted.
+async fn answer_user_turn(
+    app: &AppHandle,
+    user_turn: ChatMessage,
+    chat_history: Vec<ChatMessage>,
+    temperature: Option<f32>,
+) -> Result<GeminiResult, SpotlightError> {
+    let store = settings_store(app)?;
+    let backend = stored_backend(app);
+    let api_key = active_api_key(&store).unwrap_or_default();
+    if backend.requires_api_key() && api_key.trim().is_empty() {
+        return Err(SpotlightError::MissingApiKey);
+    }
+    let system_instructions = store
+        .get(SYSTEM_INSTRUCTIONS_KEY)
+        .and_then(|json| json.as_str().map(|s| s.to_string()));
+    // Turns saved before options were recorded fall back to today's defaults
+    let options = user_turn.options.unwrap_or_else(|| {
+        let defaults = stored_default_toggles(app);
+        TurnOptions {
+            grounding_enabled: defaults.grounding,
+            thinking_enabled: defaults.thinking,
+            ..TurnOptions::default()
+        }
+    });
+
+    let request = ChatRequest {
+        message: user_turn.content,
+        images: user_turn.images,
+        chat_history,
+        system_instructions,
+        grounding_enabled: options.grounding_enabled,
+        dynamic_threshold: options.dynamic_threshold,
+        thinking_enabled: options.thinking_enabled,
+        code_execution_enabled: options.code_execution_enabled,
+        url_context_enabled: options.url_context_enabled,
+        response_mime_type: None,
+        response_schema: None,
+        temperature,
+        stop_sequences: Vec::new(),
+        candidate_count: None,
+        seed: None,
+        presence_penalty: None,
+        frequency_penalty: None,
+        audio: None,
+        document: None,
+        cached_content: None,
+    };
+    ensure_request_fits(app, &request)?;
+    throttle_requests(app, &backend).await?;
+    let mut result = generate_with_backend(
+        Some(app),
+        &backend,
+        &api_key,
+        &stored_gemini_settings(app),
+        active_profile_model(app),
+        request,
+    )
+    .await
+    .map_err(|err| err.redacted(&api_key))?;
+    apply_output_sanitization(app, &mut result);
+    notify_response_ready(app, &result.text);
+    Ok(result)
+}
+
+/// Compresses older turns into a single summary the frontend can prepend as
+/// context, keeping long chats going without losing what hard trimming drops.
+#[tauri::command]
+async fn summarize_history(
+    app: AppHandle,
+    messages: Vec<ChatMessage>,
+) -> Result<String, SpotlightError> {
+    if messages.is_empty() {
+        return Err(SpotlightError::InvalidInput(
+            "Nothing to summarize: the conversation is empty".to_string(),
+        ));
+    }
+
+    let store = settings_store(&app)?;
+    let backend = stored_backend(&app);
+    let api_key = active_api_key(&store).unwrap_or_default();
+    if backend.requires_api_key() && api_key.trim().is_empty() {
+        return Err(SpotlightError::MissingApiKey);
+    }
+
+    let request = ChatRequest {
+        message: render_conversation_markdown(&messages, None, None),
+        images: Vec::new(),
+        chat_history: Vec::new(),
+        system_instructions: Some(SUMMARIZE_SYSTEM_PROMPT.to_string()),
+        grounding_enabled: false,
+        dynamic_threshold: None,
+        thinking_enabled: Some(false),
+        code_execution_enabled: false,
+        url_context_enabled: false,
+        response_mime_type: None,
+        response_schema: None,
+        temperature: None,
+        stop_sequences: Vec::new(),
+        candidate_count: None,
+        seed: None,
+        presence_penalty: None,
+        frequency_penalty: None,
+        audio: None,
+        document: None,
+        cached_content: None,
+    };
+    ensure_request_fits(&app, &request)?;
+    throttle_requests(&app, &backend).await?;
+    // No app handle: the summary must not stream into the visible chat
+    let result = generate_with_backend(
+        None,
+        &backend,
+        &api_key,
+        &stored_gemini_settings(&app),
+        active_profile_model(&app),
+        request,
+    )
+    .await
+    .map_err(|err| {
+        let err = err.redacted(&api_key);
+        error!("Summarize request failed: {}", err);
+        err
+    })?;
+    Ok(result.text.trim().to_string())
+}
+
+/// Asks several independent questions about the same (optional) image and
+/// returns the answers in input order. A failed question is reported in its
+/// own slot instead of failing the batch.
+#[tauri::command]
+async fn batch_ask(
+    app: AppHandle,
+    prompts: Vec<String>,
+    image_data: Option<String>,
+) -> Result<Vec<BatchAnswer>, SpotlightError> {
+    use futures_util::{stream, StreamExt};
+
+    if prompts.is_empty() {
+        return Err(SpotlightError::InvalidInput(
+            "Add at least one question to the batch".to_string(),
+        ));
+    }
+    if prompts.len() > MAX_BATCH_PROMPTS {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Too many questions: {} provided, at most {} are allowed",
+            prompts.len(),
+            MAX_BATCH_PROMPTS
+        )));
+    }
+
+    let store = settings_store(&app)?;
+    let backend = stored_backend(&app);
+    let api_key = active_api_key(&store).unwrap_or_default();
+    if backend.requires_api_key() && api_key.trim().is_empty() {
+        return Err(SpotlightError::MissingApiKey);
+    }
+    let system_instructions = store
+        .get(SYSTEM_INSTRUCTIONS_KEY)
+        .and_then(|json| json.as_str().map(|s| s.to_string()));
+    let gemini = stored_gemini_settings(&app);
+    let model = active_profile_model(&app);
+    let image_only_prompt = stored_image_only_prompt(&app);
+
+    let (app, backend, api_key, gemini) = (&app, &backend, &api_key, &gemini);
+    let (image_data, system_instructions, model, image_only_prompt) =
+        (&image_data, &system_instructions, &model, &image_only_prompt);
+    let started = tokio::time::Instant::now();
+    let outcomes: Vec<Result<GeminiResult, SpotlightError>> =
+        stream::iter(prompts.into_iter().enumerate())
+            .map(move |(index, prompt)| async move {
+                tokio::time::sleep_until(started + BATCH_REQUEST_INTERVAL * index as u32).await;
+                let message = if !prompt.trim().is_empty() {
+                    prompt
+                } else if image_data.is_some() {
+                    image_only_prompt.clone()
+                } else {
+                    return Err(SpotlightError::EmptyPrompt);
+                };
+                let request = ChatRequest {
+                    message,
+                    images: image_data.clone().map(captured_image).into_iter().collect(),
+                    chat_history: Vec::new(),
+                    system_instructions: system_instructions.clone(),
+                    grounding_enabled: false,
+                    dynamic_threshold: None,
+                    thinking_enabled: None,
+                    code_execution_enabled: false,
+                    url_context_enabled: false,
+                    response_mime_type: None,
+                    response_schema: None,
+                    temperature: None,
+                    stop_sequences: Vec::new(),
+                    candidate_count: None,
+                    seed: None,
+                    presence_penalty: None,
+                    frequency_penalty: None,
+                    audio: None,
+                    document: None,
+                    cached_content: None,
+                };
+                ensure_request_fits(app, &request)?;
+                throttle_requests(app, backend).await?;
+                // No app handle: concurrent answers must not stream into the chat
+                let mut result =
+                    generate_with_backend(None, backend, api_key, gemini, model.clone(), request)
+                        .await?;
+                apply_output_sanitization(app, &mut result);
+                Ok::<_, SpotlightError>(result)
+            })
+            .buffered(BATCH_CONCURRENCY)
+            .collect()
+            .await;
+
+    Ok(outcomes
+        .into_iter()
+        .enumerate()
+        .map(|(index, outcome)| match outcome {
+            Ok(result) => BatchAnswer {
+                result: Some(result),
+                error: None,
+            },
+            Err(err) => {
+                let err = err.redacted(api_key);
+                error!("Batch question {} failed: {}", index + 1, err);
+                BatchAnswer {
+                    result: None,
+                    error: Some(err),
+                }
+            }
+        })
+        .collect())
+}
+
+fn stored_rate_limit(app: &AppHandle) -> RateLimitSettings {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(RATE_LIMIT_KEY))
+        .and_then(|json| serde_json::from_value::<RateLimitSettings>(json).ok())
+        .unwrap_or_default()
+}
+
+#[tauri::command]
+fn get_rate_limit(app: AppHandle) -> RateLimitSettings {
+    stored_rate_limit(&app)
+}
+
+#[tauri::command]
+fn set_rate_limit(
+    app: AppHandle,
+    state: State<'_, RateLimiterState>,
+    settings: RateLimitSettings,
+) -> Result<(), SpotlightError> {
+    let store = settings_store(&app)?;
+    let settings_json = serde_json::to_value(settings)
+        .map_err(|e| SpotlightError::Store(format!("Failed to serialize rate limit: {}", e)))?;
+    store.set(RATE_LIMIT_KEY, settings_json);
+    store.save()?;
+    // Start the new rate from a full bucket rather than the old one's level
+    if let Ok(mut bucket) = state.bucket.lock() {
+        *bucket = None;
+    }
+    Ok(())
+}
+
+/// Waits for (or, in reject mode, insists on) a free slot in the local rate
+/// limit before a Gemini request. Other backends have no shared quota.
+async fn throttle_requests(app: &AppHandle, backend: &Backend) -> Result<(), SpotlightError> {
+    let settings = stored_rate_limit(app);
+    if *backend != Backend::Gemini || settings.requests_per_minute == 0 {
+        return Ok(());
+    }
+    let Some(state) = app.try_state::<RateLimiterState>() else {
+        return Ok(());
+    };
+    loop {
+        let taken = {
+            let mut bucket = state
+                .bucket
+                .lock()
+                .map_err(|_| SpotlightError::Platform("Rate limiter state is poisoned".to_string()))?;
+            let now = Instant::now();
+            bucket
+                .get_or_insert_with(|| TokenBucket::full(settings.requests_per_minute, now))
+                .try_take(settings.requests_per_minute, now)
+        };
+        match (taken, settings.mode) {
+            (Ok(()), _) => return Ok(()),
+            (Err(retry_after), RateLimitMode::Reject) => {
+                return Err(SpotlightError::RateLimitedLocally {
+                    retry_after_ms: retry_after.as_millis().try_into().unwrap_or(u64::MAX),
+                });
+            }
+            (Err(retry_after), RateLimitMode::Wait) => {
+                debug!("Rate limited locally; waiting {:?}", retry_after);
+                tokio::time::sleep(retry_after).await;
+            }
+        }
+    }
+}
+
+/// Sends a request through whichever backend is configured. `model` is the
+/// active profile's model, if any, and `gemini` only applies to Gemini.
+async fn generate_with_backend(
+    app: Option<&AppHandle>,
+    backend: &Backend,
+    api_key: &str,
+    gemini: &GeminiSettings,
+    model: Option<String>,
+    request: ChatRequest,
+) -> Result<GeminiResult, SpotlightError> {
+    match backend {
+        Backend::Gemini => {
+            GeminiBackend {
+                app,
+                api_key,
+                settings: gemini,
+                model: gemini_model(model),
+            }
+            .generate(request)
+            .await
+        }
+        Backend::OpenAiCompatible { base_url } => {
+            OpenAiCompatibleBackend {
+                base_url,
+                api_key,
+                model: model.unwrap_or_else(|| DEFAULT_OPENAI_MODEL.to_string()),
+            }
+            .generate(request)
+            .await
+        }
+        Backend::Ollama { base_url } => {
+            OllamaBackend {
+                app,
+                base_url,
+                model: model.unwrap_or_else(|| DEFAULT_OLLAMA_MODEL.to_string()),
+                sanitize: app.is_some_and(stored_sanitize_output),
+            }
+            .generate(request)
+            .await
+        }
+    }
+}
+
+fn gemini_history_contents(history: &[ChatMessage]) -> Vec<GeminiContent> {
+    history
+        .iter()
+        .map(|msg| {
+            let role = if msg.role == "assistant" {
+                "model".to_string()
+            } else {
+                msg.role.clone()
+            };
+            let mut parts = vec![GeminiPart {
+                text: Some(msg.content.clone()),
+                inline_data: None,
+            }];
+            parts.extend(msg.images.iter().map(|image| GeminiPart {
+                text: None,
+                inline_data: Some(InlineData {
+                    mime_type: image.mime_type.clone(),
+                    data: image.data.clone(),
+                }),
+            }));
+            GeminiContent { role, parts }
+        })
+        .collect()
+}
+
+fn gemini_system_instruction(instructions: Option<String>) -> Option<SystemInstruction> {
+    instructions
+        .filter(|instructions| !instructions.trim().is_empty())
+        .map(|instructions| SystemInstruction {
+            parts: vec![GeminiPart {
+                text: Some(instructions),
+                inline_data: None,
+            }],
+        })
+}
+
+/// Gemini answers a request for an expired or deleted cache with a 403 or 404
+/// naming the `CachedContent` resource.
+fn is_cache_miss(status: reqwest::StatusCode, body: &str) -> bool {
+    matches!(status.as_u16(), 403 | 404) && body.contains("CachedContent")
+}
+
+/// Uploads `contents` (typically a large screenshot or document) once so
+/// follow-ups can reference the returned cache name via `send_to_gemini`
+/// instead of resending it. The stored system instructions are cached too.
+#[tauri::command]
+async fn create_cache(
+    app: AppHandle,
+    contents: Vec<ChatMessage>,
+    ttl_seconds: u64,
+) -> Result<String, SpotlightError> {
+    if contents.is_empty() {
+        return Err(SpotlightError::InvalidInput(
+            "Nothing to cache: no contents were provided".to_string(),
+        ));
+    }
+    if ttl_seconds == 0 {
+        return Err(SpotlightError::InvalidInput(
+            "Cache TTL must be greater than zero".to_string(),
+        ));
+    }
+    if stored_backend(&app) != Backend::Gemini {
+        return Err(SpotlightError::InvalidInput(
+            "Context caching is only available with the Gemini backend".to_string(),
+        ));
+    }
+
+    let store = settings_store(&app)?;
+    let api_key = active_api_key(&store)
+        .filter(|key| !key.trim().is_empty())
+        .ok_or(SpotlightError::MissingApiKey)?;
+    let system_instructions = store
+        .get(SYSTEM_INSTRUCTIONS_KEY)
+        .and_then(|json| json.as_str().map(|s| s.to_string()));
+
+    let request = CachedContentRequest {
+        // Caches must be created for the same model that later reads them
+        model: format!("models/{}", gemini_model(active_profile_model(&app))),
+        system_instruction: gemini_system_instruction(system_instructions),
+        contents: gemini_history_contents(&contents),
+        ttl: format!("{}s", ttl_seconds),
+    };
+    create_cached_content(&api_key, &stored_gemini_settings(&app), &request)
+        .await
+        .map_err(|err| {
+            let err = err.redacted(&api_key);
+            error!("Cache creation failed: {}", err);
+            err
+        })
+}
+
+async fn create_cached_content(
+    api_key: &str,
+    gemini: &GeminiSettings,
+    request: &CachedContentRequest,
+) -> Result<String, SpotlightError> {
+    let client = reqwest::Client::new();
+    let url = gemini.endpoint("cachedContents");
+    let response = gemini_post(&client, &url, api_key, &gemini.headers)
+        .json(request)
+        .send()
+        .await?;
+    let status = response.status();
+    if !status.is_success() {
+        let error_text = response.text().await.unwrap_or_default();
+        return Err(classify_api_error(status, error_text));
+    }
+    let cache: CachedContentResponse = response.json().await?;
+    debug!("Created context cache {}", cache.name);
+    Ok(cache.name)
+}
+
+impl ChatBackend for GeminiBackend<'_> {
+    async fn generate(&self, request: ChatRequest) -> Result<GeminiResult, SpotlightError> {
+        let ChatRequest {
+            message,
+            images,
+            chat_history,
+            system_instructions,
+            grounding_enabled,
+            dynamic_threshold,
+            thinking_enabled,
+            code_execution_enabled,
+            url_context_enabled,
+            response_mime_type,
+            response_schema,
+            temperature,
+            stop_sequences,
+            candidate_count,
+            seed,
+            presence_penalty,
+            frequency_penalty,
+            audio,
+            document,
+            cached_content,
+        } = request;
+
+        // Build conversation history
+        let mut contents = gemini_history_contents(&chat_history);
+
+        // Add current message with optional image; an empty text part is
+        // omitted rather than sent, since image-only prompts need none
+        let mut current_parts = Vec::new();
+        if !message.trim().is_empty() {
+            current_parts.push(GeminiPart {
+                text: Some(message),
+                inline_data: None,
+            });
+        }
+
+        current_parts.extend(images.into_iter().map(|image| GeminiPart {
+            text: None,
+            inline_data: Some(InlineData {
+                mime_type: image.mime_type,
+                data: image.data,
+            }),
+        }));
+
+        if let Some(audio) = audio {
+            current_parts.push(GeminiPart {
+                text: None,
+                inline_data: Some(audio),
+            });
+        }
+
+        if let Some(document) = document {
+            current_parts.push(GeminiPart {
+                text: None,
+                inline_data: Some(document),
+            });
+        }
+
+        contents.push(GeminiContent {
+            role: "user".to_string(),
+            parts: current_parts,
+        });
+
+        if grounding_enabled
+            && dynamic_threshold.is_some()
+            && !supports_dynamic_retrieval(&self.model)
+        {
+            return Err(SpotlightError::InvalidInput(format!(
+                "Dynamic retrieval is only supported on Gemini 1.5 models, not {}",
+                self.model
+            )));
+        }
+
+        let mut tools = Vec::new();
+        if grounding_enabled {
+            // A threshold swaps always-on search for search-when-unsure
+            tools.push(match dynamic_threshold {
+                Some(dynamic_threshold) => Tool {
+                    google_search: None,
+                    google_search_retrieval: Some(GoogleSearchRetrieval {
+                        dynamic_retrieval_config: DynamicRetrievalConfig {
+                            mode: "MODE_DYNAMIC".to_string(),
+                            dynamic_threshold,
+                        },
+                    }),
+                    url_context: None,
+                    code_execution: None,
+                },
+                None => Tool {
+                    google_search: Some(GoogleSearch {}),
+                    google_search_retrieval: None,
+                    url_context: None,
+                    code_execution: None,
+                },
+            });
+        }
+        if url_context_enabled {
+            tools.push(Tool {
+                google_search: None,
+                google_search_retrieval: None,
+                url_context: Some(UrlContext {}),
+                code_execution: None,
+            });
+        }
+        if code_execution_enabled {
+            tools.push(Tool {
+                google_search: None,
+                google_search_retrieval: None,
+                url_context: None,
+                code_execution: Some(CodeExecution {}),
+            });
+        }
+        let tools = if tools.is_empty() { None } else { Some(tools) };
+
+        let generation_config = GenerationConfig {
+            thinking_config: thinking_enabled.map(|enabled| ThinkingConfig {
+                thinking_budget: if enabled {
+                    UNLIMITED_THINKING_BUDGET
+                } else {
+                    0
+                },
+                include_thoughts: enabled,
+            }),
+            response_mime_type: response_mime_type.clone(),
+            response_schema,
+            temperature,
+            stop_sequences,
+            candidate_count,
+            seed,
+            presence_penalty,
+            frequency_penalty,
+        };
+        let generation_config = if generation_config.is_empty() {
+            None
+        } else {
+            Some(generation_config)
+        };
+
+        // A cache carries its own system instruction and the API rejects a second one
+        let system_instruction = if cached_content.is_some() {
+            None
+        } else {
+            gemini_system_instruction(system_instructions)
+        };
+
+        let request = GeminiRequest {
+            system_instruction,
+            contents,
+            tools,
+            generation_config,
+            cached_content,
+        };
+
+        // Encoding a large screenshot takes long enough to look like a hang.
+        emit_if_attached(self.app, REQUEST_PREPARING_EVENT, ());
+        let body = serde_json::to_vec(&request)
+            .map_err(|e| SpotlightError::Parse(format!("Failed to serialize request: {}", e)))?;
+
+        // Log the raw request
+        debug!("Raw Gemini Request: {}", String::from_utf8_lossy(&body));
+
+        let client = reqwest::Client::new();
+        let url = self.settings.endpoint(&format!(
+            "models/{}:{}",
+            self.model, GENERATE_CONTENT_METHOD
+        ));
+        let response = gemini_post(&client, &url, self.api_key, &self.settings.headers)
+            .header(CONTENT_TYPE, "application/json")
+            .body(upload_body(self.app, body))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            if let Some(name) = &request.cached_content {
+                if is_cache_miss(status, &error_text) {
+                    return Err(SpotlightError::CacheExpired(name.clone()));
+                }
+            }
+            return Err(classify_api_error(status, error_text));
+        }
+
+        let response_text = response.text().await?;
+        debug!("Raw Gemini Response: {}", response_text);
+
+        let gemini_response: GeminiResponse = serde_json::from_str(&response_text)
+            .map_err(|e| SpotlightError::Parse(e.to_string()))?;
+
+        // Extract content and separate thinking from main response
+        let candidate = gemini_response.candidates.first().ok_or_else(|| {
+            match gemini_response
+                .prompt_feedback
+                .as_ref()
+                .and_then(|feedback| feedback.block_reason.clone())
+            {
+                Some(reason) => SpotlightError::Blocked(reason),
+                None => SpotlightError::Api("No response from Gemini".to_string()),
+            }
+        })?;
+        if candidate.content.parts.is_empty() {
+            if let Some(reason) = candidate
+                .finish_reason
+                .as_deref()
+                .filter(|reason| BLOCKED_FINISH_REASONS.contains(reason))
+            {
+                return Err(SpotlightError::Blocked(reason.to_string()));
+            }
+        }
+
+        let parts = &candidate.content.parts;
+        let mut thinking_texts = Vec::new();
+        let mut main_texts = Vec::new();
+        let mut code_blocks: Vec<CodeBlock> = Vec::new();
+
+        // Debug: Log the parts structure
+        debug!("Response parts count: {}", parts.len());
+        for (i, part) in parts.iter().enumerate() {
+            debug!("Part {}: text_len={}, thought={:?}", i, part.text.len(), part.thought);
+            if let Some(executable_code) = &part.executable_code {
+                code_blocks.push(CodeBlock {
+                    language: executable_code.language.clone(),
+                    code: executable_code.code.clone(),
+                    outcome: None,
+                    output: None,
+                });
+            } else if let Some(execution_result) = &part.code_execution_result {
+                // Results follow the code that produced them
+                if let Some(block) = code_blocks.last_mut().filter(|block| block.outcome.is_none()) {
+                    block.outcome = Some(execution_result.outcome.clone());
+                    block.output = execution_result.output.clone();
+                }
+            } else if part.thought.unwrap_or(false) {
+                debug!("Found thinking part: {}", &part.text[..100.min(part.text.len())]);
+                thinking_texts.push(part.text.clone());
+            } else {
+                main_texts.push(part.text.clone());
+            }
+        }
+
+        // Combine main texts into the final response
+        let text = if main_texts.is_empty() {
+            thinking_texts.first()
+                .cloned()
+                .ok_or_else(|| SpotlightError::Api("No response from Gemini".to_string()))?
+        } else {
+            main_texts.join("")
+        };
+
+        // Combine thinking texts if any exist
+        let thinking = if thinking_texts.is_empty() {
+            debug!("No thinking content found");
+            None
+        } else {
+            let combined_thinking = thinking_texts.join("");
+            debug!("Combined thinking length: {}", combined_thinking.len());
+            Some(combined_thinking)
+        };
+
+        let grounding_metadata = gemini_response
+            .candidates
+            .first()
+            .and_then(|c| c.grounding_metadata.as_ref())
+            .or(gemini_response.grounding_metadata.as_ref());
+
+        // Extract sources from grounding metadata, remembering which source each
+        // chunk produced so citations can point into `sources`. Chunks that repeat
+        // a URI share the first source seen so the list stays in relevance order.
+        let mut chunk_source_indices: Vec<Option<usize>> = Vec::new();
+        let sources = grounding_metadata
+            .and_then(|metadata| metadata.grounding_chunks.as_ref())
+            .map(|chunks| {
+                let mut sources: Vec<SourceInfo> = Vec::new();
+                let mut seen_uris: HashMap<String, usize> = HashMap::new();
+                for chunk in chunks {
+                    let source = chunk.web.as_ref().and_then(|web| {
+                        web.uri.as_ref().map(|uri| {
+                            let title = web.title.as_ref().map(|t| t.to_string()).unwrap_or_else(|| {
+                                // Fallback to hostname if title not available
+                                uri.split("://")
+                                    .nth(1)
+                                    .and_then(|s| s.split('/').next())
+                                    .unwrap_or(uri)
+                                    .to_string()
+                            });
+                            SourceInfo {
+                                title,
+                                uri: uri.to_string(),
+                            }
+                        })
+                    });
+                    chunk_source_indices.push(source.map(|source| {
+                        *seen_uris
+                            .entry(normalize_source_uri(&source.uri))
+                            .or_insert_with(|| {
+                                sources.push(source);
+                                sources.len() - 1
+                            })
+                    }));
+                }
+                sources
+            });
+
+        let citations = grounding_metadata
+            .and_then(|metadata| metadata.grounding_supports.as_ref())
+            .map(|supports| {
+                supports
+                    .iter()
+                    .filter_map(|support| {
+                        let segment = support.segment.as_ref()?;
+                        let mut source_indices: Vec<usize> = Vec::new();
+                        for &chunk_index in support.grounding_chunk_indices.iter().flatten() {
+                            if let Some(Some(source_index)) = chunk_source_indices.get(chunk_index) {
+                                if !source_indices.contains(source_index) {
+                                    source_indices.push(*source_index);
+                                }
+                            }
+                        }
+                        if source_indices.is_empty() {
+                            return None;
+                        }
+                        Some(Citation {
+                            start_index: segment.start_index,
+                            end_index: segment.end_index,
+                            text: segment.text.clone(),
+                            source_indices,
+                        })
+                    })
+                    .collect::<Vec<Citation>>()
+            })
+            .filter(|citations| !citations.is_empty());
+
+        let search_queries = grounding_metadata
+            .and_then(|metadata| metadata.web_search_queries.clone())
+            .filter(|queries| !queries.is_empty());
+
+        let candidates = if gemini_response.candidates.len() > 1 {
+            Some(
+                gemini_response
+                    .candidates
+                    .iter()
+                    .map(|candidate| {
+                        candidate
+                            .content
+                            .parts
+                            .iter()
+                            .filter(|part| !part.thought.unwrap_or(false))
+                            .map(|part| part.text.as_str())
+                            .collect::<String>()
+                    })
+                    .collect(),
+            )
+        } else {
+            None
+        };
+
+        let result = GeminiResult {
+            text,
+            thinking,
+            sources: if sources.as_ref().is_some_and(|s| !s.is_empty()) {
+                sources
+            } else {
+                None
+            },
+            citations,
+            code_blocks: if code_blocks.is_empty() {
+                None
+            } else {
+                Some(code_blocks)
+            },
+            response_mime_type,
+            model: self.model.clone(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            total_tokens: gemini_response
+                .usage_metadata
+                .as_ref()
+                .map(|usage| usage.total_token_count),
+            candidates,
+            raw_text: None,
+            search_queries,
+        };
+
+        Ok(result)
+    }
+}
+
+fn show_main_window(app: &AppHandle) {
+    if let Err(err) = app.emit("spotlight-show", ()) {
+        error!("Failed to emit show event: {err}");
+    }
+    if let Some(state) = app.try_state::<TrayMenuState>() {
+        state.set_visibility(true);
+    }
+}
+
+fn set_tray_tooltip(app: &AppHandle, tooltip: &str) {
+    if let Some(state) = app.try_state::<TrayMenuState>() {
+        state.set_tooltip(tooltip);
+    }
+}
+
+fn set_tray_status(app: &AppHandle, status: TrayStatus) {
+    if let Some(state) = app.try_state::<TrayMenuState>() {
+        state.set_status(status);
+    }
+}
+
+fn stored_window_tint(app: &AppHandle) -> Option<WindowTint> {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(WINDOW_TINT_KEY))
+        .and_then(|json| serde_json::from_value::<WindowTint>(json).ok())
+}
+
+/// Applies the backdrop to the main window, preferring acrylic and falling back
+/// to blur. Without a saved tint, the default follows the system theme.
+fn apply_main_window_effect(app: &AppHandle, tint: Option<WindowTint>) -> Result<(), SpotlightError> {
+    let Some(window) = app.get_webview_window(MAIN_WINDOW_LABEL) else {
+        return Ok(());
+    };
+    let tint = tint.unwrap_or_else(|| {
+        WindowTint::for_theme(window.theme().unwrap_or(tauri::Theme::Light))
+    });
+
+    #[cfg(target_os = "windows")]
+    {
+        use window_vibrancy::{apply_acrylic, apply_blur};
+
+        let color = Some((tint.r, tint.g, tint.b, tint.a));
+        if apply_acrylic(&window, color).is_err() {
+            apply_blur(&window, color).map_err(|e| {
+                SpotlightError::Platform(format!("Failed to apply window effect: {}", e))
+            })?;
+        }
+    }
+    #[cfg(not(target_os = "windows"))]
+    let _ = tint;
+
+    Ok(())
+}
+
+/// Puts the configured vibrancy material behind a transparent window. Failure
+/// only costs the effect, so the window is left plain.
+#[cfg(target_os = "macos")]
+fn apply_macos_vibrancy(app: &AppHandle, window: &tauri::WebviewWindow) {
+    let material = settings_store(app)
+        .ok()
+        .and_then(|store| store.get(VIBRANCY_MATERIAL_KEY))
+        .and_then(|json| serde_json::from_value::<VibrancyMaterial>(json).ok())
+        .unwrap_or_default();
+    if let Err(err) = window_vibrancy::apply_vibrancy(window, material.ns_material(), None, None) {
+        warn!("Failed to apply vibrancy to {} window: {err}", window.label());
+    }
+}
+
+/// Keeps the window effects, tray icon and frontend in step with the OS appearance.
+fn handle_theme_change(app: &AppHandle, theme: tauri::Theme) {
+    let theme_name = match theme {
+        tauri::Theme::Dark => "dark",
+        _ => "light",
+    };
+    debug!("System theme changed to {}", theme_name);
+
+    // A saved tint is a deliberate choice; only the default follows the theme
+    if stored_window_tint(app).is_none() {
+        if let Err(err) = apply_main_window_effect(app, Some(WindowTint::for_theme(theme))) {
+            error!("{err}");
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        for label in [MAIN_WINDOW_LABEL, SETTINGS_WINDOW_LABEL] {
+            if let Some(window) = app.get_webview_window(label) {
+                apply_macos_vibrancy(app, &window);
+            }
+        }
+        // Template icons are recolored by the menu bar for each appearance
+        if let Some(tray_icon) = app.try_state::<TrayIcon<tauri::Wry>>() {
+            if let Err(err) = tray_icon.set_icon_as_template(true) {
+                error!("Failed to refresh tray icon template flag: {err}");
+            }
+        }
+    }
+
+    if let Err(err) = app.emit(THEME_CHANGED_EVENT, theme_name) {
+        error!("Failed to emit theme changed event: {err}");
+    }
+}
+
+fn stored_always_on_top(app: &AppHandle) -> bool {
+    // The main window is configured always-on-top, so that is the default
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(ALWAYS_ON_TOP_KEY))
+        .and_then(|json| json.as_bool())
+        .unwrap_or(true)
+}
+
+fn apply_always_on_top(app: &AppHandle, enabled: bool) -> Result<(), SpotlightError> {
+    if let Some(window) = app.get_webview_window(MAIN_WINDOW_LABEL) {
+        window
+            .set_always_on_top(enabled)
+            .map_err(|e| SpotlightError::Platform(format!("Failed to update always-on-top: {}", e)))?;
+    }
+    if let Some(state) = app.try_state::<TrayMenuState>() {
+        if let Err(err) = state.always_on_top_item.set_checked(enabled) {
+            error!("Failed to update Always on Top menu item: {err}");
+        }
+    }
+    Ok(())
+}
+
+fn stored_window_opacity(app: &AppHandle) -> Option<f64> {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(WINDOW_OPACITY_KEY))
+        .and_then(|json| json.as_f64())
+        .filter(|level| WINDOW_OPACITY_RANGE.contains(level))
+}
+
+/// Tauri has no cross-platform opacity API, so this goes through the native
+/// window: `alphaValue` on macOS and a layered window on Windows.
+fn apply_window_opacity(app: &AppHandle, level: f64) -> Result<(), SpotlightError> {
+    let Some(window) = app.get_webview_window(MAIN_WINDOW_LABEL) else {
+        return Ok(());
+    };
+
+    #[cfg(target_os = "macos")]
+    {
+        use objc::runtime::Object;
+
+        let ns_window = window.ns_window().map_err(|e| {
+            SpotlightError::Platform(format!("Failed to access native window: {}", e))
+        })? as *mut Object;
+        #[allow(unexpected_cfgs)]
+        let () = unsafe { msg_send![ns_window, setAlphaValue: level] };
+        Ok(())
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        use windows_sys::Win32::UI::WindowsAndMessaging::{
+            GetWindowLongW, SetLayeredWindowAttributes, SetWindowLongW, GWL_EXSTYLE, LWA_ALPHA,
+            WS_EX_LAYERED,
+        };
+
+        let hwnd = window.hwnd().map_err(|e| {
+            SpotlightError::Platform(format!("Failed to access native window: {}", e))
+        })?;
+        let hwnd = hwnd.0 as windows_sys::Win32::Foundation::HWND;
+        let alpha = (level * 255.0).round() as u8;
+        let applied = unsafe {
+            let style = GetWindowLongW(hwnd, GWL_EXSTYLE);
+            SetWindowLongW(hwnd, GWL_EXSTYLE, style | WS_EX_LAYERED as i32);
+            SetLayeredWindowAttributes(hwnd, 0, alpha, LWA_ALPHA)
+        };
+        if applied == 0 {
+            return Err(SpotlightError::Platform(format!(
+                "Failed to set window opacity: {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    {
+        let _ = (window, level);
+        Err(SpotlightError::Unsupported("Window opacity".to_string()))
+    }
+}
+
+fn stored_autostart(app: &AppHandle) -> Option<bool> {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(AUTOSTART_KEY))
+        .and_then(|json| json.as_bool())
+}
+
+fn apply_autostart(app: &AppHandle, enabled: bool) -> Result<(), SpotlightError> {
+    let autolaunch = app.autolaunch();
+    let result = if enabled {
+        autolaunch.enable()
+    } else {
+        autolaunch.disable()
+    };
+    result.map_err(|e| SpotlightError::Platform(format!("Failed to update launch at login: {}", e)))?;
+    if let Some(state) = app.try_state::<TrayMenuState>() {
+        if let Err(err) = state.autostart_item.set_checked(enabled) {
+            error!("Failed to update Launch at Login menu item: {err}");
+        }
+    }
+    Ok(())
+}
+
+fn single_line_preview(text: &str, max_chars: usize) -> String {
+    let single_line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if single_line.chars().count() <= max_chars {
+        single_line
+    } else {
+        let truncated: String = single_line.chars().take(max_chars).collect();
+        format!("{}…", truncated.trim_end())
+    }
+}
+
+fn recent_query_label(query: &str) -> String {
+    single_line_preview(query, RECENT_QUERY_LABEL_MAX_CHARS)
+}
+
+fn stored_notify_on_complete(app: &AppHandle) -> bool {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(NOTIFY_ON_COMPLETE_KEY))
+        .and_then(|json| json.as_bool())
+        .unwrap_or(true)
+}
+
+/// Posts a native notification with a preview of the answer, unless the user
+/// is already looking at the main window.
+fn notify_response_ready(app: &AppHandle, text: &str) {
+    if !stored_notify_on_complete(app) {
+        return;
+    }
+    let is_watching = app
+        .get_webview_window(MAIN_WINDOW_LABEL)
+        .is_some_and(|window| {
+            window.is_visible().unwrap_or(false) && window.is_focused().unwrap_or(false)
+        });
+    if is_watching {
+        return;
+    }
+    // The notification plugin does not report clicks on desktop, so activating
+    // it just focuses the app; the answer is waiting in the main window.
+    if let Err(err) = app
+        .notification()
+        .builder()
+        .title("Spotlight answer ready")
+        .body(single_line_preview(text, NOTIFICATION_PREVIEW_MAX_CHARS))
+        .show()
+    {
+        error!("Failed to show completion notification: {err}");
+    }
+}
+
+fn load_recent_queries(app: &AppHandle) -> VecDeque<String> {
+    settings_store(app)
+        .ok()
+        .and_then(|store| store.get(RECENT_QUERIES_KEY))
+        .and_then(|json| serde_json::from_value::<VecDeque<String>>(json).ok())
+        .map(|mut queries| {
+            queries.truncate(MAX_RECENT_QUERIES);
+            queries
+        })
+        .unwrap_or_default()
+}
+
+fn record_recent_query(app: &AppHandle, query: &str) {
+    let query = query.trim();
+    if query.is_empty() {
+        return;
+    }
+    let Some(state) = app.try_state::<RecentQueriesState>() else {
+        return;
+    };
+    let Ok(mut queries) = state.queries.lock() else {
+        return;
+    };
+
+    queries.retain(|existing| existing != query);
+    queries.push_front(query.to_string());
+    queries.truncate(MAX_RECENT_QUERIES);
+
+    if let Err(err) = state.rebuild_menu(app, &queries) {
+        error!("Failed to rebuild recent queries menu: {err}");
+    }
+    match settings_store(app) {
+        Ok(store) => {
+            store.set(RECENT_QUERIES_KEY, serde_json::json!(*queries));
+            if let Err(err) = store.save() {
+                error!("Failed to save recent queries: {err}");
+            }
+        }
+        Err(err) => error!("Failed to open settings store for recent queries: {err}"),
+    }
+}
+
+fn rerun_recent_query(app: &AppHandle, menu_id: &str) {
+    let Some(index) = menu_id
+        .strip_prefix(MENU_ITEM_RECENT_PREFIX)
+        .and_then(|suffix| suffix.parse::<usize>().ok())
+    else {
+        return;
+    };
+    let prompt = app.try_state::<RecentQueriesState>().and_then(|state| {
+        state
+            .queries
+            .lock()
+            .ok()
+            .and_then(|queries| queries.get(index).cloned())
+    });
+    if let Some(prompt) = prompt {
+        if let Err(err) = app.emit(RERUN_EVENT, RerunPayload { prompt }) {
+            error!("Failed to emit rerun event: {err}");
+        }
+    }
+}
+
+/// Extracts the `q` parameter from `spotlight://ask?q=...`, URL-decoded, with
+/// control characters replaced and the length capped.
+fn parse_deep_link_query(url: &tauri::Url) -> Option<String> {
+    if url.scheme() != DEEP_LINK_SCHEME || url.host_str() != Some(DEEP_LINK_ASK_HOST) {
+        return None;
+    }
+    let raw_query = url
+        .query_pairs()
+        .find(|(name, _)| name == "q")
+        .map(|(_, value)| value.into_owned())?;
+    let query: String = raw_query
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .take(MAX_DEEP_LINK_QUERY_CHARS)
+        .collect();
+    let query = query.trim().to_string();
+    if query.is_empty() {
+        None
+    } else {
+        Some(query)
+    }
+}
+
+fn handle_deep_link_urls(app: &AppHandle, urls: &[tauri::Url]) {
+    let Some(query) = urls.iter().find_map(parse_deep_link_query) else {
+        warn!("Ignoring unrecognized deep link: {:?}", urls);
+        return;
+    };
+    debug!("Received deep link query ({} chars)", query.len());
+    if let Ok(mut pending) = app.state::<DeepLinkState>().pending_query.lock() {
+        *pending = Some(query.clone());
+    }
+    show_main_window(app);
+    if let Err(err) = app.emit(QUERY_EVENT, QueryPayload { query }) {
+        error!("Failed to emit deep link query event: {err}");
+    }
+}
+
+/// Runs an update check triggered from a menu, reporting every outcome as an
+/// event since there is no command caller to return to.
+fn spawn_update_check(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let event_result = match check_for_updates(app.clone()).await {
+            Ok(result) if result.available => Ok(()),
+            Ok(result) => app.emit(UPDATE_NOT_AVAILABLE_EVENT, result),
+            Err(err) => app.emit(UPDATE_CHECK_FAILED_EVENT, err),
+        };
+        if let Err(err) = event_result {
+            error!("Failed to emit update check event: {err}");
+        }
+    });
+}
+
+fn toggle_main_window(app: &AppHandle) {
+    let is_visible = app
+        .get_webview_window(MAIN_WINDOW_LABEL)
+        .and_then(|window| window.is_visible().ok())
+        .unwrap_or(false);
+    if is_visible {
+        hide_main_window(app);
+    } else {
+        show_main_window(app);
+    }
+}
+
+fn stored_shortcut(store: &SettingsStore, key: &str, default: &str) -> String {
+    store
+        .get(key)
+        .and_then(|json| json.as_str().map(|s| s.to_string()))
+        .unwrap_or_else(|| default.to_string())
+}
+
+/// Rewrites `accelerator` in canonical form (e.g. `cmd+shift+p` becomes
+/// `Super+Shift+P`), rejecting combos without a modifier or with anything
+/// other than exactly one key after the modifiers.
+fn normalize_accelerator(accelerator: &str) -> Result<String, SpotlightError> {
+    const MODIFIER_ORDER: [&str; 5] = ["CommandOrControl", "Super", "Control", "Alt", "Shift"];
+
+    let accelerator = accelerator.trim();
+    if accelerator.is_empty() {
+        return Err(SpotlightError::InvalidInput(
+            "Shortcut cannot be empty".to_string(),
+        ));
+    }
+
+    let mut modifiers = Vec::new();
+    let mut keys = Vec::new();
+    let mut problems = Vec::new();
+    for token in accelerator.split('+').map(str::trim) {
+        let modifier = match token.to_ascii_lowercase().as_str() {
+            "" => {
+                problems.push("it has an empty part".to_string());
+                continue;
+            }
+            "commandorcontrol" | "commandorctrl" | "cmdorctrl" | "cmdorcontrol" => "CommandOrControl",
+            "super" | "cmd" | "command" | "win" | "windows" | "meta" => "Super",
+            "control" | "ctrl" => "Control",
+            "alt" | "option" | "opt" => "Alt",
+            "shift" => "Shift",
+            _ => {
+                keys.push(token);
+                continue;
+            }
+        };
+        if !keys.is_empty() {
+            problems.push(format!("modifier '{}' must come before the key", token));
+        }
+        if !modifiers.contains(&modifier) {
+            modifiers.push(modifier);
+        }
+    }
+
+    if modifiers.is_empty() {
+        problems.push("it needs at least one modifier (Ctrl, Alt, Shift or Cmd/Super)".to_string());
+    }
+    match keys.len() {
+        0 => problems.push("it has no key after the modifiers".to_string()),
+        1 => {}
+        _ => problems.push(format!("it has more than one key ({})", keys.join(", "))),
+    }
+    if !problems.is_empty() {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Invalid shortcut '{}': {}",
+            accelerator,
+            problems.join("; ")
+        )));
+    }
+
+    modifiers.sort_by_key(|modifier| MODIFIER_ORDER.iter().position(|known| known == modifier));
+    let key = keys[0];
+    let key = if key.chars().count() == 1 {
+        key.to_uppercase()
+    } else {
+        let mut chars = key.chars();
+        chars
+            .next()
+            .map(|first| first.to_uppercase().chain(chars).collect())
+            .unwrap_or_default()
+    };
+    modifiers.push(&key);
+    Ok(modifiers.join("+"))
+}
+
+fn parse_accelerator(accelerator: &str) -> Result<Shortcut, SpotlightError> {
+    let accelerator = normalize_accelerator(accelerator)?;
+    accelerator.parse::<Shortcut>().map_err(|e| {
+        SpotlightError::InvalidInput(format!("Invalid shortcut '{}': {}", accelerator, e))
+    })
+}
+
+fn register_shortcut(
+    app: &AppHandle,
+    shortcut: Shortcut,
+    accelerator: &str,
+) -> Result<(), SpotlightError> {
+    if app.global_shortcut().is_registered(shortcut) {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Shortcut '{}' is already registered",
+            accelerator
+        )));
+    }
+    app.global_shortcut().register(shortcut).map_err(|e| {
+        let message = e.to_string();
+        let lower = message.to_ascii_lowercase();
+        // global-hotkey's FailedToRegister ("Unable to register hotkey") is the
+        // OS refusing the combo; AlreadyRegistered is a duplicate within this app.
+        // FailedToRegister also covers keys it cannot map, which aren't conflicts.
+        if lower.contains("unable to register hotkey") && !lower.contains("unknown") {
+            SpotlightError::ShortcutTaken(accelerator.to_string())
+        } else if lower.contains("already registered") {
+            SpotlightError::InvalidInput(format!(
+                "Shortcut '{}' is already registered",
+                accelerator
+            ))
+        } else {
+            SpotlightError::Platform(format!(
+                "Failed to register shortcut '{}': {}",
+                accelerator, message
+            ))
+        }
+    })
+}
+
+/// Swaps the shortcut held in `slot` for `accelerator`, registering the new
+/// combo first so a conflict leaves the old one active.
+fn replace_shortcut(
+    app: &AppHandle,
+    slot: &Mutex<Option<Shortcut>>,
+    accelerator: &str,
+) -> Result<(), SpotlightError> {
+    let shortcut = parse_accelerator(accelerator)?;
+    let mut current = slot
+        .lock()
+        .map_err(|_| SpotlightError::Platform("Global shortcut state is poisoned".to_string()))?;
+
+    if *current != Some(shortcut) {
+        register_shortcut(app, shortcut, accelerator)?;
+        if let Some(previous) = current.take() {
+            if let Err(err) = app.global_shortcut().unregister(previous) {
+                error!("Failed to unregister previous global shortcut: {err}");
+            }
+        }
+        *current = Some(shortcut);
+    }
+    Ok(())
+}
+
+/// Registers the persisted (or default) toggle and capture shortcuts.
+fn apply_stored_global_shortcuts(app: &AppHandle) {
+    apply_stored_shortcut(app, GLOBAL_SHORTCUT_KEY, DEFAULT_GLOBAL_SHORTCUT, |state| {
+        &state.current
+    });
+    apply_stored_shortcut(app, CAPTURE_SHORTCUT_KEY, DEFAULT_CAPTURE_SHORTCUT, |state| {
+        &state.capture
+    });
+}
+
+/// Registers the shortcut saved under `key`, falling back to `default` when
+/// the saved combo can no longer be registered.
+fn apply_stored_shortcut(
+    app: &AppHandle,
+    key: &str,
+    default: &str,
+    slot: fn(&GlobalShortcutState) -> &Mutex<Option<Shortcut>>,
+) {
+    let accelerator = settings_store(app)
+        .map(|store| stored_shortcut(&store, key, default))
+        .unwrap_or_else(|_| default.to_string());
+
+    let registered = parse_accelerator(&accelerator)
+        .and_then(|shortcut| register_shortcut(app, shortcut, &accelerator).map(|_| shortcut))
+        .or_else(|err| {
+            error!("Failed to apply saved shortcut {key}: {err}");
+            let shortcut = parse_accelerator(default)?;
+            register_shortcut(app, shortcut, default).map(|_| shortcut)
+        });
+
+    match registered {
+        Ok(shortcut) => {
+            if let Some(state) = app.try_state::<GlobalShortcutState>() {
+                if let Ok(mut current) = slot(&state).lock() {
+                    *current = Some(shortcut);
+                }
+            }
+        }
+        Err(err) => error!("Failed to register shortcut {key}: {err}"),
+    }
+}
+
+fn is_capture_shortcut(app: &AppHandle, shortcut: &Shortcut) -> bool {
+    app.try_state::<GlobalShortcutState>()
+        .and_then(|state| {
+            state
+                .capture
+                .lock()
+                .ok()
+                .map(|capture| *capture == Some(*shortcut))
+        })
+        .unwrap_or(false)
+}
+
+/// Hides the main window, captures the display and brings the window back
+/// with the shot attached via `capture-attached`, ready for a question.
+fn capture_and_attach(app: &AppHandle) {
+    let Some(state) = app.try_state::<CaptureDebounceState>() else {
+        return;
+    };
+    // A held lock means another capture is running right now
+    let busy = match state.last.try_lock() {
+        Ok(last) => last
+            .as_ref()
+            .is_some_and(|(taken_at, _, _)| taken_at.elapsed() < stored_capture_debounce(app)),
+        Err(_) => true,
+    };
+    // Mashing the hotkey would otherwise stack hide/show cycles
+    if busy || state.in_progress.swap(true, Ordering::SeqCst) {
+        debug!("Ignoring capture shortcut while a capture is running");
+        return;
+    }
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        capture_and_attach_inner(&app).await;
+        if let Some(state) = app.try_state::<CaptureDebounceState>() {
+            state.in_progress.store(false, Ordering::SeqCst);
+        }
+    });
+}
+
+async fn capture_and_attach_inner(app: &AppHandle) {
+    let Some(window) = app.get_webview_window(MAIN_WINDOW_LABEL) else {
+        error!("Capture shortcut pressed without a main window");
+        return;
+    };
+    if window.is_visible().unwrap_or(false) {
+        if let Err(err) = window.hide() {
+            error!("Failed to hide main window before capture: {err}");
+        }
+        tokio::time::sleep(Duration::from_millis(CAPTURE_HIDE_SETTLE_MS)).await;
+    }
+
+    let payload = match debounced_capture(app, &window.as_ref().window(), false) {
+        Ok(image_data) => CaptureReadyPayload {
+            image_data: Some(image_data),
+            error: None,
+        },
+        Err(err) => {
+            error!("Capture shortcut failed: {err}");
+            CaptureReadyPayload {
+                image_data: None,
+                error: Some(err.to_string()),
+            }
+        }
+    };
+    show_main_window(app);
+    if let Err(err) = app.emit(CAPTURE_ATTACHED_EVENT, payload) {
+        error!("Failed to emit capture attached event: {err}");
+    }
 }
 
-#[derive(Deserialize, Serialize, Clone)]
-struct WebInfo {
-    uri: Option<String>,
-    title: Option<String>,
+fn hide_main_window(app: &AppHandle) {
+    if let Err(err) = app.emit("spotlight-hide", ()) {
+        error!("Failed to emit hide event: {err}");
+    }
+    if let Some(state) = app.try_state::<TrayMenuState>() {
+        state.set_visibility(false);
+    }
 }
 
-#[derive(Deserialize, Serialize, Clone)]
-struct GroundingChunk {
-    web: Option<WebInfo>,
-}
+fn open_settings_window(app: &AppHandle) -> tauri::Result<()> {
+    if let Some(window) = app.get_webview_window(SETTINGS_WINDOW_LABEL) {
+        // Keep a position the user chose unless the main window moved displays
+        if let Some(monitor) = main_window_monitor(app) {
+            let same_monitor = window
+                .current_monitor()
+                .ok()
+                .flatten()
+                .is_some_and(|current| current.position() == monitor.position());
+            if !same_monitor {
+                if let Ok(size) = window.outer_size() {
+                    let scale = monitor.scale_factor() / window.scale_factor()?;
+                    let size = tauri::PhysicalSize::new(
+                        (f64::from(size.width) * scale).round() as u32,
+                        (f64::from(size.height) * scale).round() as u32,
+                    );
+                    window.set_position(centered_in_work_area(monitor.work_area(), size))?;
+                }
+            }
+        }
+        window.show()?;
+        window.set_focus()?;
+        // Reset the closing state by emitting an event to the frontend
+        if let Err(err) = window.emit("reset-animation-state", ()) {
+            error!("Failed to emit reset event: {err}");
+        }
+        return Ok(());
+    }
 
-#[derive(Deserialize, Serialize, Clone)]
-struct GroundingMetadata {
-    #[serde(rename = "groundingChunks")]
-    grounding_chunks: Option<Vec<GroundingChunk>>,
+    let store = settings_store(app).ok();
+    let size = store
+        .as_ref()
+        .and_then(|store| store.get(SETTINGS_WINDOW_SIZE_KEY))
+        .and_then(|json| serde_json::from_value::<WindowSize>(json).ok())
+        .filter(|size| size.width > 0.0 && size.height > 0.0)
+        .unwrap_or(DEFAULT_SETTINGS_WINDOW_SIZE);
+    let resizable = store
+        .as_ref()
+        .and_then(|store| store.get(SETTINGS_WINDOW_RESIZABLE_KEY))
+        .and_then(|json| json.as_bool())
+        .unwrap_or(false);
+
+    // Opens on the main window's display, or centered on the primary display
+    // when the main window is hidden; later opens reuse the hidden window
+    let monitor = main_window_monitor(app);
+    let builder = WebviewWindowBuilder::new(
+        app,
+        SETTINGS_WINDOW_LABEL,
+        WebviewUrl::App("settings.html".into()),
+    )
+    .title("Spotlight Settings")
+    .inner_size(size.width, size.height)
+    .resizable(resizable)
+    .visible(monitor.is_none())
+    .decorations(false)
+    .transparent(true)
+    .always_on_top(true)
+    .skip_taskbar(true);
+    let settings_window = match &monitor {
+        Some(_) => builder.build()?,
+        None => builder.center().build()?,
+    };
+    if let Some(monitor) = monitor {
+        let size = tauri::LogicalSize::new(size.width, size.height)
+            .to_physical::<u32>(monitor.scale_factor());
+        settings_window.set_position(centered_in_work_area(monitor.work_area(), size))?;
+        settings_window.show()?;
+    }
+
+    settings_window.set_focus()?;
+
+    #[cfg(target_os = "macos")]
+    apply_macos_vibrancy(app, &settings_window);
+
+    // Add event handler to handle settings window close properly
+    let settings_window_for_event = settings_window.clone();
+    let app_handle_for_event = app.clone();
+    settings_window.on_window_event(move |event| match event {
+        WindowEvent::CloseRequested { api, .. } => {
+            // Hide the window instead of closing it to prevent crashes
+            // The animation will play and then the window will be hidden
+            api.prevent_close();
+            if let Err(err) = settings_window_for_event.hide() {
+                error!("Failed to hide settings window: {err}");
+            }
+        }
+        WindowEvent::Resized(physical_size) if resizable => {
+            let Ok(scale_factor) = settings_window_for_event.scale_factor() else {
+                return;
+            };
+            let logical_size = physical_size.to_logical::<f64>(scale_factor);
+            let size = WindowSize {
+                width: logical_size.width,
+                height: logical_size.height,
+            };
+            debounce_window_save(&app_handle_for_event, SETTINGS_WINDOW_LABEL, move |app| {
+                save_settings_window_size(app, size)
+            });
+        }
+        _ => {}
+    });
+
+    Ok(())
 }
 
-#[derive(Deserialize)]
-struct GeminiResponse {
-    candidates: Vec<Candidate>,
-    #[serde(rename = "groundingMetadata")]
-    grounding_metadata: Option<GroundingMetadata>,
+/// The display showing the main window, if it is visible.
+fn main_window_monitor(app: &AppHandle) -> Option<tauri::Monitor> {
+    let window = app.get_webview_window(MAIN_WINDOW_LABEL)?;
+    if !window.is_visible().unwrap_or(false) {
+        return None;
+    }
+    window.current_monitor().ok().flatten()
 }
 
-#[derive(Deserialize)]
-struct Candidate {
-    content: Content,
-    #[serde(rename = "groundingMetadata")]
-    grounding_metadata: Option<GroundingMetadata>,
+/// Top-left corner that centers a window of `size` in `area`, pinned to the
+/// area's top-left when the window is larger than it.
+fn centered_in_work_area(
+    area: &tauri::PhysicalRect<i32, u32>,
+    size: tauri::PhysicalSize<u32>,
+) -> tauri::PhysicalPosition<i32> {
+    let offset = |available: u32, used: u32| (available.saturating_sub(used) / 2) as i32;
+    tauri::PhysicalPosition::new(
+        area.position.x + offset(area.size.width, size.width),
+        area.position.y + offset(area.size.height, size.height),
+    )
 }
 
-#[derive(Deserialize)]
-struct Content {
-    parts: Vec<Part>,
+fn settings_store(
+    app: &AppHandle,
+) -> Result<Arc<tauri_plugin_store::Store<tauri::Wry>>, tauri_plugin_store::Error> {
+    let store_path = get_settings_store_path(app);
+    debug!("Creating settings store with path: {}", store_path);
+    let store = StoreBuilder::new(app, store_path).build()?;
+    debug!("Store built successfully");
+    // ensure cache reflects on-disk contents
+    if let Err(err) = store.reload() {
+        debug!("Failed to reload settings store: {err}");
+        error!("Failed to reload settings store: {err}");
+    } else {
+        debug!("Store reloaded successfully");
+    }
+    if let Err(err) = migrate_store_schema(&store) {
+        error!("Failed to migrate settings store: {err}");
+    }
+    Ok(store)
 }
 
-#[derive(Deserialize)]
-struct Part {
-    text: String,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    thought: Option<bool>,
+/// The settings as the app last loaded or wrote them, without re-reading the
+/// file the way `settings_store` does.
+fn cached_settings(app: &AppHandle) -> serde_json::Map<String, serde_json::Value> {
+    StoreBuilder::new(app, get_settings_store_path(app))
+        .build()
+        .map(|store| store.entries().into_iter().collect())
+        .unwrap_or_default()
 }
 
-#[derive(Serialize, Clone)]
-struct SourceInfo {
-    title: String,
-    uri: String,
+/// Keys added, removed or changed between two settings snapshots, sorted.
+fn changed_settings_keys(
+    before: &serde_json::Map<String, serde_json::Value>,
+    after: &serde_json::Map<String, serde_json::Value>,
+) -> Vec<String> {
+    let mut keys: Vec<String> = before
+        .keys()
+        .chain(after.keys())
+        .filter(|key| before.get(*key) != after.get(*key))
+        .cloned()
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys
 }
 
-#[derive(Serialize)]
-struct GeminiResult {
-    text: String,
-    sources: Option<Vec<SourceInfo>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    thinking: Option<String>,
+/// Watches the settings file for edits made outside the store (by hand, a
+/// script or another process); see [`reload_edited_settings`].
+fn watch_settings_file(app: AppHandle) -> notify::Result<Debouncer<RecommendedWatcher>> {
+    let path = std::path::PathBuf::from(get_settings_store_path(&app));
+    // Watch the directory, since editors often replace the file rather than write it
+    let dir = path
+        .parent()
+        .map(|dir| dir.to_path_buf())
+        .unwrap_or_else(|| path.clone());
+    let mut debouncer = new_debouncer(SETTINGS_WATCH_DEBOUNCE, move |result| {
+        reload_edited_settings(&app, &path, result)
+    })?;
+    debouncer
+        .watcher()
+        .watch(&dir, RecursiveMode::NonRecursive)?;
+    Ok(debouncer)
 }
 
-#[tauri::command]
-async fn send_to_gemini(
-    message: String,
-    image_data: Option<String>,
-    api_key: String,
-    grounding_enabled: Option<bool>,
-    thinking_enabled: Option<bool>,
-    chat_history: Vec<ChatMessage>,
-    system_instructions: Option<String>,
-) -> Result<String, String> {
-    // Build conversation history
-    let mut contents: Vec<GeminiContent> = chat_history
+/// Reloads the settings file after it changed on disk and emits
+/// `settings-changed` with the keys that differ. Writes whose contents match
+/// what the store already holds are the app's own saves and are ignored.
+fn reload_edited_settings(app: &AppHandle, path: &std::path::Path, result: DebounceEventResult) {
+    let events = match result {
+        Ok(events) => events,
+        Err(err) => {
+            error!("Settings watcher failed: {err}");
+            return;
+        }
+    };
+    if !events
         .iter()
-        .map(|msg| {
-            let role = if msg.role == "assistant" {
-                "model".to_string()
-            } else {
-                msg.role.clone()
-            };
-            GeminiContent {
-                role,
-                parts: vec![GeminiPart {
-                    text: Some(msg.content.clone()),
-                    inline_data: None,
-                }],
-            }
-        })
-        .collect();
+        .any(|event| event.path.file_name() == path.file_name())
+    {
+        return;
+    }
+    // A half-written or hand-mangled file is picked up by the next event
+    let Some(on_disk) = std::fs::read(path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+    else {
+        return;
+    };
+    let keys = changed_settings_keys(&cached_settings(app), &on_disk);
+    if keys.is_empty() {
+        return;
+    }
 
-    // Add current message with optional image
-    let mut current_parts = vec![GeminiPart {
-        text: Some(message),
-        inline_data: None,
-    }];
+    debug!("Settings changed on disk: {:?}", keys);
+    let store = match settings_store(app) {
+        Ok(store) => store,
+        Err(err) => {
+            error!("Failed to reload settings after an outside edit: {err}");
+            return;
+        }
+    };
+    if keys
+        .iter()
+        .any(|key| key == PROFILES_KEY || key == ACTIVE_PROFILE_KEY)
+    {
+        emit_api_key_update(app, active_api_key(&store));
+    }
+    if keys.iter().any(|key| key == SYSTEM_INSTRUCTIONS_KEY) {
+        let instructions = store
+            .get(SYSTEM_INSTRUCTIONS_KEY)
+            .and_then(|json| json.as_str().map(|s| s.to_string()));
+        emit_system_instructions_update(app, instructions);
+    }
+    if let Err(err) = app.emit(SETTINGS_CHANGED_EVENT, SettingsChangedPayload { keys }) {
+        error!("Failed to emit settings changed event: {err}");
+    }
+}
 
-    // Add image part if provided
-    if let Some(img_data) = image_data {
-        current_parts.push(GeminiPart {
-            text: None,
-            inline_data: Some(InlineData {
-                mime_type: "image/png".to_string(),
-                data: img_data,
-            }),
-        });
+/// Upgrades an older settings layout in place. Cheap when the store is
+/// already current, so it is safe to run on every load.
+fn migrate_store_schema(store: &SettingsStore) -> Result<(), tauri_plugin_store::Error> {
+    let version = store
+        .get(SCHEMA_VERSION_KEY)
+        .and_then(|json| json.as_u64())
+        .unwrap_or(0);
+    if version == CURRENT_SCHEMA_VERSION {
+        return Ok(());
+    }
+    let mut settings: serde_json::Map<String, serde_json::Value> =
+        store.entries().into_iter().collect();
+    if !migrate_settings(&mut settings) {
+        return Ok(());
+    }
+    info!("Migrated settings from schema {} to {}", version, CURRENT_SCHEMA_VERSION);
+    store.clear();
+    for (key, value) in settings {
+        store.set(key, value);
+    }
+    store.save()
+}
+
+/// The profile name for a key imported from the pre-profiles layout: `default`,
+/// falling back to `legacy`, `legacy-2`, … when that is taken.
+fn unused_profile_name(profiles: &serde_json::Map<String, serde_json::Value>) -> String {
+    std::iter::once(DEFAULT_PROFILE_NAME.to_string())
+        .chain(std::iter::once(LEGACY_PROFILE_NAME.to_string()))
+        .chain((2..).map(|n| format!("{}-{}", LEGACY_PROFILE_NAME, n)))
+        .find(|name| !profiles.contains_key(name))
+        .unwrap_or_default()
+}
+
+/// Brings a raw settings map up to `CURRENT_SCHEMA_VERSION`, returning whether
+/// anything changed. Settings written by a newer version are left alone.
+///
+/// Schema history:
+/// - 0: the API key stored bare under `GEMINI_API_KEY`, no version key.
+/// - 1: keys live in named `PROFILES`, selected by `ACTIVE_PROFILE`.
+fn migrate_settings(settings: &mut serde_json::Map<String, serde_json::Value>) -> bool {
+    let version = settings
+        .get(SCHEMA_VERSION_KEY)
+        .and_then(|json| json.as_u64())
+        .unwrap_or(0);
+    if version > CURRENT_SCHEMA_VERSION {
+        warn!(
+            "Settings use schema {} but this version only knows {}; leaving them untouched",
+            version, CURRENT_SCHEMA_VERSION
+        );
+        return false;
+    }
+    if version == CURRENT_SCHEMA_VERSION {
+        return false;
+    }
+
+    if version < 1 {
+        let legacy_key = settings
+            .get(SETTINGS_STORE_KEY)
+            .and_then(|json| json.as_str().map(|key| key.to_string()))
+            .filter(|key| !key.trim().is_empty());
+        let imported = match legacy_key {
+            Some(legacy_key) => {
+                // Left in plaintext; migrate_api_key_storage moves it to the keychain
+                let profiles = settings
+                    .entry(PROFILES_KEY)
+                    .or_insert_with(|| serde_json::json!({}));
+                profiles.as_object_mut().is_some_and(|profiles| {
+                    let already_saved = profiles
+                        .values()
+                        .any(|profile| profile["apiKey"].as_str() == Some(legacy_key.as_str()));
+                    if !already_saved {
+                        let name = unused_profile_name(profiles);
+                        profiles.insert(name, serde_json::json!({ "apiKey": legacy_key }));
+                    }
+                    true
+                })
+            }
+            None => true,
+        };
+        // Never drop a key that could not be moved into a profile
+        if imported {
+            settings.remove(SETTINGS_STORE_KEY);
+        }
+        let first_profile = settings
+            .get(PROFILES_KEY)
+            .and_then(|json| json.as_object())
+            .and_then(|profiles| profiles.keys().next().cloned());
+        if let (false, Some(name)) = (settings.contains_key(ACTIVE_PROFILE_KEY), first_profile) {
+            settings.insert(ACTIVE_PROFILE_KEY.to_string(), serde_json::json!(name));
+        }
     }
 
-    contents.push(GeminiContent {
-        role: "user".to_string(),
-        parts: current_parts,
+    settings.insert(
+        SCHEMA_VERSION_KEY.to_string(),
+        serde_json::json!(CURRENT_SCHEMA_VERSION),
+    );
+    true
+}
+
+/// Runs `save` once `WINDOW_SAVE_DEBOUNCE` has passed without another call
+/// for the same `window`, so a drag writes settings.json once, not per frame.
+fn debounce_window_save(
+    app: &AppHandle,
+    window: &'static str,
+    save: impl FnOnce(&AppHandle) + Send + 'static,
+) {
+    let generation = {
+        let state = app.state::<WindowSaveDebounceState>();
+        let Ok(mut generations) = state.generations.lock() else {
+            return;
+        };
+        let generation = generations.entry(window).or_default();
+        *generation += 1;
+        *generation
+    };
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        tokio::time::sleep(WINDOW_SAVE_DEBOUNCE).await;
+        let latest = app
+            .state::<WindowSaveDebounceState>()
+            .generations
+            .lock()
+            .ok()
+            .and_then(|generations| generations.get(window).copied());
+        if latest == Some(generation) {
+            save(&app);
+        }
     });
+}
 
-    let tools = if grounding_enabled.unwrap_or(false) {
-        Some(vec![Tool {
-            google_search: Some(GoogleSearch {}),
-        }])
-    } else {
-        None
+fn save_settings_window_size(app: &AppHandle, size: WindowSize) {
+    // Minimizing reports a zero size, which is not worth restoring
+    if size.width <= 0.0 || size.height <= 0.0 {
+        return;
+    }
+    let store = match settings_store(app) {
+        Ok(store) => store,
+        Err(err) => {
+            error!("Failed to open settings store for settings window size: {err}");
+            return;
+        }
     };
+    match serde_json::to_value(size) {
+        Ok(size_json) => store.set(SETTINGS_WINDOW_SIZE_KEY, size_json),
+        Err(err) => {
+            error!("Failed to serialize settings window size: {err}");
+            return;
+        }
+    }
+    if let Err(err) = store.save() {
+        error!("Failed to save settings window size: {err}");
+    }
+}
 
-    let generation_config = if let Some(enabled) = thinking_enabled {
-        Some(GenerationConfig {
-            thinking_config: ThinkingConfig {
-                thinking_budget: if enabled {
-                    UNLIMITED_THINKING_BUDGET
-                } else {
-                    0
-                },
-                include_thoughts: enabled,
-            },
+/// Identifies the set of connected displays, independent of the order the
+/// OS lists them in, so each dock/undock layout remembers its own rect.
+fn monitor_signature(monitors: &[tauri::Monitor]) -> String {
+    let mut displays: Vec<String> = monitors
+        .iter()
+        .map(|monitor| {
+            let position = monitor.position();
+            let size = monitor.size();
+            format!(
+                "{}@{},{}:{}x{}",
+                monitor.name().map(String::as_str).unwrap_or("?"),
+                position.x,
+                position.y,
+                size.width,
+                size.height
+            )
         })
-    } else {
-        None
-    };
+        .collect();
+    displays.sort_unstable();
+    displays.join(";")
+}
 
-    let system_instruction = if let Some(instructions) = system_instructions {
-        if !instructions.trim().is_empty() {
-            Some(SystemInstruction {
-                parts: vec![GeminiPart {
-                    text: Some(instructions),
-                    inline_data: None,
-                }],
-            })
-        } else {
-            None
+fn stored_window_geometries(
+    store: &tauri_plugin_store::Store<tauri::Wry>,
+) -> HashMap<String, WindowGeometry> {
+    store
+        .get(MAIN_WINDOW_GEOMETRIES_KEY)
+        .and_then(|json| serde_json::from_value(json).ok())
+        .unwrap_or_default()
+}
+
+fn save_window_geometry(app: &AppHandle, window: &tauri::WebviewWindow) {
+    let (Ok(position), Ok(size)) = (window.outer_position(), window.inner_size()) else {
+        return;
+    };
+    let Ok(monitors) = window.available_monitors() else {
+        return;
+    };
+    let geometry = WindowGeometry {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+    };
+    let store = match settings_store(app) {
+        Ok(store) => store,
+        Err(err) => {
+            error!("Failed to open settings store for window geometry: {err}");
+            return;
         }
-    } else {
-        None
     };
+    let mut geometries = stored_window_geometries(&store);
+    geometries.insert(monitor_signature(&monitors), geometry);
+    match serde_json::to_value(geometries) {
+        Ok(geometries_json) => {
+            store.set(MAIN_WINDOW_GEOMETRIES_KEY, geometries_json);
+            store.delete(MAIN_WINDOW_GEOMETRY_KEY);
+        }
+        Err(err) => {
+            error!("Failed to serialize window geometry: {err}");
+            return;
+        }
+    }
+    if let Err(err) = store.save() {
+        error!("Failed to save window geometry: {err}");
+    }
+}
 
-    let request = GeminiRequest {
-        system_instruction,
-        contents,
-        tools,
-        generation_config,
+/// Restores the rect saved for the current set of monitors. A layout seen
+/// for the first time (e.g. just undocked) gets a centered window instead,
+/// as does a saved rect that somehow no longer fits on any display.
+fn restore_window_geometry(app: &AppHandle, window: &tauri::WebviewWindow) {
+    let Ok(store) = settings_store(app) else {
+        return;
+    };
+    let monitors = window.available_monitors().unwrap_or_default();
+    let geometry = stored_window_geometries(&store)
+        .remove(&monitor_signature(&monitors))
+        .or_else(|| {
+            store
+                .get(MAIN_WINDOW_GEOMETRY_KEY)
+                .and_then(|json| serde_json::from_value::<WindowGeometry>(json).ok())
+        });
+    let Some(geometry) = geometry else {
+        if let Err(err) = window.center() {
+            error!("Failed to center window: {err}");
+        }
+        return;
     };
 
-    // Log the raw request
-    if let Ok(request_json) = serde_json::to_string(&request) {
-        println!("DEBUG: Raw Gemini Request: {}", request_json);
-    }
+    let fits_on_screen = monitors.iter().any(|monitor| geometry.fits_within(monitor));
 
-    let client = reqwest::Client::new();
-    let url = format!("{}?key={}", GEMINI_API_ENDPOINT, api_key);
+    if let Err(err) = window.set_size(tauri::PhysicalSize::new(geometry.width, geometry.height)) {
+        error!("Failed to restore window size: {err}");
+    }
+    let position_result = if fits_on_screen {
+        window.set_position(tauri::PhysicalPosition::new(geometry.x, geometry.y))
+    } else {
+        window.center()
+    };
+    if let Err(err) = position_result {
+        error!("Failed to restore window position: {err}");
+    }
+}
 
-    let response = client
-        .post(&url)
-        .json(&request)
-        .send()
-        .await
-        .map_err(|e| format!("Request failed: {}", e))?;
+fn emit_api_key_update(app: &AppHandle, value: Option<String>) {
+    // A different key may see a different set of models
+    if let Some(cache) = app.try_state::<ModelCacheState>() {
+        if let Ok(mut models) = cache.models.lock() {
+            *models = None;
+        }
+    }
+    set_tray_status(
+        app,
+        if value.is_some() {
+            TrayStatus::Ready
+        } else {
+            TrayStatus::NoApiKey
+        },
+    );
+    if let Err(err) = app.emit(API_KEY_UPDATED_EVENT, ApiKeyPayload { api_key: value }) {
+        error!("Failed to emit API key update event: {err}");
+    }
+}
 
-    if !response.status().is_success() {
-        let error_text = response.text().await.unwrap_or_default();
-        println!("DEBUG: API Error Response: {}", error_text);
-        return Err(format!("API error: {}", error_text));
+fn emit_system_instructions_update(app: &AppHandle, value: Option<String>) {
+    if let Err(err) = app.emit(SYSTEM_INSTRUCTIONS_UPDATED_EVENT, SystemInstructionsPayload { system_instructions: value }) {
+        error!("Failed to emit system instructions update event: {err}");
     }
+}
 
-    let response_text = response.text().await.map_err(|e| format!("Failed to read response body: {}", e))?;
-    println!("DEBUG: Raw Gemini Response: {}", response_text);
+type SettingsStore = tauri_plugin_store::Store<tauri::Wry>;
 
-    let gemini_response: GeminiResponse = serde_json::from_str(&response_text)
-        .map_err(|e| format!("Failed to parse response: {}", e))?;
+fn keychain_entry(profile_name: &str) -> keyring::Result<keyring::Entry> {
+    keyring::Entry::new(KEYCHAIN_SERVICE, profile_name)
+}
 
-    // Extract content and separate thinking from main response
-    let candidate = gemini_response
-        .candidates
-        .first()
-        .ok_or_else(|| "No response from Gemini".to_string())?;
+fn load_profiles(store: &SettingsStore) -> BTreeMap<String, ApiProfile> {
+    store
+        .get(PROFILES_KEY)
+        .and_then(|json| serde_json::from_value::<BTreeMap<String, ApiProfile>>(json.clone()).ok())
+        .unwrap_or_default()
+}
 
-    let parts = &candidate.content.parts;
-    let mut thinking_texts = Vec::new();
-    let mut main_texts = Vec::new();
+fn active_profile_name(store: &SettingsStore) -> Option<String> {
+    store
+        .get(ACTIVE_PROFILE_KEY)
+        .and_then(|json| json.as_str().map(|s| s.to_string()))
+}
 
-    // Debug: Log the parts structure
-    println!("DEBUG: Response parts count: {}", parts.len());
-    for (i, part) in parts.iter().enumerate() {
-        println!("DEBUG: Part {}: text_len={}, thought={:?}", i, part.text.len(), part.thought);
-        if part.thought.unwrap_or(false) {
-            println!("DEBUG: Found thinking part: {}", &part.text[..100.min(part.text.len())]);
-            thinking_texts.push(part.text.clone());
-        } else {
-            main_texts.push(part.text.clone());
+fn save_profiles(
+    store: &SettingsStore,
+    profiles: &BTreeMap<String, ApiProfile>,
+    active: Option<&str>,
+) -> Result<(), SpotlightError> {
+    let profiles_json = serde_json::to_value(profiles)
+        .map_err(|e| SpotlightError::Store(format!("Failed to serialize profiles: {}", e)))?;
+    store.set(PROFILES_KEY, profiles_json);
+    match active {
+        Some(name) => store.set(ACTIVE_PROFILE_KEY, name),
+        None => {
+            store.delete(ACTIVE_PROFILE_KEY);
         }
     }
+    store.save().map_err(SpotlightError::from)
+}
 
-    // Combine main texts into the final response
-    let text = if main_texts.is_empty() {
-        thinking_texts.first()
-            .cloned()
-            .ok_or_else(|| "No response from Gemini".to_string())?
-    } else {
-        main_texts.join("")
-    };
+fn active_api_key(store: &SettingsStore) -> Option<String> {
+    let backend = store
+        .get(BACKEND_KEY)
+        .and_then(|json| serde_json::from_value::<Backend>(json).ok())
+        .unwrap_or_default();
+    with_env_api_key(&backend, active_profile_api_key(store))
+}
 
-    // Combine thinking texts if any exist
-    let thinking = if thinking_texts.is_empty() {
-        println!("DEBUG: No thinking content found");
-        None
-    } else {
-        let combined_thinking = thinking_texts.join("");
-        println!("DEBUG: Combined thinking length: {}", combined_thinking.len());
-        Some(combined_thinking)
-    };
-
-    // Extract sources from grounding metadata
-    let sources = gemini_response
-        .candidates
-        .first()
-        .and_then(|c| c.grounding_metadata.as_ref())
-        .or(gemini_response.grounding_metadata.as_ref())
-        .and_then(|metadata| metadata.grounding_chunks.as_ref())
-        .map(|chunks| {
-            chunks
-                .iter()
-                .filter_map(|chunk| {
-                    chunk.web.as_ref().and_then(|web| {
-                        web.uri.as_ref().map(|uri| {
-                            let title =
-                                web.title
-                                    .as_ref()
-                                    .map(|t| t.to_string())
-                                    .unwrap_or_else(|| {
-                                        // Fallback to hostname if title not available
-                                        uri.split("://")
-                                            .nth(1)
-                                            .and_then(|s| s.split('/').next())
-                                            .unwrap_or(uri)
-                                            .to_string()
-                                    });
-                            SourceInfo {
-                                title,
-                                uri: uri.to_string(),
-                            }
-                        })
-                    })
-                })
-                .collect::<Vec<SourceInfo>>()
-        });
+/// The active profile's own key, ignoring the environment.
+fn active_profile_api_key(store: &SettingsStore) -> Option<String> {
+    active_profile_name(store).and_then(|active| {
+        load_profiles(store)
+            .get(&active)
+            .and_then(|profile| profile.resolve_api_key(&active))
+    })
+}
 
-    let result = GeminiResult {
-        text,
-        thinking,
-        sources: if sources.as_ref().map_or(false, |s| !s.is_empty()) {
-            sources
-        } else {
-            None
-        },
-    };
+/// Where the key [`active_api_key`] returns comes from.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum ApiKeySource {
+    Profile,
+    Environment,
+    None,
+}
 
-    serde_json::to_string(&result).map_err(|e| format!("Failed to serialize result: {}", e))
+/// Lets the frontend tell a key from `GEMINI_API_KEY` apart from a saved one.
+#[tauri::command]
+fn get_api_key_source(app: AppHandle) -> Result<ApiKeySource, SpotlightError> {
+    let store = settings_store(&app)?;
+    Ok(match active_api_key(&store) {
+        None => ApiKeySource::None,
+        Some(key) if active_profile_api_key(&store).as_ref() == Some(&key) => ApiKeySource::Profile,
+        Some(_) => ApiKeySource::Environment,
+    })
 }
 
-fn show_main_window(app: &AppHandle) {
-    if let Err(err) = app.emit("spotlight-show", ()) {
-        eprintln!("Failed to emit show event: {err}");
-    }
-    if let Some(state) = app.try_state::<TrayMenuState>() {
-        state.set_visibility(true);
-    }
+fn env_api_key() -> Option<String> {
+    std::env::var(API_KEY_ENV_VAR)
+        .ok()
+        .map(|key| key.trim().to_string())
+        .filter(|key| !key.is_empty())
 }
 
-fn hide_main_window(app: &AppHandle) {
-    if let Err(err) = app.emit("spotlight-hide", ()) {
-        eprintln!("Failed to emit hide event: {err}");
+/// Applies the key precedence: a stored key wins over `GEMINI_API_KEY`
+/// unless `GEMINI_API_KEY_OVERRIDE` is set to 1, true or yes. The variable
+/// holds a Gemini key, so other backends only ever get the stored one.
+fn with_env_api_key(backend: &Backend, stored: Option<String>) -> Option<String> {
+    if *backend != Backend::Gemini {
+        return stored;
     }
-    if let Some(state) = app.try_state::<TrayMenuState>() {
-        state.set_visibility(false);
+    let overrides = std::env::var(API_KEY_OVERRIDE_ENV_VAR)
+        .map(|flag| matches!(flag.trim().to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false);
+    if overrides {
+        env_api_key().or(stored)
+    } else {
+        stored.or_else(env_api_key)
     }
 }
 
-fn open_settings_window(app: &AppHandle) -> tauri::Result<()> {
-    if let Some(window) = app.get_webview_window(SETTINGS_WINDOW_LABEL) {
-        window.show()?;
-        window.set_focus()?;
-        // Reset the closing state by emitting an event to the frontend
-        if let Err(err) = window.emit("reset-animation-state", ()) {
-            eprintln!("Failed to emit reset event: {err}");
-        }
+/// Moves plaintext profile keys (including ones carried over from the
+/// pre-profiles layout by [`migrate_settings`]) into the keychain once it
+/// becomes available.
+fn migrate_api_key_storage(store: &SettingsStore) -> Result<(), SpotlightError> {
+    let mut profiles = load_profiles(store);
+    let has_plaintext_keys = profiles
+        .values()
+        .any(|profile| !profile.key_in_keychain && !profile.api_key.is_empty());
+    if !has_plaintext_keys {
         return Ok(());
     }
 
-    let settings_window = WebviewWindowBuilder::new(
-        app,
-        SETTINGS_WINDOW_LABEL,
-        WebviewUrl::App("settings.html".into()),
-    )
-    .title("Spotlight Settings")
-    .inner_size(520.0, 700.0)
-    .resizable(false)
-    .visible(true)
-    .decorations(false)
-    .transparent(true)
-    .center()
-    .always_on_top(true)
-    .skip_taskbar(true)
-    .build()?;
+    for (name, profile) in profiles.iter_mut() {
+        if !profile.key_in_keychain && !profile.api_key.is_empty() {
+            let api_key = std::mem::take(&mut profile.api_key);
+            profile.store_api_key(name, api_key);
+        }
+    }
 
-    settings_window.set_focus()?;
+    let active = active_profile_name(store).or_else(|| profiles.keys().next().cloned());
+    save_profiles(store, &profiles, active.as_deref())
+}
 
-    // Add event handler to handle settings window close properly
-    let settings_window_for_event = settings_window.clone();
-    settings_window.on_window_event(move |event| {
-        if let WindowEvent::CloseRequested { api, .. } = event {
-            // Hide the window instead of closing it to prevent crashes
-            // The animation will play and then the window will be hidden
-            api.prevent_close();
-            if let Err(err) = settings_window_for_event.hide() {
-                eprintln!("Failed to hide settings window: {err}");
-            }
-        }
-    });
+#[tauri::command]
+fn get_api_key(app: AppHandle) -> Result<Option<String>, SpotlightError> {
+    debug!("Getting API key from store...");
+    let store = settings_store(&app)?;
+    let value = active_api_key(&store);
+    debug!("Retrieved API key value: {}", value.is_some());
+    Ok(value)
+}
 
+#[tauri::command]
+fn set_api_key(app: AppHandle, api_key: String) -> Result<(), SpotlightError> {
+    debug!("Setting API key in store...");
+    let store = settings_store(&app)?;
+    let active = active_profile_name(&store).unwrap_or_else(|| DEFAULT_PROFILE_NAME.to_string());
+    let mut profiles = load_profiles(&store);
+    profiles
+        .entry(active.clone())
+        .and_modify(|profile| profile.store_api_key(&active, api_key.clone()))
+        .or_insert_with(|| ApiProfile::new(&active, api_key.clone(), None));
+    debug!("Key set on profile '{}', attempting to save to disk...", active);
+    save_profiles(&store, &profiles, Some(&active))?;
+    debug!("Store saved successfully to disk");
+    emit_api_key_update(&app, Some(api_key));
+    debug!("API key update event emitted");
     Ok(())
 }
 
-fn settings_store(
-    app: &AppHandle,
-) -> Result<Arc<tauri_plugin_store::Store<tauri::Wry>>, tauri_plugin_store::Error> {
-    let store_path = get_settings_store_path(app);
-    println!("DEBUG: Creating settings store with path: {}", store_path);
-    let store = StoreBuilder::new(app, store_path).build()?;
-    println!("DEBUG: Store built successfully");
-    // ensure cache reflects on-disk contents
-    if let Err(err) = store.reload() {
-        println!("DEBUG: Failed to reload settings store: {err}");
-        eprintln!("Failed to reload settings store: {err}");
-    } else {
-        println!("DEBUG: Store reloaded successfully");
+#[tauri::command]
+fn clear_api_key(app: AppHandle) -> Result<(), SpotlightError> {
+    debug!("Clearing API key from store...");
+    let store = settings_store(&app)?;
+    let mut profiles = load_profiles(&store);
+    let active = active_profile_name(&store);
+    if let Some(active) = &active {
+        debug!("Clearing the key of active profile '{}'...", active);
+        if let Some(profile) = profiles.get_mut(active) {
+            profile.clear_api_key(active);
+        }
     }
-    Ok(store)
+    save_profiles(&store, &profiles, active.as_deref())?;
+    debug!("Store saved successfully to disk");
+    emit_api_key_update(&app, active_api_key(&store));
+    debug!("API key clear event emitted");
+    Ok(())
 }
 
-fn emit_api_key_update(app: &AppHandle, value: Option<String>) {
-    if let Err(err) = app.emit(API_KEY_UPDATED_EVENT, ApiKeyPayload { api_key: value }) {
-        eprintln!("Failed to emit API key update event: {err}");
-    }
+#[tauri::command]
+fn list_profiles(app: AppHandle) -> Result<Vec<ProfileSummary>, SpotlightError> {
+    let store = settings_store(&app)?;
+    let active = active_profile_name(&store);
+    let summaries = load_profiles(&store)
+        .into_iter()
+        .map(|(name, profile)| ProfileSummary {
+            is_active: active.as_deref() == Some(name.as_str()),
+            name,
+            model: profile.model,
+        })
+        .collect();
+    Ok(summaries)
 }
 
-fn emit_system_instructions_update(app: &AppHandle, value: Option<String>) {
-    if let Err(err) = app.emit(SYSTEM_INSTRUCTIONS_UPDATED_EVENT, SystemInstructionsPayload { system_instructions: value }) {
-        eprintln!("Failed to emit system instructions update event: {err}");
+#[tauri::command]
+fn add_profile(
+    app: AppHandle,
+    name: String,
+    api_key: String,
+    model: Option<String>,
+) -> Result<(), SpotlightError> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(SpotlightError::InvalidInput(
+            "Profile name cannot be empty".to_string(),
+        ));
     }
-}
 
-#[tauri::command]
-fn get_api_key(app: AppHandle) -> Result<Option<String>, String> {
-    println!("DEBUG: Getting API key from store...");
-    let store = settings_store(&app).map_err(|e| format!("Failed to create settings store: {}", e))?;
-    let value = store
-        .get(SETTINGS_STORE_KEY)
-        .and_then(|json| json.as_str().map(|s| s.to_string()));
-    println!("DEBUG: Retrieved API key value: {}", value.is_some());
-    Ok(value)
+    debug!("Adding profile: {}", name);
+    let store = settings_store(&app)?;
+    let mut profiles = load_profiles(&store);
+    if let Some(existing) = profiles.get(&name) {
+        existing.forget_api_key(&name);
+    }
+    let profile = ApiProfile::new(&name, api_key, model);
+    profiles.insert(name.clone(), profile);
+
+    // The first profile becomes active so the app is usable immediately
+    let active = active_profile_name(&store).unwrap_or_else(|| name.clone());
+    save_profiles(&store, &profiles, Some(&active))?;
+    if active == name {
+        emit_api_key_update(&app, active_api_key(&store));
+    }
+    Ok(())
 }
 
 #[tauri::command]
-fn set_api_key(app: AppHandle, api_key: String) -> Result<(), String> {
-    println!("DEBUG: Setting API key in store...");
-    let store = settings_store(&app).map_err(|e| format!("Failed to create settings store: {}", e))?;
-    println!("DEBUG: Store created successfully, setting key...");
-    store.set(SETTINGS_STORE_KEY, api_key.clone());
-    println!("DEBUG: Key set in memory, attempting to save to disk...");
-    store.save().map_err(|e| {
-        println!("DEBUG: Store save failed with error: {:?}", e);
-        format!("Failed to save store: {}", e)
-    })?;
-    println!("DEBUG: Store saved successfully to disk");
-    emit_api_key_update(&app, Some(api_key));
-    println!("DEBUG: API key update event emitted");
+fn set_active_profile(app: AppHandle, name: String) -> Result<(), SpotlightError> {
+    debug!("Switching active profile to: {}", name);
+    let store = settings_store(&app)?;
+    let profiles = load_profiles(&store);
+    if !profiles.contains_key(&name) {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Profile '{}' does not exist",
+            name
+        )));
+    }
+    save_profiles(&store, &profiles, Some(&name))?;
+    emit_api_key_update(&app, active_api_key(&store));
     Ok(())
 }
 
 #[tauri::command]
-fn clear_api_key(app: AppHandle) -> Result<(), String> {
-    println!("DEBUG: Clearing API key from store...");
-    let store = settings_store(&app).map_err(|e| format!("Failed to create settings store: {}", e))?;
-    println!("DEBUG: Store created successfully, deleting key...");
-    store.delete(SETTINGS_STORE_KEY);
-    println!("DEBUG: Key deleted from memory, attempting to save to disk...");
-    store.save().map_err(|e| {
-        println!("DEBUG: Store save failed with error: {:?}", e);
-        format!("Failed to save store after clearing: {}", e)
-    })?;
-    println!("DEBUG: Store saved successfully to disk");
-    emit_api_key_update(&app, None);
-    println!("DEBUG: API key clear event emitted");
+fn delete_profile(app: AppHandle, name: String) -> Result<(), SpotlightError> {
+    debug!("Deleting profile: {}", name);
+    let store = settings_store(&app)?;
+    let mut profiles = load_profiles(&store);
+    let Some(removed) = profiles.remove(&name) else {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Profile '{}' does not exist",
+            name
+        )));
+    };
+    removed.forget_api_key(&name);
+
+    let active = active_profile_name(&store);
+    let was_active = active.as_deref() == Some(name.as_str());
+    let next_active = if was_active {
+        profiles.keys().next().cloned()
+    } else {
+        active
+    };
+    save_profiles(&store, &profiles, next_active.as_deref())?;
+    if was_active {
+        emit_api_key_update(&app, active_api_key(&store));
+    }
     Ok(())
 }
 
 #[tauri::command]
-fn get_system_instructions(app: AppHandle) -> Result<Option<String>, String> {
-    println!("DEBUG: Getting system instructions from store...");
-    let store = settings_store(&app).map_err(|e| format!("Failed to create settings store: {}", e))?;
+fn get_system_instructions(app: AppHandle) -> Result<Option<String>, SpotlightError> {
+    debug!("Getting system instructions from store...");
+    let store = settings_store(&app)?;
     let value = store
         .get(SYSTEM_INSTRUCTIONS_KEY)
         .and_then(|json| json.as_str().map(|s| s.to_string()));
-    println!("DEBUG: Retrieved system instructions value: {}", value.is_some());
+    debug!("Retrieved system instructions value: {}", value.is_some());
     Ok(value)
 }
 
 #[tauri::command]
-fn set_system_instructions(app: AppHandle, instructions: String) -> Result<(), String> {
-    println!("DEBUG: Setting system instructions in store...");
-    let store = settings_store(&app).map_err(|e| format!("Failed to create settings store: {}", e))?;
-    println!("DEBUG: Store created successfully, setting instructions...");
+fn set_system_instructions(app: AppHandle, instructions: String) -> Result<(), SpotlightError> {
+    debug!("Setting system instructions in store...");
+    let store = settings_store(&app)?;
+    debug!("Store created successfully, setting instructions...");
     store.set(SYSTEM_INSTRUCTIONS_KEY, instructions.clone());
-    println!("DEBUG: Instructions set in memory, attempting to save to disk...");
+    debug!("Instructions set in memory, attempting to save to disk...");
     store.save().map_err(|e| {
-        println!("DEBUG: Store save failed with error: {:?}", e);
-        format!("Failed to save store: {}", e)
+        debug!("Store save failed with error: {:?}", e);
+        SpotlightError::from(e)
     })?;
-    println!("DEBUG: Store saved successfully to disk");
+    debug!("Store saved successfully to disk");
     emit_system_instructions_update(&app, Some(instructions));
-    println!("DEBUG: System instructions update event emitted");
+    debug!("System instructions update event emitted");
     Ok(())
 }
 
 #[tauri::command]
-fn clear_system_instructions(app: AppHandle) -> Result<(), String> {
-    println!("DEBUG: Clearing system instructions from store...");
-    let store = settings_store(&app).map_err(|e| format!("Failed to create settings store: {}", e))?;
-    println!("DEBUG: Store created successfully, deleting instructions...");
+fn clear_system_instructions(app: AppHandle) -> Result<(), SpotlightError> {
+    debug!("Clearing system instructions from store...");
+    let store = settings_store(&app)?;
+    debug!("Store created successfully, deleting instructions...");
     store.delete(SYSTEM_INSTRUCTIONS_KEY);
-    println!("DEBUG: Instructions deleted from memory, attempting to save to disk...");
+    debug!("Instructions deleted from memory, attempting to save to disk...");
     store.save().map_err(|e| {
-        println!("DEBUG: Store save failed with error: {:?}", e);
-        format!("Failed to save store after clearing: {}", e)
+        debug!("Store save failed with error: {:?}", e);
+        SpotlightError::from(e)
     })?;
-    println!("DEBUG: Store saved successfully to disk");
+    debug!("Store saved successfully to disk");
+    emit_system_instructions_update(&app, None);
+    debug!("System instructions clear event emitted");
+    Ok(())
+}
+
+/// Erases every API key, conversation and setting, returning the app to a
+/// first-run state. `confirm` must be `true` so a stray call cannot wipe data.
+#[tauri::command]
+fn reset_all_data(app: AppHandle, confirm: bool) -> Result<(), SpotlightError> {
+    if !confirm {
+        return Err(SpotlightError::InvalidInput(
+            "Resetting erases all keys, history and settings; pass confirm to proceed".to_string(),
+        ));
+    }
+    info!("Resetting all data...");
+    let store = settings_store(&app)?;
+    // Keychain entries outlive the store, so remove them while profiles are known
+    for (name, profile) in load_profiles(&store) {
+        profile.forget_api_key(&name);
+    }
+    store.clear();
+    store.save()?;
+    let conversations = conversations_store(&app)?;
+    conversations.clear();
+    conversations.save()?;
+    clear_response_cache(&app)?;
+    let stats = usage_stats_store(&app)?;
+    stats.clear();
+    stats.save()?;
+
+    // The window keeps its current look until told about the restored defaults
+    if let Err(err) = apply_always_on_top(&app, stored_always_on_top(&app)) {
+        error!("Failed to restore always-on-top during reset: {err}");
+    }
+    if let Err(err) = apply_window_opacity(&app, *WINDOW_OPACITY_RANGE.end()) {
+        error!("Failed to restore window opacity during reset: {err}");
+    }
+
+    if let Some(state) = app.try_state::<SpeechState>() {
+        if let Err(err) = stop_speaking(state) {
+            error!("Failed to stop speaking during reset: {err}");
+        }
+    }
+    if let Some(state) = app.try_state::<RecentQueriesState>() {
+        if let Ok(mut queries) = state.queries.lock() {
+            queries.clear();
+            if let Err(err) = state.rebuild_menu(&app, &queries) {
+                error!("Failed to rebuild recent queries menu: {err}");
+            }
+        }
+    }
+    if let Some(state) = app.try_state::<TrayMenuState>() {
+        state.set_left_click_behavior(TrayLeftClickBehavior::default());
+    }
+    if let Some(state) = app.try_state::<GlobalShortcutState>() {
+        for (slot, default) in [
+            (&state.current, DEFAULT_GLOBAL_SHORTCUT),
+            (&state.capture, DEFAULT_CAPTURE_SHORTCUT),
+        ] {
+            if let Err(err) = replace_shortcut(&app, slot, default) {
+                error!("Failed to restore default shortcut {default}: {err}");
+            }
+        }
+    }
+
+    emit_api_key_update(&app, None);
     emit_system_instructions_update(&app, None);
-    println!("DEBUG: System instructions clear event emitted");
+    if let Err(err) = app.emit(DATA_RESET_EVENT, ()) {
+        error!("Failed to emit data reset event: {err}");
+    }
     Ok(())
 }
 
 #[tauri::command]
-fn get_instruction_presets(app: AppHandle) -> Result<Vec<InstructionPreset>, String> {
-    println!("DEBUG: Getting instruction presets from store...");
-    let store = settings_store(&app).map_err(|e| format!("Failed to create settings store: {}", e))?;
+fn get_instruction_presets(app: AppHandle) -> Result<Vec<InstructionPreset>, SpotlightError> {
+    debug!("Getting instruction presets from store...");
+    let store = settings_store(&app)?;
     let presets = store
         .get(SYSTEM_INSTRUCTIONS_PRESETS_KEY)
         .and_then(|json| serde_json::from_value::<Vec<InstructionPreset>>(json.clone()).ok())
         .unwrap_or_default();
-    println!("DEBUG: Retrieved {} presets", presets.len());
+    debug!("Retrieved {} presets", presets.len());
     Ok(presets)
 }
 
 #[tauri::command]
-fn save_instruction_preset(app: AppHandle, preset: InstructionPreset) -> Result<(), String> {
-    println!("DEBUG: Saving instruction preset: {}", preset.name);
-    let store = settings_store(&app).map_err(|e| format!("Failed to create settings store: {}", e))?;
+fn save_instruction_preset(app: AppHandle, preset: InstructionPreset) -> Result<(), SpotlightError> {
+    debug!("Saving instruction preset: {}", preset.name);
+    let store = settings_store(&app)?;
     
     let mut presets = store
         .get(SYSTEM_INSTRUCTIONS_PRESETS_KEY)
@@ -796,38 +8867,681 @@ fn save_instruction_preset(app: AppHandle, preset: InstructionPreset) -> Result<
     presets.push(preset);
     
     let presets_json = serde_json::to_value(&presets)
-        .map_err(|e| format!("Failed to serialize presets: {}", e))?;
+        .map_err(|e| SpotlightError::Store(format!("Failed to serialize presets: {}", e)))?;
+    
+    store.set(SYSTEM_INSTRUCTIONS_PRESETS_KEY, presets_json);
+    store.save().map_err(SpotlightError::from)?;
+    debug!("Preset saved successfully");
+    Ok(())
+}
+
+#[tauri::command]
+fn delete_instruction_preset(app: AppHandle, preset_id: String) -> Result<(), SpotlightError> {
+    debug!("Deleting instruction preset: {}", preset_id);
+    let store = settings_store(&app)?;
+    
+    let mut presets = store
+        .get(SYSTEM_INSTRUCTIONS_PRESETS_KEY)
+        .and_then(|json| serde_json::from_value::<Vec<InstructionPreset>>(json.clone()).ok())
+        .unwrap_or_default();
+    
+    presets.retain(|p| p.id != preset_id);
+    
+    let presets_json = serde_json::to_value(&presets)
+        .map_err(|e| SpotlightError::Store(format!("Failed to serialize presets: {}", e)))?;
     
     store.set(SYSTEM_INSTRUCTIONS_PRESETS_KEY, presets_json);
-    store.save().map_err(|e| format!("Failed to save store: {}", e))?;
-    println!("DEBUG: Preset saved successfully");
+    store.save().map_err(SpotlightError::from)?;
+    debug!("Preset deleted successfully");
     Ok(())
 }
 
-#[tauri::command]
-fn delete_instruction_preset(app: AppHandle, preset_id: String) -> Result<(), String> {
-    println!("DEBUG: Deleting instruction preset: {}", preset_id);
-    let store = settings_store(&app).map_err(|e| format!("Failed to create settings store: {}", e))?;
-    
-    let mut presets = store
-        .get(SYSTEM_INSTRUCTIONS_PRESETS_KEY)
-        .and_then(|json| serde_json::from_value::<Vec<InstructionPreset>>(json.clone()).ok())
-        .unwrap_or_default();
-    
-    presets.retain(|p| p.id != preset_id);
-    
-    let presets_json = serde_json::to_value(&presets)
-        .map_err(|e| format!("Failed to serialize presets: {}", e))?;
-    
-    store.set(SYSTEM_INSTRUCTIONS_PRESETS_KEY, presets_json);
-    store.save().map_err(|e| format!("Failed to save store: {}", e))?;
-    println!("DEBUG: Preset deleted successfully");
-    Ok(())
+type ConversationsStore = tauri_plugin_store::Store<tauri::Wry>;
+
+fn conversations_store(
+    app: &AppHandle,
+) -> Result<Arc<ConversationsStore>, tauri_plugin_store::Error> {
+    StoreBuilder::new(app, app_data_file_path(app, CONVERSATIONS_FILE_NAME)).build()
+}
+
+/// Moves conversations saved in settings.json by earlier versions into their
+/// own file. Settings only lose them once the new file has been written.
+fn move_conversations_out_of_settings(
+    settings: &SettingsStore,
+    conversations: &ConversationsStore,
+) -> Result<(), tauri_plugin_store::Error> {
+    let keys = [CONVERSATIONS_KEY, ACTIVE_CONVERSATION_KEY];
+    if !keys.iter().any(|key| settings.has(key)) {
+        return Ok(());
+    }
+    for key in keys {
+        if let Some(value) = settings.get(key).filter(|_| !conversations.has(key)) {
+            conversations.set(key, value);
+        }
+    }
+    conversations.save()?;
+    for key in keys {
+        settings.delete(key);
+    }
+    settings.save()
+}
+
+/// Loads saved conversations, starting with an empty default conversation so
+/// there is always one to switch back to.
+fn load_conversations(store: &ConversationsStore) -> Vec<Conversation> {
+    let conversations = store
+        .get(CONVERSATIONS_KEY)
+        .and_then(|json| serde_json::from_value::<Vec<Conversation>>(json.clone()).ok())
+        .unwrap_or_default();
+    if conversations.is_empty() {
+        return vec![Conversation {
+            id: DEFAULT_CONVERSATION_ID.to_string(),
+            title: DEFAULT_CONVERSATION_TITLE.to_string(),
+            system_prompt: None,
+            messages: Vec::new(),
+            tags: Vec::new(),
+            updated_at: None,
+        }];
+    }
+    conversations
+}
+
+/// The active conversation's id, falling back to the first saved one.
+fn active_conversation_id(store: &ConversationsStore, conversations: &[Conversation]) -> String {
+    store
+        .get(ACTIVE_CONVERSATION_KEY)
+        .and_then(|json| json.as_str().map(|s| s.to_string()))
+        .filter(|id| conversations.iter().any(|conversation| &conversation.id == id))
+        .unwrap_or_else(|| conversations[0].id.clone())
+}
+
+fn save_conversations(
+    store: &ConversationsStore,
+    conversations: &[Conversation],
+    active: &str,
+) -> Result<(), SpotlightError> {
+    let conversations_json = serde_json::to_value(conversations)
+        .map_err(|e| SpotlightError::Store(format!("Failed to serialize conversations: {}", e)))?;
+    store.set(CONVERSATIONS_KEY, conversations_json);
+    store.set(ACTIVE_CONVERSATION_KEY, active);
+    store.save().map_err(SpotlightError::from)
+}
+
+fn find_conversation<'a>(
+    conversations: &'a mut [Conversation],
+    id: &str,
+) -> Result<&'a mut Conversation, SpotlightError> {
+    conversations
+        .iter_mut()
+        .find(|conversation| conversation.id == id)
+        .ok_or_else(|| {
+            SpotlightError::InvalidInput(format!("Conversation '{}' does not exist", id))
+        })
+}
+
+/// System prompt of a saved conversation, if it has a non-empty one.
+fn conversation_system_prompt(store: &ConversationsStore, id: &str) -> Option<String> {
+    load_conversations(store)
+        .into_iter()
+        .find(|conversation| conversation.id == id)
+        .and_then(|conversation| conversation.system_prompt)
+        .filter(|prompt| !prompt.trim().is_empty())
+}
+
+/// Lists saved conversations, keeping only those tagged with every one of
+/// `filter_tags` when given.
+#[tauri::command]
+fn list_conversations(
+    app: AppHandle,
+    filter_tags: Option<Vec<String>>,
+) -> Result<Vec<ConversationSummary>, SpotlightError> {
+    let store = conversations_store(&app)?;
+    let conversations = load_conversations(&store);
+    let active = active_conversation_id(&store, &conversations);
+    let filter_tags = normalize_tags(filter_tags.unwrap_or_default());
+    Ok(conversations
+        .iter()
+        .filter(|conversation| {
+            filter_tags
+                .iter()
+                .all(|tag| conversation.tags.contains(tag))
+        })
+        .map(|conversation| ConversationSummary {
+            id: conversation.id.clone(),
+            title: conversation.title.clone(),
+            tags: conversation.tags.clone(),
+            updated_at: conversation.updated_at.clone(),
+            message_count: conversation.messages.len(),
+            is_active: conversation.id == active,
+        })
+        .collect())
+}
+
+#[tauri::command]
+fn get_active_conversation(app: AppHandle) -> Result<Conversation, SpotlightError> {
+    let store = conversations_store(&app)?;
+    let mut conversations = load_conversations(&store);
+    let active = active_conversation_id(&store, &conversations);
+    Ok(find_conversation(&mut conversations, &active)?.clone())
+}
+
+/// Creates an empty conversation and makes it the active one.
+#[tauri::command]
+fn create_conversation(
+    app: AppHandle,
+    title: Option<String>,
+    system_prompt: Option<String>,
+) -> Result<Conversation, SpotlightError> {
+    let store = conversations_store(&app)?;
+    let mut conversations = load_conversations(&store);
+    let title = title
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty())
+        .unwrap_or_else(|| DEFAULT_CONVERSATION_TITLE.to_string());
+    let now = chrono::Utc::now();
+    let conversation = Conversation {
+        id: format!("conversation-{}", uuid::Uuid::new_v4()),
+        title,
+        system_prompt: system_prompt.filter(|prompt| !prompt.trim().is_empty()),
+        messages: Vec::new(),
+        tags: Vec::new(),
+        updated_at: Some(now.to_rfc3339()),
+    };
+    debug!("Creating conversation: {}", conversation.id);
+    conversations.push(conversation.clone());
+    save_conversations(&store, &conversations, &conversation.id)?;
+    Ok(conversation)
+}
+
+#[tauri::command]
+fn rename_conversation(app: AppHandle, id: String, title: String) -> Result<(), SpotlightError> {
+    let title = title.trim();
+    if title.is_empty() {
+        return Err(SpotlightError::InvalidInput(
+            "Conversation title cannot be empty".to_string(),
+        ));
+    }
+    let store = conversations_store(&app)?;
+    let mut conversations = load_conversations(&store);
+    let active = active_conversation_id(&store, &conversations);
+    find_conversation(&mut conversations, &id)?.title = title.to_string();
+    save_conversations(&store, &conversations, &active)
+}
+
+/// Makes `id` the active conversation and returns it so the frontend can load
+/// its messages.
+#[tauri::command]
+fn switch_conversation(app: AppHandle, id: String) -> Result<Conversation, SpotlightError> {
+    debug!("Switching to conversation: {}", id);
+    let store = conversations_store(&app)?;
+    let mut conversations = load_conversations(&store);
+    let conversation = find_conversation(&mut conversations, &id)?.clone();
+    save_conversations(&store, &conversations, &id)?;
+    Ok(conversation)
+}
+
+#[tauri::command]
+fn set_conversation_system_prompt(
+    app: AppHandle,
+    id: String,
+    system_prompt: Option<String>,
+) -> Result<(), SpotlightError> {
+    let store = conversations_store(&app)?;
+    let mut conversations = load_conversations(&store);
+    let active = active_conversation_id(&store, &conversations);
+    find_conversation(&mut conversations, &id)?.system_prompt =
+        system_prompt.filter(|prompt| !prompt.trim().is_empty());
+    save_conversations(&store, &conversations, &active)
+}
+
+#[tauri::command]
+fn save_conversation_messages(
+    app: AppHandle,
+    id: String,
+    messages: Vec<ChatMessage>,
+) -> Result<(), SpotlightError> {
+    let store = conversations_store(&app)?;
+    let mut conversations = load_conversations(&store);
+    let active = active_conversation_id(&store, &conversations);
+    let conversation = find_conversation(&mut conversations, &id)?;
+    conversation.messages = messages;
+    conversation.updated_at = Some(chrono::Utc::now().to_rfc3339());
+    save_conversations(&store, &conversations, &active)
+}
+
+/// Replaces a conversation's tags; see [`normalize_tags`].
+#[tauri::command]
+fn set_conversation_tags(
+    app: AppHandle,
+    id: String,
+    tags: Vec<String>,
+) -> Result<(), SpotlightError> {
+    let store = conversations_store(&app)?;
+    let mut conversations = load_conversations(&store);
+    let active = active_conversation_id(&store, &conversations);
+    find_conversation(&mut conversations, &id)?.tags = normalize_tags(tags);
+    save_conversations(&store, &conversations, &active)
+}
+
+/// Trims and lowercases tags so "Research " and "research" are one tag, then
+/// drops empties and duplicates, keeping first-seen order.
+fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+/// Removes the message at `message_index` together with the other half of
+/// its question/answer pair, then returns the updated conversation.
+#[tauri::command]
+fn delete_message(
+    app: AppHandle,
+    conversation_id: String,
+    message_index: usize,
+) -> Result<Conversation, SpotlightError> {
+    let store = conversations_store(&app)?;
+    let mut conversations = load_conversations(&store);
+    let active = active_conversation_id(&store, &conversations);
+    let conversation = find_conversation(&mut conversations, &conversation_id)?;
+    let removed = remove_message_pair(&mut conversation.messages, message_index)?;
+    conversation.updated_at = Some(chrono::Utc::now().to_rfc3339());
+    debug!(
+        "Deleted messages {:?} from conversation {}",
+        removed, conversation_id
+    );
+    let conversation = conversation.clone();
+    save_conversations(&store, &conversations, &active)?;
+    Ok(conversation)
+}
+
+/// Removes `messages[index]` and its partner: the answer after a user turn,
+/// or the question before an answer. Returns the range that was removed.
+fn remove_message_pair(
+    messages: &mut Vec<ChatMessage>,
+    index: usize,
+) -> Result<std::ops::Range<usize>, SpotlightError> {
+    let Some(message) = messages.get(index) else {
+        return Err(SpotlightError::InvalidInput(format!(
+            "Message {} does not exist in a conversation of {} messages",
+            index,
+            messages.len()
+        )));
+    };
+    let is_answer = |message: &ChatMessage| matches!(message.role.as_str(), "assistant" | "model");
+    let range = if message.role == "user" {
+        let answered = messages.get(index + 1).is_some_and(is_answer);
+        index..index + 1 + usize::from(answered)
+    } else if is_answer(message) {
+        let asked = index
+            .checked_sub(1)
+            .and_then(|previous| messages.get(previous))
+            .is_some_and(|previous| previous.role == "user");
+        index - usize::from(asked)..index + 1
+    } else {
+        index..index + 1
+    };
+    messages.drain(range.clone());
+    Ok(range)
+}
+
+/// Case-insensitive search over every saved message. Words may match
+/// anywhere; `"quoted phrases"` must match as written. A message has to
+/// contain every term, and hits are ordered by how often the terms occur.
+#[tauri::command]
+fn search_history(app: AppHandle, query: String) -> Result<Vec<SearchHit>, SpotlightError> {
+    let store = conversations_store(&app)?;
+    Ok(search_conversations(&load_conversations(&store), &query))
+}
+
+fn search_conversations(conversations: &[Conversation], query: &str) -> Vec<SearchHit> {
+    let terms = parse_search_terms(query);
+    if terms.is_empty() {
+        return Vec::new();
+    }
+    let mut hits = Vec::new();
+    for conversation in conversations {
+        for (message_index, message) in conversation.messages.iter().enumerate() {
+            let mut matches = Vec::new();
+            for term in &terms {
+                let term_matches = find_case_insensitive(&message.content, term);
+                if term_matches.is_empty() {
+                    break;
+                }
+                matches.push(term_matches);
+            }
+            if matches.len() < terms.len() {
+                continue;
+            }
+            let mut matches: Vec<_> = matches.into_iter().flatten().collect();
+            matches.sort_by_key(|range| (range.start, std::cmp::Reverse(range.end)));
+            hits.push(SearchHit {
+                conversation_id: conversation.id.clone(),
+                conversation_title: conversation.title.clone(),
+                message_index,
+                role: message.role.clone(),
+                score: matches.len(),
+                snippet: highlighted_snippet(&message.content, &matches),
+            });
+        }
+    }
+    // Stable, so equal scores keep conversation order
+    hits.sort_by_key(|hit| std::cmp::Reverse(hit.score));
+    hits.truncate(MAX_SEARCH_HITS);
+    hits
+}
+
+/// Splits a query into words and `"quoted phrases"`; an unclosed quote runs
+/// to the end of the query.
+fn parse_search_terms(query: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    for (index, part) in query.split('"').enumerate() {
+        if index % 2 == 1 {
+            let phrase = part.split_whitespace().collect::<Vec<_>>().join(" ");
+            if !phrase.is_empty() {
+                terms.push(phrase);
+            }
+        } else {
+            terms.extend(part.split_whitespace().map(str::to_string));
+        }
+    }
+    terms
+}
+
+/// Byte ranges in `text` where `term` occurs, ignoring case. Matching runs on
+/// lowercased characters but the ranges point into the original text.
+fn find_case_insensitive(text: &str, term: &str) -> Vec<std::ops::Range<usize>> {
+    let needle: Vec<char> = term.chars().flat_map(char::to_lowercase).collect();
+    let haystack: Vec<(char, usize, usize)> = text
+        .char_indices()
+        .flat_map(|(start, ch)| {
+            let end = start + ch.len_utf8();
+            ch.to_lowercase().map(move |lower| (lower, start, end))
+        })
+        .collect();
+    let mut matches = Vec::new();
+    if needle.is_empty() {
+        return matches;
+    }
+    let mut index = 0;
+    while index + needle.len() <= haystack.len() {
+        let window = &haystack[index..index + needle.len()];
+        if window
+            .iter()
+            .zip(&needle)
+            .all(|(&(ch, _, _), want)| ch == *want)
+        {
+            matches.push(window[0].1..window[needle.len() - 1].2);
+            index += needle.len();
+        } else {
+            index += 1;
+        }
+    }
+    matches
+}
+
+/// Cuts a single-line snippet around the first of `matches` (sorted by start)
+/// and bolds every match that falls inside it.
+fn highlighted_snippet(text: &str, matches: &[std::ops::Range<usize>]) -> String {
+    let Some(first) = matches.first() else {
+        return String::new();
+    };
+    let start = text[..first.start]
+        .char_indices()
+        .rev()
+        .nth(SEARCH_SNIPPET_CONTEXT_CHARS - 1)
+        .map_or(0, |(index, _)| index);
+    let end = text[first.end..]
+        .char_indices()
+        .nth(SEARCH_SNIPPET_CONTEXT_CHARS)
+        .map_or(text.len(), |(index, _)| first.end + index);
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let mut copied = start;
+    for range in matches {
+        // Overlapping matches (e.g. a word inside a phrase) are already bold
+        if range.start < copied || range.end > end {
+            continue;
+        }
+        snippet.push_str(&text[copied..range.start]);
+        snippet.push_str("**");
+        snippet.push_str(&text[range.clone()]);
+        snippet.push_str("**");
+        copied = range.end;
+    }
+    snippet.push_str(&text[copied..end]);
+    if end < text.len() {
+        snippet.push('…');
+    }
+    snippet.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+const CLI_USAGE: &str = "Usage: spotlight --ask \"question\" [--json] [--grounding] [--seed N]";
+
+/// Options for headless mode, see `CLI_USAGE`.
+struct CliArgs {
+    question: String,
+    json: bool,
+    grounding: bool,
+    seed: Option<i32>,
+}
+
+/// Returns `None` when no `--ask` was given so the GUI starts as usual, leaving
+/// other arguments (such as deep links) for the app. With `--ask`, anything
+/// unrecognised is an error.
+fn parse_cli_args(args: impl IntoIterator<Item = String>) -> Result<Option<CliArgs>, String> {
+    let mut question = None;
+    let mut json = false;
+    let mut grounding = false;
+    let mut seed = None;
+    let mut unknown = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--ask" {
+            question = Some(args.next().ok_or("--ask requires a question")?);
+        } else if let Some(value) = arg.strip_prefix("--ask=") {
+            question = Some(value.to_string());
+        } else if arg == "--json" {
+            json = true;
+        } else if arg == "--grounding" {
+            grounding = true;
+        } else if arg == "--seed" {
+            seed = Some(parse_seed(&args.next().ok_or("--seed requires a number")?)?);
+        } else if let Some(value) = arg.strip_prefix("--seed=") {
+            seed = Some(parse_seed(value)?);
+        } else if unknown.is_none() {
+            unknown = Some(arg);
+        }
+    }
+    if let (Some(_), Some(arg)) = (&question, unknown) {
+        return Err(format!("Unknown argument '{}'", arg));
+    }
+    Ok(question.map(|question| CliArgs {
+        question,
+        json,
+        grounding,
+        seed,
+    }))
+}
+
+fn parse_seed(value: &str) -> Result<i32, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid --seed '{}': expected an integer", value))
+}
+
+/// Reads the settings file written by the store plugin without starting Tauri.
+fn read_settings_file() -> Result<serde_json::Map<String, serde_json::Value>, SpotlightError> {
+    let data_dir = dirs::data_dir().ok_or_else(|| {
+        SpotlightError::Platform("Failed to locate the app data directory".to_string())
+    })?;
+    let path = data_dir.join(APP_IDENTIFIER).join(SETTINGS_FILE_NAME);
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => {
+            let mut settings = serde_json::from_str(&contents).map_err(|e| {
+                SpotlightError::Store(format!("Failed to parse {}: {}", path.display(), e))
+            })?;
+            // Only upgraded in memory; the app rewrites the file on its next launch
+            migrate_settings(&mut settings);
+            Ok(settings)
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(serde_json::Map::new()),
+        Err(err) => Err(SpotlightError::Store(format!(
+            "Failed to read {}: {}",
+            path.display(),
+            err
+        ))),
+    }
+}
+
+async fn ask_headless(args: &CliArgs) -> Result<GeminiResult, SpotlightError> {
+    let question = args.question.trim();
+    if question.is_empty() {
+        return Err(SpotlightError::InvalidInput("The question is empty".to_string()));
+    }
+
+    let settings = read_settings_file()?;
+    let backend: Backend = settings
+        .get(BACKEND_KEY)
+        .and_then(|json| serde_json::from_value(json.clone()).ok())
+        .unwrap_or_default();
+    let active = settings
+        .get(ACTIVE_PROFILE_KEY)
+        .and_then(|json| json.as_str())
+        .map(|name| name.to_string());
+    let profile = active.as_ref().and_then(|name| {
+        settings
+            .get(PROFILES_KEY)
+            .and_then(|json| serde_json::from_value::<BTreeMap<String, ApiProfile>>(json.clone()).ok())
+            .and_then(|mut profiles| profiles.remove(name))
+    });
+    let stored_key = active
+        .as_deref()
+        .zip(profile.as_ref())
+        .and_then(|(name, profile)| profile.resolve_api_key(name));
+    let api_key = with_env_api_key(&backend, stored_key).unwrap_or_default();
+    if backend.requires_api_key() && api_key.trim().is_empty() {
+        return Err(SpotlightError::MissingApiKey);
+    }
+
+    let request = ChatRequest {
+        message: question.to_string(),
+        images: Vec::new(),
+        chat_history: Vec::new(),
+        system_instructions: settings
+            .get(SYSTEM_INSTRUCTIONS_KEY)
+            .and_then(|json| json.as_str())
+            .map(|instructions| instructions.to_string()),
+        grounding_enabled: args.grounding,
+        dynamic_threshold: None,
+        thinking_enabled: None,
+        code_execution_enabled: false,
+        url_context_enabled: false,
+        response_mime_type: None,
+        response_schema: None,
+        temperature: None,
+        stop_sequences: Vec::new(),
+        candidate_count: None,
+        seed: args.seed,
+        presence_penalty: None,
+        frequency_penalty: None,
+        audio: None,
+        document: None,
+        cached_content: None,
+    };
+    let model = profile.and_then(|profile| profile.model);
+    let gemini = GeminiSettings::from_json(
+        settings.get(API_VERSION_KEY),
+        settings.get(CUSTOM_HEADERS_KEY),
+    );
+    generate_with_backend(None, &backend, &api_key, &gemini, model, request)
+        .await
+        .map_err(|err| err.redacted(&api_key))
+}
+
+/// Release builds use the GUI subsystem on Windows, which has no console, so
+/// output only reaches the terminal after attaching to the one that started us.
+#[cfg(target_os = "windows")]
+fn attach_parent_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+
+    // Fails when launched from Explorer, where there is nothing to print to anyway
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn attach_parent_console() {}
+
+/// Answers a single question on stdout and returns the process exit code.
+fn run_cli(args: CliArgs) -> i32 {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            eprintln!("Failed to start async runtime: {err}");
+            return 1;
+        }
+    };
+    match runtime.block_on(ask_headless(&args)) {
+        Ok(result) if args.json => match serde_json::to_string(&result) {
+            Ok(json) => {
+                println!("{json}");
+                0
+            }
+            Err(err) => {
+                eprintln!("Failed to serialize result: {err}");
+                1
+            }
+        },
+        Ok(result) => {
+            println!("{}", result.text);
+            0
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            1
+        }
+    }
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    match parse_cli_args(std::env::args().skip(1)) {
+        Ok(Some(args)) => {
+            attach_parent_console();
+            std::process::exit(run_cli(args));
+        }
+        Ok(None) => {}
+        Err(err) => {
+            attach_parent_console();
+            eprintln!("{err}\n{CLI_USAGE}");
+            std::process::exit(2);
+        }
+    }
+
     tauri::Builder::default()
+        // Must be registered first so a second launch exits before creating a tray
+        .plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
+            show_main_window(app);
+            if let Err(err) = app.emit(SECOND_INSTANCE_EVENT, SecondInstancePayload { args, cwd }) {
+                error!("Failed to forward second instance arguments: {err}");
+            }
+        }))
+        .plugin(tauri_plugin_deep_link::init())
+        .plugin(tauri_plugin_notification::init())
+        .plugin(tauri_plugin_autostart::init(
+            MacosLauncher::LaunchAgent,
+            Some(vec![AUTOSTART_ARG]),
+        ))
         .menu(|app_handle| {
             let api_settings_item = MenuItem::with_id(
                 app_handle,
@@ -836,6 +9550,13 @@ pub fn run() {
                 true,
                 Some("CmdOrCtrl+,"),
             )?;
+            let check_update_item = MenuItem::with_id(
+                app_handle,
+                MENU_ITEM_CHECK_UPDATE,
+                "Check for Updates...",
+                true,
+                None::<&str>,
+            )?;
             #[cfg(target_os = "linux")]
             let quit_item = MenuItem::with_id(
                 app_handle,
@@ -853,6 +9574,7 @@ pub fn run() {
                 "Spotlight",
             )
                 .item(&api_settings_item)
+                .item(&check_update_item)
                 .separator()
                 .item(&quit_item)
                 .build()?;
@@ -872,15 +9594,30 @@ pub fn run() {
         .on_menu_event(|app, event| match event.id().as_ref() {
             MENU_ITEM_API_SETTINGS => {
                 if let Err(err) = open_settings_window(app) {
-                    eprintln!("Failed to open settings window from menu: {err}");
+                    error!("Failed to open settings window from menu: {err}");
                 }
             }
+            MENU_ITEM_CHECK_UPDATE => spawn_update_check(app),
             MENU_ITEM_QUIT => app.exit(0),
             _ => {}
         })
         .plugin(tauri_plugin_opener::init())
-        .plugin(tauri_plugin_global_shortcut::Builder::new().build())
+        .plugin(
+            tauri_plugin_global_shortcut::Builder::new()
+                .with_handler(|app, shortcut, event| {
+                    if event.state() != ShortcutState::Pressed {
+                        return;
+                    }
+                    if is_capture_shortcut(app, shortcut) {
+                        capture_and_attach(app);
+                    } else {
+                        toggle_main_window(app);
+                    }
+                })
+                .build(),
+        )
         .plugin(tauri_plugin_store::Builder::new().build())
+        .plugin(tauri_plugin_clipboard_manager::init())
         .setup(|app| {
             #[cfg(desktop)]
             app.handle()
@@ -891,7 +9628,92 @@ pub fn run() {
 
             let handle = app.handle();
 
-            let tray_menu = {
+            if let Err(err) = init_logging(handle) {
+                eprintln!("{err}");
+            }
+
+            match settings_store(handle) {
+                Ok(store) => {
+                    if let Err(err) = migrate_api_key_storage(&store) {
+                        error!("Failed to migrate legacy API key: {err}");
+                    }
+                    if let Err(err) = conversations_store(handle).and_then(|conversations| {
+                        move_conversations_out_of_settings(&store, &conversations)
+                    }) {
+                        error!("Failed to move conversations out of settings: {err}");
+                    }
+                    // The frontend reads it through get_api_key and get_api_key_source
+                    if env_api_key().is_some() {
+                        info!(
+                            "Using {} from the environment where applicable",
+                            API_KEY_ENV_VAR
+                        );
+                    }
+                    emit_default_toggles(handle);
+                    match watch_settings_file(handle.clone()) {
+                        Ok(debouncer) => {
+                            handle.manage(SettingsWatcherState {
+                                _debouncer: debouncer,
+                            });
+                        }
+                        Err(err) => error!("Failed to watch the settings file: {err}"),
+                    }
+                }
+                Err(err) => error!("Failed to open settings store for migration: {err}"),
+            }
+
+            app.manage(GlobalShortcutState::default());
+            app.manage(CaptureDebounceState::default());
+            app.manage(SpeechState::default());
+            app.manage(RecordingState::default());
+            app.manage(RateLimiterState::default());
+            app.manage(WindowSaveDebounceState::default());
+            apply_stored_global_shortcuts(handle);
+
+            // Linux and Windows dev builds need the scheme registered at runtime
+            #[cfg(any(target_os = "linux", all(debug_assertions, windows)))]
+            {
+                if let Err(err) = app.deep_link().register_all() {
+                    error!("Failed to register deep link schemes: {err}");
+                }
+            }
+            app.manage(DeepLinkState::default());
+            app.manage(ModelCacheState::default());
+            app.manage(FaviconCacheState::default());
+            app.manage(SourceRegistryState::default());
+            let deep_link_handle = handle.clone();
+            app.deep_link().on_open_url(move |event| {
+                handle_deep_link_urls(&deep_link_handle, &event.urls());
+            });
+            // A link that launched the app arrives here rather than through on_open_url
+            match app.deep_link().get_current() {
+                Ok(Some(urls)) => handle_deep_link_urls(handle, &urls),
+                Ok(None) => {}
+                Err(err) => error!("Failed to read launch deep link: {err}"),
+            }
+
+            let recent_submenu = Submenu::new(handle, "Recent", true)?;
+            let recent_state = RecentQueriesState {
+                queries: Mutex::new(load_recent_queries(handle)),
+                submenu: recent_submenu.clone(),
+            };
+            if let Ok(queries) = recent_state.queries.lock() {
+                recent_state.rebuild_menu(handle, &queries)?;
+            }
+            app.manage(recent_state);
+
+            // Re-apply the saved choice in case the login item was removed outside the app
+            let autostart_enabled = match stored_autostart(handle) {
+                Some(enabled) => {
+                    if let Err(err) = apply_autostart(handle, enabled) {
+                        error!("{err}");
+                    }
+                    enabled
+                }
+                None => app.autolaunch().is_enabled().unwrap_or(false),
+            };
+
+            let (tray_menu, show_item, hide_item, always_on_top_item, autostart_item) = {
                 let menu = Menu::new(handle)?;
                 let show_item = MenuItem::with_id(
                     handle,
@@ -907,6 +9729,22 @@ pub fn run() {
                     true,
                     None::<&str>,
                 )?;
+                let always_on_top_item = CheckMenuItem::with_id(
+                    handle,
+                    MENU_ITEM_ALWAYS_ON_TOP,
+                    "Always on Top",
+                    true,
+                    stored_always_on_top(handle),
+                    None::<&str>,
+                )?;
+                let autostart_item = CheckMenuItem::with_id(
+                    handle,
+                    MENU_ITEM_AUTOSTART,
+                    "Launch at Login",
+                    true,
+                    autostart_enabled,
+                    None::<&str>,
+                )?;
                 let settings_item = MenuItem::with_id(
                     handle,
                     MENU_ITEM_API_SETTINGS,
@@ -914,6 +9752,13 @@ pub fn run() {
                     true,
                     None::<&str>,
                 )?;
+                let check_update_item = MenuItem::with_id(
+                    handle,
+                    MENU_ITEM_CHECK_UPDATE,
+                    "Check for Updates...",
+                    true,
+                    None::<&str>,
+                )?;
                 let quit_item = MenuItem::with_id(
                     handle,
                     MENU_ITEM_QUIT,
@@ -923,31 +9768,77 @@ pub fn run() {
                 )?;
                 menu.append(&show_item)?;
                 menu.append(&hide_item)?;
+                menu.append(&always_on_top_item)?;
+                menu.append(&autostart_item)?;
+                menu.append(&recent_submenu)?;
                 menu.append(&settings_item)?;
+                menu.append(&check_update_item)?;
                 menu.append(&PredefinedMenuItem::separator(handle)?)?;
                 menu.append(&quit_item)?;
-                let tray_state = TrayMenuState {
-                    show_item: show_item.clone(),
-                    hide_item: hide_item.clone(),
-                };
-                tray_state.set_visibility(false);
-                app.manage(tray_state);
-                menu
+                (menu, show_item, hide_item, always_on_top_item, autostart_item)
+            };
+
+            let has_api_key = settings_store(handle)
+                .ok()
+                .and_then(|store| active_api_key(&store))
+                .is_some();
+            let initial_status = if has_api_key {
+                TrayStatus::Ready
+            } else {
+                TrayStatus::NoApiKey
             };
 
+            let left_click_behavior = settings_store(handle)
+                .ok()
+                .and_then(|store| store.get(TRAY_LEFT_CLICK_KEY))
+                .and_then(|json| serde_json::from_value::<TrayLeftClickBehavior>(json).ok())
+                .unwrap_or_default();
+
             let mut tray_builder = TrayIconBuilder::with_id(TRAY_ICON_ID)
-                .tooltip(TRAY_TOOLTIP)
+                .tooltip(initial_status.tooltip())
                 .menu(&tray_menu)
-                .show_menu_on_left_click(true)
+                .show_menu_on_left_click(left_click_behavior == TrayLeftClickBehavior::Menu)
+                .on_tray_icon_event(|tray, event| {
+                    if let TrayIconEvent::Click {
+                        button: MouseButton::Left,
+                        button_state: MouseButtonState::Up,
+                        ..
+                    } = event
+                    {
+                        let app_handle = tray.app_handle();
+                        let should_toggle = app_handle
+                            .try_state::<TrayMenuState>()
+                            .is_some_and(|state| state.left_click_toggles_window());
+                        if should_toggle {
+                            toggle_main_window(app_handle);
+                        }
+                    }
+                })
                 .on_menu_event(|app_handle, event| match event.id().as_ref() {
                     MENU_ITEM_SHOW => show_main_window(app_handle),
                     MENU_ITEM_HIDE => hide_main_window(app_handle),
+                    MENU_ITEM_ALWAYS_ON_TOP => {
+                        let enabled = !stored_always_on_top(app_handle);
+                        if let Err(err) = set_always_on_top(app_handle.clone(), enabled) {
+                            error!("Failed to toggle always-on-top from tray: {err}");
+                        }
+                    }
+                    MENU_ITEM_AUTOSTART => {
+                        let enabled = !app_handle.autolaunch().is_enabled().unwrap_or(false);
+                        if let Err(err) = set_autostart(app_handle.clone(), enabled) {
+                            error!("Failed to toggle launch at login from tray: {err}");
+                        }
+                    }
                     MENU_ITEM_API_SETTINGS => {
                         if let Err(err) = open_settings_window(app_handle) {
-                            eprintln!("Failed to open settings window from tray: {err}");
+                            error!("Failed to open settings window from tray: {err}");
                         }
                     }
+                    MENU_ITEM_CHECK_UPDATE => spawn_update_check(app_handle),
                     MENU_ITEM_QUIT => app_handle.exit(0),
+                    id if id.starts_with(MENU_ITEM_RECENT_PREFIX) => {
+                        rerun_recent_query(app_handle, id)
+                    }
                     _ => {}
                 });
 
@@ -961,48 +9852,169 @@ pub fn run() {
             }
 
             let tray_icon = tray_builder.build(app)?;
+            let tray_state = TrayMenuState {
+                show_item,
+                hide_item,
+                always_on_top_item,
+                autostart_item,
+                tray_icon: tray_icon.clone(),
+                status: Arc::new(Mutex::new(initial_status)),
+                left_click_behavior: Arc::new(Mutex::new(left_click_behavior)),
+            };
+            tray_state.set_visibility(false);
+            app.manage(tray_state);
             app.manage(tray_icon);
 
             if let Some(main_window) = app.get_webview_window(MAIN_WINDOW_LABEL) {
                 let _ = main_window.hide();
+                restore_window_geometry(handle, &main_window);
+                if let Err(err) = apply_always_on_top(handle, stored_always_on_top(handle)) {
+                    error!("{err}");
+                }
+                if let Some(level) = stored_window_opacity(handle) {
+                    if let Err(err) = apply_window_opacity(handle, level) {
+                        error!("{err}");
+                    }
+                }
                 let window_for_event = main_window.clone();
                 let app_handle_for_event = handle.clone();
-                main_window.on_window_event(move |event| {
-                    if let WindowEvent::CloseRequested { api, .. } = event {
+                main_window.on_window_event(move |event| match event {
+                    WindowEvent::CloseRequested { api, .. } => {
                         api.prevent_close();
                         if let Err(err) = window_for_event.hide() {
-                            eprintln!("Failed to hide window on close request: {err}");
+                            error!("Failed to hide window on close request: {err}");
                         }
                         hide_main_window(&app_handle_for_event);
                     }
+                    WindowEvent::Moved(_) | WindowEvent::Resized(_) => {
+                        let window = window_for_event.clone();
+                        debounce_window_save(
+                            &app_handle_for_event,
+                            MAIN_WINDOW_LABEL,
+                            move |app| save_window_geometry(app, &window),
+                        );
+                    }
+                    WindowEvent::ThemeChanged(theme) => {
+                        handle_theme_change(&app_handle_for_event, *theme);
+                    }
+                    WindowEvent::DragDrop(DragDropEvent::Drop { paths, .. }) => {
+                        handle_dropped_files(&app_handle_for_event, paths.clone());
+                    }
+                    _ => {}
                 });
             }
 
-            #[cfg(target_os = "windows")]
-            {
-                use window_vibrancy::{apply_acrylic, apply_blur};
+            if let Err(err) = apply_main_window_effect(handle, stored_window_tint(handle)) {
+                error!("{err}");
+            }
 
-                if let Some(window) = app.get_webview_window(MAIN_WINDOW_LABEL) {
-                    if apply_acrylic(&window, Some((255, 255, 255, 125))).is_err() {
-                        let _ = apply_blur(&window, Some((255, 255, 255, 125)));
-                    }
-                }
+            #[cfg(target_os = "macos")]
+            if let Some(window) = app.get_webview_window(MAIN_WINDOW_LABEL) {
+                apply_macos_vibrancy(handle, &window);
             }
 
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             capture_screen,
+            capture_screen_delayed,
+            capture_to_clipboard,
+            capture_to_file,
+            capture_with_redactions,
+            capture_with_thumbnail,
+            capture_region,
+            capture_all_displays,
+            get_excluded_windows,
+            set_excluded_windows,
+            get_clipboard_image,
+            attach_image_file,
+            ocr_image,
+            export_conversation_markdown,
+            save_markdown,
+            copy_text,
+            copy_as_markdown,
             send_to_gemini,
+            regenerate,
+            edit_and_resend,
             sync_tray_visibility,
+            get_global_shortcut,
+            set_global_shortcut,
+            get_capture_shortcut,
+            set_capture_shortcut,
+            set_always_on_top,
+            set_window_opacity,
+            apply_window_effect,
+            set_autostart,
+            is_autostart_enabled,
+            set_notify_on_complete,
+            check_for_updates,
+            set_update_channel,
+            get_last_response,
+            get_usage_stats,
+            set_cache_last_response,
+            set_tray_behavior,
+            get_backend,
+            set_backend,
+            get_log_path,
+            list_models,
+            health_check,
+            get_app_info,
+            fetch_favicon,
+            take_pending_query,
+            open_source,
+            open_source_by_id,
+            set_history_token_budget,
+            set_max_request_bytes,
+            set_context_warning_threshold,
+            set_image_only_prompt,
+            set_sanitize_output,
+            get_tts_settings,
+            set_tts_enabled,
+            set_tts_voice,
+            set_tts_rate,
+            speak,
+            stop_speaking,
+            set_microphone_enabled,
+            start_recording,
+            stop_recording,
+            get_custom_headers,
+            set_custom_headers,
+            get_api_version,
+            set_api_version,
+            create_cache,
+            capture_and_ask,
+            set_capture_debounce_ms,
+            summarize_history,
+            batch_ask,
+            get_rate_limit,
+            get_default_toggles,
+            set_default_grounding,
+            set_default_thinking,
+            set_rate_limit,
+            list_conversations,
+            get_active_conversation,
+            create_conversation,
+            rename_conversation,
+            switch_conversation,
+            set_conversation_system_prompt,
+            save_conversation_messages,
+            set_conversation_tags,
+            delete_message,
+            search_history,
             open_api_settings_window,
             close_api_settings_window,
             get_api_key,
+            get_api_key_source,
             set_api_key,
             clear_api_key,
+            list_profiles,
+            add_profile,
+            set_active_profile,
+            delete_profile,
             get_system_instructions,
             set_system_instructions,
             clear_system_instructions,
+            reset_all_data,
             get_instruction_presets,
             save_instruction_preset,
             delete_instruction_preset
@@ -1010,3 +10022,945 @@ pub fn run() {
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_API_KEY: &str = "AIzaSyTestKey1234567890";
+
+    #[test]
+    fn api_errors_never_contain_the_api_key() {
+        let body = format!(
+            "{{\"error\": {{\"message\": \"API key not valid: {}\", \"status\": \"API_KEY_INVALID\"}}}}",
+            TEST_API_KEY
+        );
+        let statuses = [400, 401, 403, 408, 429, 500, 503];
+        for status in statuses {
+            let status = reqwest::StatusCode::from_u16(status).unwrap();
+            let err = classify_api_error(status, body.clone()).redacted(TEST_API_KEY);
+            let message = err.to_string();
+            let serialized = serde_json::to_string(&err).unwrap();
+            assert!(!message.contains(TEST_API_KEY), "leaked in message: {message}");
+            assert!(!serialized.contains(TEST_API_KEY), "leaked in JSON: {serialized}");
+        }
+    }
+
+    #[test]
+    fn redaction_covers_every_error_kind() {
+        let leaky = format!("request to https://example.com/?key={}", TEST_API_KEY);
+        let errors = [
+            SpotlightError::AuthFailed(leaky.clone()),
+            SpotlightError::RateLimited(leaky.clone()),
+            SpotlightError::Network(leaky.clone()),
+            SpotlightError::Timeout(leaky.clone()),
+            SpotlightError::Parse(leaky.clone()),
+            SpotlightError::Blocked(leaky.clone()),
+            SpotlightError::Api(leaky.clone()),
+            SpotlightError::Capture(leaky.clone()),
+            SpotlightError::Store(leaky.clone()),
+            SpotlightError::InvalidInput(leaky.clone()),
+            SpotlightError::Platform(leaky.clone()),
+            SpotlightError::Update(leaky.clone()),
+            SpotlightError::CacheExpired(leaky.clone()),
+            SpotlightError::ShortcutTaken(leaky.clone()),
+            SpotlightError::MicrophoneUnavailable(leaky.clone()),
+            SpotlightError::SpeechUnavailable(leaky.clone()),
+            SpotlightError::Unsupported(leaky),
+            SpotlightError::PayloadTooLarge { size: 2, limit: 1 },
+        ];
+        for err in errors {
+            // Variants without a message have nothing to redact
+            let has_message = !matches!(err, SpotlightError::PayloadTooLarge { .. });
+            let message = err.redacted(TEST_API_KEY).to_string();
+            assert!(!message.contains(TEST_API_KEY), "leaked in message: {message}");
+            assert_eq!(message.contains("[REDACTED]"), has_message, "{message}");
+        }
+    }
+
+    #[test]
+    fn markdown_export_closes_code_fences_and_numbers_sources() {
+        let messages = vec![
+            ChatMessage {
+                role: "user".to_string(),
+                content: "What is on screen?".to_string(),
+                images: vec![ImageInput {
+                    mime_type: "image/png".to_string(),
+                    data: "aGVsbG8=".to_string(),
+                }],
+                model: None,
+                created_at: None,
+                options: None,
+            },
+            ChatMessage {
+                role: "assistant".to_string(),
+                content: "Run this:\n\n```rust\nfn main() {}".to_string(),
+                images: Vec::new(),
+                model: Some("gemini-flash-latest".to_string()),
+                created_at: Some("2025-01-01T12:00:00+00:00".to_string()),
+                options: None,
+            },
+        ];
+        let sources = [SourceInfo {
+            title: "Rust [docs]".to_string(),
+            uri: "https://doc.rust-lang.org".to_string(),
+        }];
+
+        let markdown = render_conversation_markdown(&messages, Some(&sources), None);
+
+        assert!(markdown.starts_with("## User\n\nWhat is on screen?\n"));
+        assert!(markdown.contains("1 image attached"));
+        assert!(markdown.contains(
+            "## Assistant\n\n_gemini-flash-latest · 2025-01-01T12:00:00+00:00_\n\nRun this:"
+        ));
+        assert!(!markdown.contains("aGVsbG8="));
+        assert!(markdown.contains("```rust\nfn main() {}\n```\n"));
+        assert!(markdown.ends_with("## Sources\n\n1. [Rust \\[docs\\]](https://doc.rust-lang.org)\n"));
+    }
+
+    #[test]
+    fn markdown_export_footnotes_cited_spans() {
+        let messages = vec![
+            ChatMessage {
+                role: "user".to_string(),
+                content: "Tell me about Rust".to_string(),
+                images: Vec::new(),
+                model: None,
+                created_at: None,
+                options: None,
+            },
+            ChatMessage {
+                role: "assistant".to_string(),
+                content: "Rust is fast. It is also safé. Done".to_string(),
+                images: Vec::new(),
+                model: None,
+                created_at: None,
+                options: None,
+            },
+        ];
+        let sources = [
+            SourceInfo {
+                title: "Rust".to_string(),
+                uri: "https://rust-lang.org".to_string(),
+            },
+            SourceInfo {
+                title: "Docs".to_string(),
+                uri: "https://doc.rust-lang.org".to_string(),
+            },
+        ];
+        let citation = |start_index, end_index, source_indices: &[usize]| Citation {
+            start_index,
+            end_index,
+            text: None,
+            source_indices: source_indices.to_vec(),
+        };
+        let citations = [
+            citation(0, 13, &[1]),
+            // Overlaps the first span and ends at the same place
+            citation(5, 13, &[0, 1]),
+            citation(14, 31, &[0, 7]),
+            // Inside the two-byte "é": skipped rather than splitting it
+            citation(14, 29, &[1]),
+        ];
+
+        let markdown = render_conversation_markdown(&messages, Some(&sources), Some(&citations));
+
+        assert!(markdown.contains("Tell me about Rust\n"));
+        assert!(markdown.contains("Rust is fast.[^1][^2] It is also safé.[^1] Done\n"));
+        assert!(markdown.ends_with(
+            "2. [Docs](https://doc.rust-lang.org)\n\n\
+             [^1]: [Rust](https://rust-lang.org)\n\
+             [^2]: [Docs](https://doc.rust-lang.org)\n"
+        ));
+
+        let (text, cited) = insert_footnote_markers("short", &[citation(0, 99, &[0])], 1);
+        assert_eq!((text.as_str(), cited), ("short[^1]", vec![0]));
+    }
+
+    #[test]
+    fn sanitized_markdown_drops_dangerous_html_but_keeps_code() {
+        let text = "Hi <b>there</b><script>alert(1)</script>!\n\
+                    See [docs](javascript:alert(1)) and [site](https://example.com).\n\
+                    Also [ref][1] and [home][2].\n\
+                    [1]: JavaScript:alert(1) \"title\"\n\
+                    [2]: https://example.com\n\
+                    Inline `<script>` stays, as does 1 < 2.\n\
+                    ```html\n<script>kept()</script>\n```\n\
+                    <IFRAME src=x></iframe><!-- note -->done";
+
+        let sanitized = sanitize_markdown(text);
+
+        assert!(sanitized.starts_with("Hi there!\n"));
+        assert!(sanitized.contains("[docs](#)"));
+        assert!(sanitized.contains("[site](https://example.com)"));
+        assert!(sanitized.contains("\n[1]: #\n"));
+        assert!(sanitized.contains("\n[2]: https://example.com\n"));
+        assert!(sanitized.contains("Inline `<script>` stays, as does 1 < 2."));
+        assert!(sanitized.contains("```html\n<script>kept()</script>\n```\n"));
+        assert!(sanitized.ends_with("\ndone"));
+        assert!(!sanitized.contains("alert"));
+    }
+
+    #[test]
+    fn api_version_must_look_like_a_google_api_version() {
+        for version in ["v1", "v1beta", "v1alpha", "v2"] {
+            assert!(is_valid_api_version(version), "{version}");
+        }
+        for version in ["", "v", "1", "vbeta", "v1gamma", "v1/../x", "V1"] {
+            assert!(!is_valid_api_version(version), "{version}");
+        }
+    }
+
+    #[test]
+    fn changed_settings_keys_lists_added_removed_and_modified() {
+        let before = serde_json::json!({ "A": 1, "B": true, "C": "same" });
+        let after = serde_json::json!({ "B": false, "C": "same", "D": [1] });
+        assert_eq!(
+            changed_settings_keys(before.as_object().unwrap(), after.as_object().unwrap()),
+            ["A", "B", "D"]
+        );
+        assert!(changed_settings_keys(after.as_object().unwrap(), after.as_object().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn settings_migrate_from_each_historical_layout() {
+        let migrate = |fixture: serde_json::Value| {
+            let mut settings = fixture.as_object().unwrap().clone();
+            let changed = migrate_settings(&mut settings);
+            (changed, serde_json::Value::Object(settings))
+        };
+
+        // Fresh install
+        let (changed, settings) = migrate(serde_json::json!({}));
+        assert!(changed);
+        assert_eq!(settings, serde_json::json!({ "SCHEMA_VERSION": 1 }));
+
+        // Schema 0: bare key
+        let (changed, settings) = migrate(serde_json::json!({
+            "GEMINI_API_KEY": "legacy-key",
+            "ALWAYS_ON_TOP": true,
+        }));
+        assert!(changed);
+        assert_eq!(
+            settings,
+            serde_json::json!({
+                "SCHEMA_VERSION": 1,
+                "PROFILES": { "default": { "apiKey": "legacy-key" } },
+                "ACTIVE_PROFILE": "default",
+                "ALWAYS_ON_TOP": true,
+            })
+        );
+        let profile: ApiProfile =
+            serde_json::from_value(settings["PROFILES"]["default"].clone()).unwrap();
+        assert_eq!(profile.api_key, "legacy-key");
+        assert!(!profile.key_in_keychain);
+
+        // Schema 0 after profiles landed but before versioning: keep the profile
+        let (changed, settings) = migrate(serde_json::json!({
+            "GEMINI_API_KEY": "stale-key",
+            "PROFILES": { "work": { "keyInKeychain": true } },
+            "ACTIVE_PROFILE": "work",
+        }));
+        assert!(changed);
+        assert_eq!(
+            settings,
+            serde_json::json!({
+                "SCHEMA_VERSION": 1,
+                "PROFILES": {
+                    "work": { "keyInKeychain": true },
+                    "default": { "apiKey": "stale-key" },
+                },
+                "ACTIVE_PROFILE": "work",
+            })
+        );
+
+        // Schema 0 with a "default" profile of its own: the old key gets a new name
+        let (changed, settings) = migrate(serde_json::json!({
+            "GEMINI_API_KEY": "stale-key",
+            "PROFILES": {
+                "default": { "apiKey": "current-key", "model": "gemini-pro" },
+                "legacy": { "apiKey": "other-key" },
+            },
+            "ACTIVE_PROFILE": "default",
+        }));
+        assert!(changed);
+        assert_eq!(
+            settings,
+            serde_json::json!({
+                "SCHEMA_VERSION": 1,
+                "PROFILES": {
+                    "default": { "apiKey": "current-key", "model": "gemini-pro" },
+                    "legacy": { "apiKey": "other-key" },
+                    "legacy-2": { "apiKey": "stale-key" },
+                },
+                "ACTIVE_PROFILE": "default",
+            })
+        );
+
+        // A legacy key that a profile already holds is not duplicated
+        let (_, settings) = migrate(serde_json::json!({
+            "GEMINI_API_KEY": "same-key",
+            "PROFILES": { "default": { "apiKey": "same-key" } },
+        }));
+        assert_eq!(
+            settings["PROFILES"],
+            serde_json::json!({ "default": { "apiKey": "same-key" } })
+        );
+        assert!(settings.get("GEMINI_API_KEY").is_none());
+
+        // Current and newer schemas are left alone
+        for version in [CURRENT_SCHEMA_VERSION, CURRENT_SCHEMA_VERSION + 1] {
+            let fixture = serde_json::json!({
+                "SCHEMA_VERSION": version,
+                "GEMINI_API_KEY": "untouched",
+            });
+            assert_eq!(migrate(fixture.clone()), (false, fixture));
+        }
+    }
+
+    #[test]
+    fn token_bucket_allows_a_burst_then_refills_at_the_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::full(2, start);
+        assert!(bucket.try_take(2, start).is_ok());
+        assert!(bucket.try_take(2, start).is_ok());
+
+        let wait = bucket.try_take(2, start).unwrap_err();
+        assert_eq!(wait.as_secs(), 30);
+        assert!(bucket.try_take(2, start + Duration::from_secs(15)).is_err());
+        assert!(bucket.try_take(2, start + Duration::from_secs(30)).is_ok());
+
+        // An idle bucket never holds more than a minute's worth
+        let later = start + Duration::from_secs(600);
+        assert!(bucket.try_take(2, later).is_ok());
+        assert!(bucket.try_take(2, later).is_ok());
+        assert!(bucket.try_take(2, later).is_err());
+    }
+
+    #[test]
+    fn local_rate_limit_error_carries_retry_after() {
+        let err = SpotlightError::RateLimitedLocally {
+            retry_after_ms: 1500,
+        };
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(json["kind"], "rateLimitedLocally");
+        assert_eq!(json["retryAfterMs"], 1500);
+        assert_eq!(json["message"], "Too many requests; try again in 1.5s");
+
+        let json = serde_json::to_value(SpotlightError::EmptyPrompt).unwrap();
+        assert!(json.get("retryAfterMs").is_none());
+    }
+
+    #[test]
+    fn redactions_must_fit_and_fill_only_their_region() {
+        let white = image::Rgba([255, 255, 255, 255]);
+        let mut image = image::RgbaImage::from_pixel(4, 4, white);
+        let region = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+
+        for bad in [region(0, 0, 0, 1), region(3, 0, 2, 1), region(0, 0, 1, 5), region(u32::MAX, 0, 2, 1)] {
+            let result = redact_regions(&mut image, &[region(0, 0, 1, 1), bad], RedactionStyle::Fill);
+            assert!(matches!(result, Err(SpotlightError::InvalidInput(_))));
+        }
+        assert!(image.pixels().all(|pixel| *pixel == white), "rejected batch must not redact");
+
+        redact_regions(&mut image, &[region(1, 1, 2, 2)], RedactionStyle::Fill).unwrap();
+        assert_eq!(*image.get_pixel(1, 1), image::Rgba([0, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(2, 2), image::Rgba([0, 0, 0, 255]));
+        assert_eq!(*image.get_pixel(0, 0), white);
+        assert_eq!(*image.get_pixel(3, 3), white);
+    }
+
+    #[test]
+    fn recordings_encode_as_mono_pcm_wav() {
+        let wav = encode_wav(&[0, i16::MAX, -1], 16_000);
+        assert_eq!(wav.len(), 44 + 6);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 36 + 6);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u16::from_le_bytes([wav[22], wav[23]]), 1);
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 16_000);
+        assert_eq!(u32::from_le_bytes(wav[28..32].try_into().unwrap()), 32_000);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 6);
+        assert_eq!(&wav[44..], &[0x00, 0x00, 0xff, 0x7f, 0xff, 0xff]);
+
+        assert_eq!(rms_level(&[]), 0.0);
+        assert_eq!(rms_level(&[0; 64]), 0.0);
+        assert!((rms_level(&[i16::MAX, -i16::MAX]) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn settings_window_centers_in_the_work_area() {
+        let area = tauri::PhysicalRect {
+            position: tauri::PhysicalPosition::new(-1920, 40),
+            size: tauri::PhysicalSize::new(1920, 1040),
+        };
+        let centered = centered_in_work_area(&area, tauri::PhysicalSize::new(800, 600));
+        assert_eq!((centered.x, centered.y), (-1920 + 560, 40 + 220));
+
+        let oversized = centered_in_work_area(&area, tauri::PhysicalSize::new(2560, 1440));
+        assert_eq!((oversized.x, oversized.y), (-1920, 40));
+    }
+
+    #[test]
+    fn beta_channel_reads_the_beta_manifest() {
+        let stable = vec![
+            tauri::Url::parse("https://example.com/releases/latest.json").unwrap(),
+            tauri::Url::parse("https://example.com/{{target}}/{{current_version}}").unwrap(),
+        ];
+        assert_eq!(
+            channel_update_endpoints(&stable, UpdateChannel::Stable),
+            stable
+        );
+
+        let beta: Vec<String> = channel_update_endpoints(&stable, UpdateChannel::Beta)
+            .iter()
+            .map(|endpoint| endpoint.to_string())
+            .collect();
+        assert_eq!(beta[0], "https://example.com/releases/latest-beta.json");
+        assert_eq!(
+            beta[1],
+            "https://example.com/%7B%7Btarget%7D%7D/%7B%7Bcurrent_version%7D%7D?channel=beta"
+        );
+    }
+
+    #[test]
+    fn icon_links_are_found_in_page_markup() {
+        let html = r#"<HEAD><link rel="stylesheet" href="/a.css">
+            <LINK REL='Shortcut Icon' HREF='/Static/Fav.png'>
+            <link rel=icon href=/later.ico></head>"#;
+        assert_eq!(find_icon_href(html).as_deref(), Some("/Static/Fav.png"));
+        assert_eq!(
+            find_icon_href(r#"<link href="x.svg" rel="icon" type="image/svg+xml"/>"#).as_deref(),
+            Some("x.svg")
+        );
+        assert_eq!(
+            find_icon_href(r#"<link rel="apple-touch-icon" href="t.png">"#),
+            None
+        );
+        assert_eq!(find_icon_href("<link rel=icon"), None);
+        assert_eq!(find_icon_href("<p>no links</p>"), None);
+    }
+
+    #[test]
+    fn displays_are_laid_out_on_one_canvas() {
+        // A monitor left of the primary, one below-right with a gap
+        let displays = [
+            (-1280, 200, 1280, 720),
+            (0, 0, 1440, 900),
+            (1600, 900, 800, 600),
+        ];
+        let (width, height, placements) = panorama_layout(&displays, 2.0);
+        assert_eq!((width, height), (2 * (1280 + 2400), 2 * 1500));
+        let areas: Vec<_> = placements
+            .iter()
+            .map(|r| (r.x, r.y, r.width, r.height))
+            .collect();
+        assert_eq!(
+            areas,
+            [
+                (0, 400, 2560, 1440),
+                (2560, 0, 2880, 1800),
+                (5760, 1800, 1600, 1200),
+            ]
+        );
+
+        // Fractional scales still leave neighbouring displays flush
+        let (_, _, placements) = panorama_layout(&[(0, 0, 1001, 10), (1001, 0, 1001, 10)], 1.5);
+        assert_eq!(placements[0].x + placements[0].width, placements[1].x);
+    }
+
+    #[test]
+    fn excluded_window_patterns_match_names_and_titles() {
+        let patterns = vec!["1password".to_string(), "*- Private Browsing".to_string()];
+        assert!(is_window_excluded(&patterns, "1Password 7", ""));
+        assert!(is_window_excluded(
+            &patterns,
+            "Firefox",
+            "Bank - Private Browsing"
+        ));
+        assert!(!is_window_excluded(
+            &patterns,
+            "Firefox",
+            "Private Browsing notes"
+        ));
+        assert!(!is_window_excluded(&[], "1Password", "Vault"));
+
+        let glob = |pattern: &str, text: &str| {
+            wildcard_match(
+                &pattern.chars().collect::<Vec<_>>(),
+                &text.chars().collect::<Vec<_>>(),
+            )
+        };
+        assert!(glob("k?ep*ss*", "keepassxc"));
+        assert!(glob("*", ""));
+        assert!(glob("a*b*c", "aXbYbZc"));
+        assert!(!glob("a*b", "aXbY"));
+        assert!(!glob("?", ""));
+    }
+
+    #[test]
+    fn excluded_windows_map_onto_the_capture() {
+        // A display at (-1920, 0) captured at 2x
+        let region =
+            window_rect_in_capture((-1900, 100, -1400, 400), (-1920, 0), 2.0, (3840, 2160));
+        let region = region.map(|r| (r.x, r.y, r.width, r.height));
+        assert_eq!(region, Some((40, 200, 1000, 600)));
+
+        // Partly off the display: clipped to the capture
+        let region = window_rect_in_capture((1800, -50, 2100, 100), (0, 0), 1.0, (1920, 1080));
+        assert_eq!(
+            region.map(|r| (r.x, r.y, r.width, r.height)),
+            Some((1800, 0, 120, 100))
+        );
+
+        // On another display entirely
+        assert!(window_rect_in_capture((2000, 0, 2500, 500), (0, 0), 1.0, (1920, 1080)).is_none());
+
+        // Masked on the second display of a panorama only
+        let white = image::Rgba([255, 255, 255, 255]);
+        let mut image = image::RgbaImage::from_pixel(40, 20, white);
+        let excluded = [(110, 5, 120, 10)];
+        assert!(!mask_window_rects(&mut image, &excluded, (0, 0), 20).unwrap());
+        assert!(mask_window_rects(&mut image, &excluded, (100, 0), 20).unwrap());
+        assert_eq!(image.get_pixel(19, 10), &white);
+        assert_eq!(image.get_pixel(20, 9), &white);
+        assert_ne!(image.get_pixel(20, 10), &white);
+        assert_ne!(image.get_pixel(39, 19), &white);
+    }
+
+    #[test]
+    fn region_is_clipped_and_narrowed_to_the_aspect_ratio() {
+        let region = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+        let parts = |r: Rect| (r.x, r.y, r.width, r.height);
+
+        assert_eq!(
+            clip_rect(region(1800, 1000, 400, 400), 1920, 1080).map(parts),
+            Some((1800, 1000, 120, 80))
+        );
+        assert!(clip_rect(region(1920, 0, 10, 10), 1920, 1080).is_none());
+        assert!(clip_rect(region(u32::MAX, 0, 10, 10), 1920, 1080).is_none());
+
+        // Too wide: the width shrinks around the centre
+        let fitted = fit_aspect_ratio(region(100, 100, 1000, 450), (16, 9)).unwrap();
+        assert_eq!(parts(fitted), (200, 100, 800, 450));
+        let fitted = fit_aspect_ratio(region(0, 0, 1000, 900), (16, 9)).unwrap();
+        assert_eq!(parts(fitted), (0, 169, 1000, 562));
+        let fitted = fit_aspect_ratio(region(10, 10, 300, 200), (1, 1)).unwrap();
+        assert_eq!(parts(fitted), (60, 10, 200, 200));
+
+        for (rect, ratio) in [
+            (region(0, 0, 10, 10), (0, 1)),
+            (region(0, 0, 1, 10), (1, 100)),
+        ] {
+            assert!(matches!(
+                fit_aspect_ratio(rect, ratio),
+                Err(SpotlightError::InvalidInput(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn source_ids_are_stable_per_normalized_uri() {
+        let id = source_id("https://Example.com/docs/");
+        assert_eq!(id, source_id("HTTPS://example.com/docs"));
+        assert_eq!(id, source_id("https://example.com/docs"));
+        assert_ne!(id, source_id("https://example.com/Docs"));
+        assert_eq!(id.len(), "src-".len() + 16);
+        assert!(id.starts_with("src-"));
+    }
+
+    #[test]
+    fn usage_series_fills_missing_days_up_to_today() {
+        let today = chrono::NaiveDate::from_ymd_opt(2025, 3, 1).unwrap();
+        let recorded = HashMap::from([
+            (
+                "2025-02-27".to_string(),
+                DailyUsage {
+                    requests: 3,
+                    total_tokens: 1200,
+                    grounded_requests: 1,
+                    ungrounded_requests: 2,
+                },
+            ),
+            (
+                "2025-01-01".to_string(),
+                DailyUsage {
+                    requests: 9,
+                    ..Default::default()
+                },
+            ),
+        ]);
+
+        let series = usage_series(&recorded, today, 3);
+        let dates: Vec<&str> = series.iter().map(|day| day.date.as_str()).collect();
+        assert_eq!(dates, ["2025-02-27", "2025-02-28", "2025-03-01"]);
+        assert_eq!(series[0].usage.requests, 3);
+        assert_eq!(series[0].usage.total_tokens, 1200);
+        assert_eq!(series[1].usage.requests, 0);
+
+        let json = serde_json::to_value(&series[0]).unwrap();
+        assert_eq!(json["date"], "2025-02-27");
+        assert_eq!(json["groundedRequests"], 1);
+        assert_eq!(json["ungroundedRequests"], 2);
+
+        assert_eq!(usage_series(&recorded, today, 1).len(), 1);
+        assert_eq!(usage_series(&recorded, today, 365).len(), 365);
+    }
+
+    #[test]
+    fn deleting_a_message_removes_its_pair() {
+        let message = |role: &str, content: &str| ChatMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+            images: Vec::new(),
+            model: None,
+            created_at: None,
+            options: None,
+        };
+        let conversation = vec![
+            message("user", "q1"),
+            message("assistant", "a1"),
+            message("user", "q2"),
+            message("model", "a2"),
+            message("user", "q3"),
+        ];
+        let contents = |messages: &[ChatMessage]| {
+            messages
+                .iter()
+                .map(|message| message.content.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let mut messages = conversation.clone();
+        assert_eq!(remove_message_pair(&mut messages, 0).unwrap(), 0..2);
+        assert_eq!(contents(&messages), ["q2", "a2", "q3"]);
+
+        let mut messages = conversation.clone();
+        assert_eq!(remove_message_pair(&mut messages, 3).unwrap(), 2..4);
+        assert_eq!(contents(&messages), ["q1", "a1", "q3"]);
+
+        // An unanswered question goes alone
+        let mut messages = conversation.clone();
+        assert_eq!(remove_message_pair(&mut messages, 4).unwrap(), 4..5);
+        assert_eq!(contents(&messages), ["q1", "a1", "q2", "a2"]);
+
+        let mut messages = conversation;
+        assert!(matches!(
+            remove_message_pair(&mut messages, 5),
+            Err(SpotlightError::InvalidInput(_))
+        ));
+        assert_eq!(messages.len(), 5);
+    }
+
+    #[test]
+    fn history_search_matches_words_and_phrases_ignoring_case() {
+        assert_eq!(
+            parse_search_terms(r#"  rust "borrow   checker" lifetimes "open"#),
+            ["rust", "borrow checker", "lifetimes", "open"]
+        );
+        assert!(parse_search_terms(r#" "" "#).is_empty());
+        assert_eq!(
+            find_case_insensitive("ÉCOLE, école", "École"),
+            [0..6, 8..14]
+        );
+
+        let message = |role: &str, content: &str| ChatMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+            images: Vec::new(),
+            model: None,
+            created_at: None,
+            options: None,
+        };
+        let conversations = [
+            Conversation {
+                id: "a".to_string(),
+                title: "Rust".to_string(),
+                system_prompt: None,
+                tags: Vec::new(),
+                updated_at: None,
+                messages: vec![
+                    message("user", "How does the Borrow Checker work?"),
+                    message(
+                        "assistant",
+                        "The borrow checker tracks borrows.\nBorrow rules apply.",
+                    ),
+                ],
+            },
+            Conversation {
+                id: "b".to_string(),
+                title: "Other".to_string(),
+                system_prompt: None,
+                tags: Vec::new(),
+                updated_at: None,
+                messages: vec![message("user", "checker borrow")],
+            },
+        ];
+
+        let hits = search_conversations(&conversations, "borrow");
+        let found: Vec<_> = hits
+            .iter()
+            .map(|hit| (hit.conversation_id.as_str(), hit.message_index, hit.score))
+            .collect();
+        assert_eq!(found, [("a", 1, 3), ("a", 0, 1), ("b", 0, 1)]);
+        assert_eq!(
+            hits[0].snippet,
+            "The **borrow** checker tracks **borrow**s. **Borrow** rules apply."
+        );
+
+        let hits = search_conversations(&conversations, r#""borrow checker" work"#);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].snippet, "How does the **Borrow Checker** **work**?");
+        assert!(search_conversations(&conversations, "borrow missing").is_empty());
+        assert!(search_conversations(&conversations, "   ").is_empty());
+
+        let long = format!("{}needle{}", "a ".repeat(100), " b".repeat(100));
+        let snippet = highlighted_snippet(&long, &find_case_insensitive(&long, "needle"));
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+        assert!(snippet.contains("**needle**"));
+    }
+
+    #[test]
+    fn conversation_tags_are_trimmed_lowercased_and_deduplicated() {
+        let tags = ["  Research", "coding", "", "research ", "CODING", " "]
+            .map(str::to_string)
+            .to_vec();
+        assert_eq!(normalize_tags(tags), ["research", "coding"]);
+
+        // Conversations saved before tags existed still load
+        let conversation: Conversation =
+            serde_json::from_value(serde_json::json!({ "id": "a", "title": "Old" })).unwrap();
+        assert!(conversation.tags.is_empty());
+        assert!(conversation.updated_at.is_none());
+    }
+
+    #[test]
+    fn export_paths_must_be_absolute_with_a_known_extension() {
+        let root = if cfg!(windows) {
+            "C:\\exports"
+        } else {
+            "/exports"
+        };
+        let path = |name: &str| std::path::Path::new(root).join(name);
+
+        assert!(validate_export_path(&path("shot.PNG"), &["png"]).is_ok());
+        assert!(validate_export_path(&path("chat.markdown"), &["md", "markdown"]).is_ok());
+        for (bad, extensions) in [
+            (path("shot.jpg"), &["png"][..]),
+            (path("shot"), &["png"][..]),
+            (std::path::PathBuf::from("shot.png"), &["png"][..]),
+            (std::path::PathBuf::from("../chat.md"), &["md"][..]),
+        ] {
+            assert!(matches!(
+                validate_export_path(&bad, extensions),
+                Err(SpotlightError::InvalidInput(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn gemini_model_falls_back_and_drops_the_resource_prefix() {
+        assert_eq!(gemini_model(None), GEMINI_MODEL);
+        assert_eq!(gemini_model(Some("  ".to_string())), GEMINI_MODEL);
+        assert_eq!(
+            gemini_model(Some("models/gemini-1.5-pro".to_string())),
+            "gemini-1.5-pro"
+        );
+        assert_eq!(
+            gemini_model(Some("gemini-2.5-pro".to_string())),
+            "gemini-2.5-pro"
+        );
+    }
+
+    #[test]
+    fn dynamic_retrieval_is_limited_to_gemini_1_5() {
+        assert!(supports_dynamic_retrieval("gemini-1.5-flash"));
+        assert!(supports_dynamic_retrieval("gemini-1.5-pro-002"));
+        assert!(!supports_dynamic_retrieval(GEMINI_MODEL));
+        assert!(!supports_dynamic_retrieval("gemini-2.5-pro"));
+    }
+
+    #[test]
+    fn favicons_are_only_fetched_from_public_hosts() {
+        let public = |uri: &str| is_public_host(&reqwest::Url::parse(uri).unwrap());
+        assert!(public("https://www.rust-lang.org/learn"));
+        assert!(public("http://8.8.8.8/"));
+        assert!(public("http://[2606:4700::1111]/"));
+        for uri in [
+            "http://localhost:8080/",
+            "http://api.localhost/",
+            "http://printer.local/",
+            "http://router/",
+            "http://127.0.0.1/",
+            "http://10.0.0.1/",
+            "http://192.168.1.1/",
+            "http://169.254.169.254/latest/meta-data",
+            "http://100.64.0.1/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[fd00::1]/",
+            "http://[fe80::1]/",
+            "http://[::ffff:127.0.0.1]/",
+        ] {
+            assert!(!public(uri), "{uri}");
+        }
+    }
+
+    #[test]
+    fn cursor_colors_and_alpha_come_from_black_and_white_renders() {
+        // A transparent pixel, an opaque one and a half-transparent white one
+        let on_black = [0, 0, 0, 255, 200, 100, 50, 255, 128, 128, 128, 255];
+        let on_white = [255, 255, 255, 255, 200, 100, 50, 255, 255, 255, 255, 255];
+        assert_eq!(
+            cursor_from_backgrounds(&on_black, &on_white),
+            vec![0, 0, 0, 0, 200, 100, 50, 255, 255, 255, 255, 128]
+        );
+    }
+
+    #[test]
+    fn cli_args_are_left_to_the_gui_without_ask() {
+        let args = |list: &[&str]| list.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+
+        assert!(matches!(
+            parse_cli_args(args(&["--minimized", "spotlight://x"])),
+            Ok(None)
+        ));
+        let parsed = parse_cli_args(args(&["--ask", "hi", "--json", "--seed=3"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (parsed.question.as_str(), parsed.json, parsed.seed),
+            ("hi", true, Some(3))
+        );
+        assert_eq!(
+            parse_cli_args(args(&["--ask", "hi", "--jsn"])).err(),
+            Some("Unknown argument '--jsn'".to_string())
+        );
+    }
+
+    #[test]
+    fn editing_a_turn_drops_everything_after_it() {
+        let message = |role: &str, content: &str| ChatMessage {
+            role: role.to_string(),
+            content: content.to_string(),
+            images: Vec::new(),
+            model: None,
+            created_at: None,
+            options: None,
+        };
+        let conversation = vec![
+            message("user", "q1"),
+            message("assistant", "a1"),
+            message("user", "q2"),
+            message("assistant", "a2"),
+        ];
+
+        let mut messages = conversation.clone();
+        let edited = take_edited_turn(&mut messages, 0, "q1 again".to_string()).unwrap();
+        assert_eq!(
+            (edited.role.as_str(), edited.content.as_str()),
+            ("user", "q1 again")
+        );
+        assert!(messages.is_empty());
+
+        let mut messages = conversation.clone();
+        let edited = take_edited_turn(&mut messages, 2, "q2 again".to_string()).unwrap();
+        assert_eq!(edited.content, "q2 again");
+        let contents: Vec<_> = messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["q1", "a1"]);
+
+        // Answers can't be edited and nothing is cut when the index is rejected
+        let mut messages = conversation.clone();
+        assert!(matches!(
+            take_edited_turn(&mut messages, 1, "a1 again".to_string()),
+            Err(SpotlightError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            take_edited_turn(&mut messages, 4, "q3".to_string()),
+            Err(SpotlightError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            take_edited_turn(&mut messages, 2, "  ".to_string()),
+            Err(SpotlightError::EmptyPrompt)
+        ));
+        assert_eq!(messages.len(), 4);
+    }
+
+    #[test]
+    fn cursor_sprite_is_drawn_at_the_tip_and_clipped() {
+        let (width, height) = (4, 4);
+        let mut rgba = vec![0u8; width * height * 4];
+        let pixel = |rgba: &[u8], x: usize, y: usize| {
+            let offset = (y * width + x) * 4;
+            rgba[offset..offset + 4].to_vec()
+        };
+
+        draw_cursor_sprite(&mut rgba, width as u32, height as u32, 1, 0, 1);
+        assert_eq!(pixel(&rgba, 0, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(&rgba, 1, 0), [0, 0, 0, 255]);
+        assert_eq!(pixel(&rgba, 2, 2), [255, 255, 255, 255]);
+        assert_eq!(pixel(&rgba, 3, 0), [0, 0, 0, 0]);
+
+        // Mostly off-canvas at a large scale must not panic
+        draw_cursor_sprite(&mut rgba, width as u32, height as u32, -30, 3, 4);
+        draw_cursor_sprite(&mut rgba, width as u32, height as u32, 3, 3, 2);
+        assert_eq!(pixel(&rgba, 3, 3), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn accelerators_are_normalized_and_validated() {
+        assert_eq!(normalize_accelerator(" ctrl+shift+p ").unwrap(), "Control+Shift+P");
+        assert_eq!(normalize_accelerator("shift+cmd+space").unwrap(), "Super+Shift+Space");
+        assert_eq!(normalize_accelerator("Win+Alt+f5").unwrap(), "Super+Alt+F5");
+        assert_eq!(
+            normalize_accelerator("cmdorctrl+CTRL+control+k").unwrap(),
+            "CommandOrControl+Control+K"
+        );
+        for invalid in ["", "P", "Ctrl+Shift", "Ctrl+A+B", "Ctrl+A+Shift", "Ctrl++A"] {
+            assert!(
+                matches!(normalize_accelerator(invalid), Err(SpotlightError::InvalidInput(_))),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn deep_link_query_is_decoded_and_sanitized() {
+        let parse = |link: &str| parse_deep_link_query(&tauri::Url::parse(link).unwrap());
+
+        assert_eq!(
+            parse("spotlight://ask?q=summarize%20this").as_deref(),
+            Some("summarize this")
+        );
+        assert_eq!(
+            parse("spotlight://ask?q=line%0Abreak%00").as_deref(),
+            Some("line break")
+        );
+        assert_eq!(parse("spotlight://ask?q=%20%20"), None);
+        assert_eq!(parse("spotlight://settings?q=hi"), None);
+        assert_eq!(parse("https://ask?q=hi"), None);
+        let long = format!("spotlight://ask?q={}", "a".repeat(MAX_DEEP_LINK_QUERY_CHARS + 10));
+        assert_eq!(parse(&long).map(|q| q.len()), Some(MAX_DEEP_LINK_QUERY_CHARS));
+    }
+
+    #[test]
+    fn image_mime_is_sniffed_from_magic_bytes() {
+        assert_eq!(sniff_image_mime(b"\x89PNG\r\n\x1a\n\0\0"), Some("image/png"));
+        assert_eq!(sniff_image_mime(&[0xFF, 0xD8, 0xFF, 0xE0]), Some("image/jpeg"));
+        assert_eq!(sniff_image_mime(b"GIF89a..."), Some("image/gif"));
+        assert_eq!(sniff_image_mime(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff_image_mime(b"RIFF\0\0\0\0WAVE"), None);
+        assert_eq!(sniff_image_mime(b"%PDF-1.7"), None);
+    }
+}
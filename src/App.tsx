@@ -1,6 +1,5 @@
 import { useState, useEffect, useRef, memo, useCallback, Suspense, lazy } from "react";
 import { getCurrentWindow, LogicalSize } from "@tauri-apps/api/window";
-import { register } from "@tauri-apps/plugin-global-shortcut";
 import { invoke } from "@tauri-apps/api/core";
 import { check } from "@tauri-apps/plugin-updater";
 import { listen, UnlistenFn } from "@tauri-apps/api/event";
@@ -12,12 +11,43 @@ interface SourceInfo {
   uri: string;
 }
 
+interface ImageInput {
+  mimeType: string;
+  data: string;
+}
+
+// A file sent as inline data with the next message
+interface DocumentAttachment {
+  name: string;
+  mimeType: string;
+  data: string;
+}
+
+const DOCUMENT_ACCEPT = ".pdf,.txt,.md,.csv,.html,.json,.xml";
+
+const readFileAsBase64 = (file: File) =>
+  new Promise<string>((resolve, reject) => {
+    const reader = new FileReader();
+    // Strip the "data:<mime>;base64," prefix
+    reader.onload = () => resolve(String(reader.result).split(",")[1] ?? "");
+    reader.onerror = () => reject(reader.error);
+    reader.readAsDataURL(file);
+  });
+
+interface TurnOptions {
+  groundingEnabled: boolean;
+  thinkingEnabled: boolean;
+}
+
 interface Message {
   role: "user" | "assistant";
   content: string;
+  images?: ImageInput[];
+  options?: TurnOptions; // Toggles a user turn was sent with, replayed on regenerate
   thinking?: string;
   thinkingTime?: number; // Time in milliseconds
   sources?: SourceInfo[];
+  streaming?: boolean; // Partial text from a streaming backend
 }
 
 // Tauri command parameter interfaces
@@ -29,14 +59,33 @@ interface SendToGeminiParams extends Record<string, unknown> {
   thinkingEnabled: boolean;
   chatHistory: Message[];
   systemInstructions?: string;
+  document?: { mimeType: string; data: string } | null;
 }
 
 interface GeminiResult {
   text: string;
   thinking?: string;
   sources?: SourceInfo[];
+  responseMimeType?: string;
+}
+
+// Last successful answer, kept on disk for offline re-reading
+interface CachedResponse {
+  text: string;
+  sources?: SourceInfo[];
+  model: string;
+  createdAt: string;
 }
 
+// Structured error returned by every backend command
+interface SpotlightError {
+  kind: string;
+  message: string;
+}
+
+const isSpotlightError = (error: unknown): error is SpotlightError =>
+  typeof error === "object" && error !== null && "kind" in error && "message" in error;
+
 interface UpdateInfo {
   version: string;
   body: string;
@@ -59,11 +108,36 @@ const TIMEOUTS = {
 
 type AppWindow = ReturnType<typeof getCurrentWindow>;
 
-const GLOBAL_SHORTCUT = "CommandOrControl+K";
 
 const MessageRenderer = lazy(() => import("./components/MessageRenderer"));
 const API_KEY_UPDATED_EVENT = "api-key-updated";
 const SYSTEM_INSTRUCTIONS_UPDATED_EVENT = "system-instructions-updated";
+const RERUN_EVENT = "spotlight-rerun";
+const QUERY_EVENT = "spotlight-query";
+const UPDATE_AVAILABLE_EVENT = "update-available";
+const GEMINI_CHUNK_EVENT = "gemini-chunk";
+const THEME_CHANGED_EVENT = "theme-changed";
+
+// Site icon for a source pill; renders nothing until (or unless) one is found
+const SourceFavicon = memo(({ uri }: { uri: string }) => {
+  const [icon, setIcon] = useState<string | null>(null);
+
+  useEffect(() => {
+    let cancelled = false;
+    invoke<string | null>("fetch_favicon", { uri })
+      .then((dataUrl) => {
+        if (!cancelled) setIcon(dataUrl);
+      })
+      .catch(() => {});
+    return () => {
+      cancelled = true;
+    };
+  }, [uri]);
+
+  return icon ? <img className="source-favicon" src={icon} alt="" aria-hidden="true" /> : null;
+});
+
+SourceFavicon.displayName = 'SourceFavicon';
 
 // Memoized chat message component for performance
 const ChatMessage = memo(({ msg, idx }: { msg: Message; idx: number }) => (
@@ -83,6 +157,7 @@ const ChatMessage = memo(({ msg, idx }: { msg: Message; idx: number }) => (
             rel="noopener noreferrer"
             className="source-pill"
           >
+            <SourceFavicon uri={source.uri} />
             {source.title}
           </a>
         ))}
@@ -99,9 +174,11 @@ function App() {
   const [screenCaptureEnabled, setScreenCaptureEnabled] = useState(false);
   const [groundingEnabled, setGroundingEnabled] = useState(false);
   const [thinkingEnabled, setThinkingEnabled] = useState(false);
+  const [attachedDocument, setAttachedDocument] = useState<DocumentAttachment | null>(null);
   const [chatHistory, setChatHistory] = useState<Message[]>([]);
   const [isLoading, setIsLoading] = useState(false);
   const [apiKey, setApiKey] = useState("");
+  const [backendType, setBackendType] = useState("gemini");
   const [systemInstructions, setSystemInstructions] = useState("");
   const [isWindows, setIsWindows] = useState(false);
   const [shouldAnimate, setShouldAnimate] = useState(false);
@@ -117,6 +194,20 @@ function App() {
   const chatHistoryRef = useRef<Message[]>([]);
   const isWindowsRef = useRef(false);
   const isExpandedRef = useRef(isExpanded);
+  const autoSubmitRef = useRef(false);
+
+  // Queries from spotlight:// links wait in the backend until taken here
+  const takePendingQuery = useCallback(async () => {
+    try {
+      const query = await invoke<string | null>("take_pending_query");
+      if (query) {
+        autoSubmitRef.current = true;
+        setSearchQuery(query);
+      }
+    } catch (error) {
+      console.error("Failed to read deep link query:", error);
+    }
+  }, []);
 
   const adjustWindowSize = useCallback(
     async (expanded: boolean) => {
@@ -163,20 +254,24 @@ function App() {
   useEffect(() => {
     const loadSettings = async () => {
       try {
-        const [storedKey, storedInstructions] = await Promise.all([
+        const [storedKey, storedInstructions, backend] = await Promise.all([
           invoke<string | null>("get_api_key"),
-          invoke<string | null>("get_system_instructions")
+          invoke<string | null>("get_system_instructions"),
+          invoke<{ type: string }>("get_backend")
         ]);
         setApiKey(storedKey ?? "");
         setSystemInstructions(storedInstructions ?? "");
+        setBackendType(backend.type);
         console.log("Settings loaded successfully");
       } catch (error) {
         console.error("Failed to load settings from command:", error);
         // Continue without settings - user can set them later
       }
+      // A link that launched the app runs once the API key is known
+      await takePendingQuery();
     };
     void loadSettings();
-  }, []);
+  }, [takePendingQuery]);
 
   // Update state handlers
 
@@ -368,35 +463,16 @@ function App() {
     });
   }, [ensureWindowHidden, ensureWindowShown, runWithToggleGuard]);
 
-  
-  useEffect(() => {
-    const setupShortcut = async () => {
-      try {
-        console.log("🔧 Setting up global shortcut...");
-
-        await register(GLOBAL_SHORTCUT, () => {
-          console.log("⌨️ Global shortcut triggered!");
-          void toggleWindow();
-        });
-
-        console.log("✅ Global shortcut (Cmd+K / Ctrl+K) registered successfully!");
-      } catch (error) {
-        console.error("❌ Failed to setup shortcut:", error);
-        console.error("This could be due to:");
-        console.error("1. Missing accessibility permissions on macOS");
-        console.error("2. Another app using the same shortcut");
-        console.error("3. Missing permissions in capabilities file");
-      }
-    };
-
-    void setupShortcut();
-  }, [toggleWindow]);
-
   useEffect(() => {
     let unlistenShow: UnlistenFn | undefined;
     let unlistenHide: UnlistenFn | undefined;
     let unlistenApiKey: UnlistenFn | undefined;
     let unlistenSystemInstructions: UnlistenFn | undefined;
+    let unlistenRerun: UnlistenFn | undefined;
+    let unlistenQuery: UnlistenFn | undefined;
+    let unlistenUpdateAvailable: UnlistenFn | undefined;
+    let unlistenChunk: UnlistenFn | undefined;
+    let unlistenTheme: UnlistenFn | undefined;
 
     const registerListeners = async () => {
       unlistenShow = await listen("spotlight-show", () => {
@@ -413,6 +489,33 @@ function App() {
         const nextInstructions = event.payload?.systemInstructions ?? "";
         setSystemInstructions(nextInstructions);
       });
+      unlistenQuery = await listen(QUERY_EVENT, () => {
+        void takePendingQuery();
+      });
+      unlistenRerun = await listen<{ prompt: string }>(RERUN_EVENT, (event) => {
+        setSearchQuery(event.payload.prompt);
+        void showWindow();
+      });
+      unlistenUpdateAvailable = await listen<UpdateInfo>(UPDATE_AVAILABLE_EVENT, (event) => {
+        setUpdateInfo(event.payload);
+        setUpdateState('available');
+        setShowUpdateNotification(true);
+      });
+      unlistenTheme = await listen<"dark" | "light">(THEME_CHANGED_EVENT, (event) => {
+        // Mirrors the OS appearance for styles that can't rely on prefers-color-scheme
+        document.documentElement.dataset.theme = event.payload;
+      });
+      // `replace` carries the whole (sanitized) answer so far instead of a piece
+      unlistenChunk = await listen<{ text: string; replace?: boolean }>(GEMINI_CHUNK_EVENT, (event) => {
+        const { text, replace } = event.payload;
+        setChatHistory((prev) => {
+          const last = prev[prev.length - 1];
+          if (last?.role === "assistant" && last.streaming) {
+            return [...prev.slice(0, -1), { ...last, content: replace ? text : last.content + text }];
+          }
+          return [...prev, { role: "assistant", content: text, streaming: true }];
+        });
+      });
     };
 
     void registerListeners();
@@ -422,8 +525,13 @@ function App() {
       unlistenHide?.();
       unlistenApiKey?.();
       unlistenSystemInstructions?.();
+      unlistenRerun?.();
+      unlistenQuery?.();
+      unlistenUpdateAvailable?.();
+      unlistenChunk?.();
+      unlistenTheme?.();
     };
-  }, [hideWindow, showWindow]);
+  }, [hideWindow, showWindow, takePendingQuery]);
 
   useEffect(() => {
     // Scroll to bottom when chat history updates
@@ -437,8 +545,8 @@ function App() {
   const sendMessage = async () => {
     if (!searchQuery.trim() || isLoading) return;
 
-    // Check if API key is set
-    if (!apiKey.trim()) {
+    // Check if API key is set (local backends run without one)
+    if (backendType === "gemini" && !apiKey.trim()) {
       setChatHistory((prev) => [
         ...prev,
         { role: "user", content: searchQuery },
@@ -461,10 +569,15 @@ function App() {
 
     setIsLoading(true);
     const userMessage = searchQuery;
+    const attachment = attachedDocument;
     setSearchQuery("");
+    setAttachedDocument(null);
 
     // Add user message to chat
-    setChatHistory((prev) => [...prev, { role: "user", content: userMessage }]);
+    setChatHistory((prev) => [
+      ...prev,
+      { role: "user", content: userMessage, options: { groundingEnabled, thinkingEnabled } },
+    ]);
 
     // Expand if not already
     if (!isExpanded) {
@@ -477,7 +590,17 @@ function App() {
 
       // Capture screen if enabled
       if (screenCaptureEnabled) {
-        imageData = await invoke<string>("capture_screen");
+        const capturedImage = await invoke<string>("capture_screen");
+        imageData = capturedImage;
+
+        // Keep the screenshot with the message so follow-ups can refer back to it
+        setChatHistory((prev) =>
+          prev.map((msg, idx) =>
+            idx === prev.length - 1 && msg.role === "user"
+              ? { ...msg, images: [{ mimeType: "image/png", data: capturedImage }] }
+              : msg,
+          ),
+        );
       }
 
       // Send to Gemini with full chat history
@@ -489,6 +612,7 @@ function App() {
         thinkingEnabled,
         chatHistory,
         systemInstructions,
+        document: attachment && { mimeType: attachment.mimeType, data: attachment.data },
       };
 
       const startTime = Date.now();
@@ -499,12 +623,15 @@ function App() {
       // Parse response (it now contains both text and sources)
       const result: GeminiResult = JSON.parse(response);
 
-      // Add assistant response to chat
+      // Add assistant response to chat, replacing any streamed partial text
       setChatHistory((prev) => [
-        ...prev,
+        ...prev.filter((msg) => !msg.streaming),
         {
           role: "assistant",
-          content: result.text,
+          // Structured output is raw JSON, so show it verbatim instead of as markdown
+          content: result.responseMimeType === "application/json"
+            ? `\`\`\`json\n${result.text}\n\`\`\``
+            : result.text,
           thinking: result.thinking,
           thinkingTime: result.thinking ? thinkingTime : undefined,
           sources: result.sources,
@@ -515,28 +642,71 @@ function App() {
 
       let errorMessage = "Sorry, something went wrong. Please try again.";
 
-      if (error instanceof Error) {
-        if (error.message.includes("API error")) {
-          errorMessage = "Unable to connect to Gemini API. Please check your API key and try again.";
-        } else if (error.message.includes("Failed to parse")) {
-          errorMessage = "Received an unexpected response from Gemini. Please try again.";
-        } else if (error.message.includes("Network") || error.message.includes("fetch")) {
-          errorMessage = "Network error. Please check your internet connection and try again.";
+      if (isSpotlightError(error)) {
+        switch (error.kind) {
+          case "missingApiKey":
+            errorMessage = "Please set your API key first. Use Spotlight > Settings in the menu bar to add your Gemini API key.";
+            break;
+          case "authFailed":
+            errorMessage = "Gemini rejected your API key. Please check it in Settings and try again.";
+            break;
+          case "rateLimited":
+            errorMessage = "Gemini is rate limiting requests. Please wait a moment and try again.";
+            break;
+          case "network":
+          case "offline":
+            errorMessage = "Network error. Please check your internet connection and try again.";
+            break;
+          case "timeout":
+            errorMessage = "The request timed out. Please try again.";
+            break;
+          case "parse":
+            errorMessage = "Received an unexpected response from Gemini. Please try again.";
+            break;
+          case "blocked":
+            errorMessage = "Gemini declined to answer this request.";
+            break;
+          case "capture":
+            errorMessage = "Unable to capture the screen. Please check screen recording permissions.";
+            break;
+          default:
+            errorMessage = error.message;
         }
       }
 
+      // Offer the last answer so there is still something to read offline
+      let cached: CachedResponse | null = null;
+      if (isSpotlightError(error) && (error.kind === "network" || error.kind === "offline")) {
+        cached = await invoke<CachedResponse | null>("get_last_response").catch(() => null);
+      }
+
       setChatHistory((prev) => [
-        ...prev,
+        ...prev.filter((msg) => !msg.streaming),
         {
           role: "assistant",
           content: errorMessage,
         },
+        ...(cached
+          ? [{
+              role: "assistant" as const,
+              content: `*Offline — last cached answer:*\n\n${cached.text}`,
+              sources: cached.sources,
+            }]
+          : []),
       ]);
     } finally {
       setIsLoading(false);
     }
   };
 
+  useEffect(() => {
+    if (autoSubmitRef.current && searchQuery.trim()) {
+      autoSubmitRef.current = false;
+      void sendMessage();
+    }
+    // sendMessage is recreated every render; only a new query should trigger this
+  }, [searchQuery]);
+
   const handleKeyDown = async (e: React.KeyboardEvent) => {
     if (e.key === "Enter" && searchQuery.trim() !== "" && e.target === inputRef.current) {
       e.preventDefault();
@@ -628,6 +798,27 @@ function App() {
             />
             <span>Extended Thinking</span>
           </label>
+          <label className="screen-capture-toggle" title={attachedDocument?.name}>
+            <input
+              type="file"
+              accept={DOCUMENT_ACCEPT}
+              hidden
+              onChange={async (e) => {
+                const file = e.target.files?.[0];
+                e.target.value = "";
+                if (!file) return;
+                try {
+                  const data = await readFileAsBase64(file);
+                  // Some platforms report no type for .md and friends
+                  setAttachedDocument({ name: file.name, mimeType: file.type || "text/plain", data });
+                } catch (error) {
+                  console.error("Failed to read document:", error);
+                }
+              }}
+              aria-label="Attach a document"
+            />
+            <span>{attachedDocument ? `📄 ${attachedDocument.name}` : "Attach File"}</span>
+          </label>
         </div>
       </div>
 
@@ -1,4 +1,5 @@
 import { useState } from "react";
+import { invoke } from "@tauri-apps/api/core";
 
 interface CopyButtonProps {
   text: string;
@@ -10,32 +11,12 @@ const CopyButton = ({ text, className }: CopyButtonProps) => {
 
   const handleCopy = async () => {
     try {
-      // Try modern clipboard API first
-      await navigator.clipboard.writeText(text);
+      // The backend clipboard avoids webview clipboard-permission quirks
+      await invoke("copy_text", { text });
       setCopied(true);
       setTimeout(() => setCopied(false), 2000);
     } catch (error) {
-      // Fallback for older browsers
-      try {
-        const textArea = document.createElement("textarea");
-        textArea.value = text;
-        textArea.style.position = "fixed";
-        textArea.style.left = "-999999px";
-        textArea.style.top = "-999999px";
-        document.body.appendChild(textArea);
-        textArea.focus();
-        textArea.select();
-
-        const successful = document.execCommand("copy");
-        document.body.removeChild(textArea);
-
-        if (successful) {
-          setCopied(true);
-          setTimeout(() => setCopied(false), 2000);
-        }
-      } catch (fallbackError) {
-        console.error("Failed to copy text:", fallbackError);
-      }
+      console.error("Failed to copy text:", error);
     }
   };
 
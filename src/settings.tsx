@@ -5,14 +5,24 @@ import { getCurrentWindow } from "@tauri-apps/api/window";
 import { PresetManager } from "./components/PresetManager";
 import "./settings.css";
 
+interface AppInfo {
+  version: string;
+  tauriVersion: string;
+  os: string;
+  arch: string;
+  buildDate: string;
+}
+
 function SettingsApp() {
   const [apiKey, setApiKey] = useState("");
+  const [apiKeyFromEnv, setApiKeyFromEnv] = useState(false);
   const [systemInstructions, setSystemInstructions] = useState("");
   const [isBusy, setIsBusy] = useState(false);
   const [statusMessage, setStatusMessage] = useState<string | null>(null);
   const [statusIntent, setStatusIntent] = useState<"success" | "info" | "error">("info");
   const [characterCount, setCharacterCount] = useState(0);
   const [isClosing, setIsClosing] = useState(false);
+  const [appInfo, setAppInfo] = useState<AppInfo | null>(null);
 
   const handleClose = useCallback(async () => {
     console.log("Close button clicked, attempting to close settings window...");
@@ -39,11 +49,15 @@ function SettingsApp() {
   useEffect(() => {
     const loadSettings = async () => {
       try {
-        const [storedKey, storedInstructions] = await Promise.all([
+        const [storedKey, keySource, storedInstructions] = await Promise.all([
           invoke<string | null>("get_api_key"),
+          invoke<"profile" | "environment" | "none">("get_api_key_source"),
           invoke<string | null>("get_system_instructions")
         ]);
-        setApiKey(storedKey ?? "");
+        // Don't offer to save a key that only lives in the environment
+        const fromEnv = keySource === "environment";
+        setApiKeyFromEnv(fromEnv);
+        setApiKey(fromEnv ? "" : storedKey ?? "");
         setSystemInstructions(storedInstructions ?? "");
       } catch (error) {
         console.error("Failed to load settings:", error);
@@ -55,6 +69,12 @@ function SettingsApp() {
     void loadSettings();
   }, []);
 
+  useEffect(() => {
+    invoke<AppInfo>("get_app_info")
+      .then(setAppInfo)
+      .catch((error) => console.error("Failed to load app info:", error));
+  }, []);
+
   // Update character count
   useEffect(() => {
     setCharacterCount(systemInstructions.length);
@@ -139,7 +159,12 @@ function SettingsApp() {
       console.error("Failed to save settings:", error);
       console.error("Error details:", JSON.stringify(error, null, 2));
       setStatusIntent("error");
-      setStatusMessage(`Could not save settings: ${error instanceof Error ? error.message : String(error)}`);
+      const message = error instanceof Error
+        ? error.message
+        : typeof error === "object" && error !== null && "message" in error
+          ? String((error as { message: unknown }).message)
+          : String(error);
+      setStatusMessage(`Could not save settings: ${message}`);
     } finally {
       setIsBusy(false);
     }
@@ -281,7 +306,7 @@ function SettingsApp() {
             type="password"
             value={apiKey}
             onChange={(event) => setApiKey(event.target.value)}
-            placeholder="Enter API Key"
+            placeholder={apiKeyFromEnv ? "Using GEMINI_API_KEY from the environment" : "Enter API Key"}
             autoFocus
             disabled={isBusy}
           />
@@ -290,7 +315,7 @@ function SettingsApp() {
         {/* Update Status Section */}
         <div className="settings-section">
           <h4>App Updates</h4>
-          <p style={{marginBottom: '8px'}}>Version: <span className="current-version">0.1.1</span></p>
+          <p style={{marginBottom: '8px'}}>Version: <span className="current-version">{appInfo?.version ?? "…"}</span></p>
           <div className="update-status-container">
             {statusMessage && statusMessage.includes("Update installed") ? (
               <div className="update-status-success">
@@ -363,6 +388,11 @@ function SettingsApp() {
             Check Updates
           </button>
         </div>
+        {appInfo && (
+          <div className="settings-footer">
+            Spotlight {appInfo.version} · Tauri {appInfo.tauriVersion} · {appInfo.os}/{appInfo.arch} · built {appInfo.buildDate}
+          </div>
+        )}
       </form>
     </div>
   );